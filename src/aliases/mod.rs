@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+/// The aliases set with `alias`, by name, which stand in for the first word of simple commands.
+#[derive(Debug, Default, Clone)]
+pub struct Aliases {
+    values: BTreeMap<String, String>,
+}
+
+/// Whether `name` can be an alias. Characters with a meaning to the shell are left out, along with `=`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.bytes().any(|b| b" \t\n=/$`'\"\\;&|()<>".contains(&b))
+}
+
+impl Aliases {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// The aliases sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.values.iter()
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    /// Returns whether there was such an alias.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.values.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod names {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        assert!(is_valid_name("ll"));
+        assert!(is_valid_name("g-s.x"));
+        assert!(!is_valid_name("a=b"));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name(""));
+    }
+}
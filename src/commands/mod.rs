@@ -1,19 +1,77 @@
+use crate::aliases;
+use crate::conditional;
+use crate::data;
+use crate::editor::Mode;
+use crate::frecency::{self, Database};
+use crate::history::{
+    self,
+    record::{self, Filter, Record},
+};
+use crate::jobs::{self, Job};
+use crate::parse::{syntax, Redirect};
+use crate::signals;
+use crate::spell;
+use crate::traps::Condition;
+use crate::vars::{self, Variables};
 use crate::CommandInfo;
+use crate::Control;
+use crate::ExecError;
 use crate::ParsedCommand;
+use crate::Shell;
+use crate::ShellOptions;
 use std::io::Error;
 
-use std::collections::HashMap;
 use std::env;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::path::Path;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::process::{self};
+use std::rc::Rc;
 
-pub static BUILTINS: [&str; 5] = ["exit", "echo", "type", "pwd", "cd"];
+pub static BUILTINS: [&str; 30] = [
+    "exit", "echo", "type", "pwd", "cd", "z", "shopt", "set", "history", "hash", "export", "unset",
+    "command", "jobs", "fg", "bg", "disown", "wait", "trap", "kill", "break", "continue", "return",
+    "local", "test", "[", "alias", "unalias", "source", ".",
+];
+
+pub fn is_builtin(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| BUILTINS.contains(&name))
+}
+
+/// The POSIX special builtins we have, along with `source`; assignments before them are kept.
+static SPECIAL_BUILTINS: [&str; 10] = [
+    "break", "continue", ".", "source", "exit", "export", "return", "set", "trap", "unset",
+];
+
+pub fn is_special_builtin(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| SPECIAL_BUILTINS.contains(&name))
+}
+
+/// Exit status of a command that was found but could not be executed.
+pub const NOT_EXECUTABLE: i32 = 126;
+/// Exit status of a command that could not be found.
+pub const NOT_FOUND: i32 = 127;
+/// Exit status of a line that could not be parsed.
+pub const SYNTAX_ERROR: i32 = 2;
 
 pub trait Execute {
-    fn execute(&self) -> Result<(), Error>;
+    /// Runs the command, returning its exit status.
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error>;
+
+    /// Starts the command in a forked copy of the shell without waiting for it, returning the pid of the child.
+    fn spawn(&self, shell: &mut Shell) -> Result<libc::pid_t, Error> {
+        shell.fork(false, |shell| self.run_forked(shell))
+    }
+
+    /// Runs the command in a forked copy of the shell, like one part of a pipeline, which exits with the status returned.
+    fn run_forked(&self, shell: &mut Shell) -> i32 {
+        self.execute(shell).unwrap_or(1)
+    }
 }
 
 #[derive(Debug)]
@@ -29,7 +87,6 @@ pub struct EchoCommand {
 #[derive(Debug)]
 pub struct TypeCommand {
     options: ParsedCommand,
-    valid_commands: HashMap<String, OsString>,
 }
 
 #[derive(Debug)]
@@ -37,10 +94,18 @@ pub struct InvalidCommand {
     options: ParsedCommand,
 }
 
+#[derive(Debug)]
+pub struct UnrunnableCommand {
+    options: ParsedCommand,
+    error: ExecError,
+}
+
 #[derive(Debug)]
 pub struct RunCommand {
     options: ParsedCommand,
     command: CommandInfo,
+    /// `NAME=value` words before the command, which only apply to its environment.
+    env: Vec<(String, OsString)>,
 }
 
 #[derive(Debug)]
@@ -53,6 +118,146 @@ pub struct CdCommand {
     options: ParsedCommand,
 }
 
+#[derive(Debug)]
+pub struct ZCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct ShoptCommand {
+    options: ParsedCommand,
+}
+
+/// `set -o` and `set +o`, for the options that go by name.
+#[derive(Debug)]
+pub struct SetCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct HistoryCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct HashCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct AssignCommand {
+    assignments: Vec<(String, OsString)>,
+}
+
+/// A builtin with variables assigned for as long as it runs, like `HOME=/tmp cd`.
+pub struct AssignedCommand {
+    assignments: Vec<(String, OsString)>,
+    command: Box<dyn Execute>,
+}
+
+#[derive(Debug)]
+pub struct ExportCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct UnsetCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct CommandCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct JobsCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct FgCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct BgCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct DisownCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct WaitCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct TrapCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct KillCommand {
+    options: ParsedCommand,
+}
+
+/// A command whose output or errors go somewhere other than the shell's, like `ls > log 2>&1`.
+pub struct RedirectedCommand {
+    redirects: ParsedCommand,
+    command: Box<dyn Execute>,
+}
+
+#[derive(Debug)]
+pub struct FunctionCommand {
+    options: ParsedCommand,
+    body: Rc<syntax::Command>,
+    /// `NAME=value` words before the call, which only apply while the function runs.
+    env: Vec<(String, OsString)>,
+}
+
+#[derive(Debug)]
+pub struct ReturnCommand {
+    options: ParsedCommand,
+}
+
+#[derive(Debug)]
+pub struct LocalCommand {
+    options: ParsedCommand,
+}
+
+pub struct AliasCommand {
+    options: ParsedCommand,
+}
+
+pub struct UnaliasCommand {
+    options: ParsedCommand,
+}
+
+/// `source` and `.`, which are the same command under two names.
+pub struct SourceCommand {
+    options: ParsedCommand,
+    name: &'static str,
+}
+
+/// `test` and `[`, which is the same but wants a `]` after its arguments.
+pub struct TestCommand {
+    options: ParsedCommand,
+    name: &'static str,
+    bracket: bool,
+}
+
+/// `break` and `continue`, which only differ in what they leave the loops to do.
+#[derive(Debug)]
+pub struct LoopCommand {
+    options: ParsedCommand,
+    name: &'static str,
+    control: fn(usize) -> Control,
+}
+
 /*******************************
  ------------ Exit ------------
 *******************************/
@@ -64,10 +269,13 @@ impl ExitCommand {
 }
 
 impl Execute for ExitCommand {
-    fn execute(&self) -> Result<(), Error> {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
         match &self.options.args {
-            Some(val) if val.first().unwrap() == "0" => process::exit(exitcode::OK),
-            _ => process::exit(exitcode::USAGE),
+            None => shell.exit(shell.last_status),
+            Some(val) => match val.first().unwrap().to_str().map(str::parse) {
+                Some(Ok(status)) => shell.exit(status),
+                _ => shell.exit(exitcode::USAGE),
+            },
         }
     }
 }
@@ -82,14 +290,16 @@ impl EchoCommand {
 }
 
 impl Execute for EchoCommand {
-    fn execute(&self) -> Result<(), Error> {
-        writeln!(
-            self.options
-                .get_output()
-                .expect("Failed to get write output."),
-            "{}",
-            self.options.args.clone().unwrap().join(" ")
-        )
+    fn execute(&self, _shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self
+            .options
+            .get_output()
+            .expect("Failed to get write output.");
+        let args = self.options.args.clone().unwrap_or_default();
+        let words: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        out.write_all(&words.join(b" ".as_slice()))?;
+        writeln!(out)?;
+        Ok(exitcode::OK)
     }
 }
 
@@ -103,14 +313,37 @@ impl InvalidCommand {
 }
 
 impl Execute for InvalidCommand {
-    fn execute(&self) -> Result<(), Error> {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
         writeln!(
             self.options
                 .get_output()
                 .expect("Failed to get write output."),
-            "{}: command not found",
-            self.options.cmd.clone().unwrap()
-        )
+            "{}{}: command not found",
+            shell.location_prefix(),
+            self.options.cmd.clone().unwrap().to_string_lossy()
+        )?;
+        Ok(NOT_FOUND)
+    }
+}
+
+/*******************************
+ ------------ Unrunnable ------------
+*******************************/
+impl UnrunnableCommand {
+    pub fn new(options: ParsedCommand, error: ExecError) -> Self {
+        UnrunnableCommand { options, error }
+    }
+}
+
+impl Execute for UnrunnableCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        eprintln!(
+            "shelly: {}{}: {}",
+            shell.location_prefix(),
+            self.options.cmd.clone().unwrap().to_string_lossy(),
+            self.error
+        );
+        Ok(self.error.status())
     }
 }
 
@@ -118,20 +351,60 @@ impl Execute for InvalidCommand {
  ------------ Run ------------
 *******************************/
 impl RunCommand {
-    pub fn new(options: ParsedCommand, command: CommandInfo) -> Self {
-        RunCommand { command, options }
+    pub fn new(options: ParsedCommand, command: CommandInfo, env: Vec<(String, OsString)>) -> Self {
+        RunCommand {
+            command,
+            options,
+            env,
+        }
+    }
+
+    fn report(&self, shell: &Shell, error: ExecError) -> Result<i32, Error> {
+        eprintln!(
+            "shelly: {}{}: {}",
+            shell.location_prefix(),
+            self.command.bin.to_string_lossy(),
+            error
+        );
+        Ok(error.status())
+    }
+
+    /// Replaces the current process with the command; this only returns if that fails.
+    fn exec(&self, shell: &Shell) -> Result<i32, Error> {
+        let args = self.options.args.clone().unwrap_or_default();
+        let exec = |program: &OsStr, args: &[OsString]| {
+            let mut command = Command::new(program);
+            command.args(args).envs(self.env.iter().cloned());
+            Ok::<Error, Error>(command.arg0(&self.command.bin).exec())
+        };
+
+        let err = exec(&self.command.path, &args)?;
+        // An executable that is neither a binary nor has a `#!` line is a script for us to interpret.
+        if err.raw_os_error() == Some(libc::ENOEXEC) {
+            let script_args: Vec<OsString> = std::iter::once(self.command.path.clone())
+                .chain(args)
+                .collect();
+            let err = exec(env::current_exe()?.as_os_str(), &script_args)?;
+            return self.report(shell, ExecError::Other(err.to_string()));
+        }
+        self.report(
+            shell,
+            ExecError::from_spawn(Path::new(&self.command.path), &err),
+        )
     }
 }
 
 impl Execute for RunCommand {
-    fn execute(&self) -> Result<(), Error> {
-        let mut out = self.options.get_output()?;
-        let output = Command::new(self.command.bin.clone())
-            .args(self.options.args.clone().unwrap_or_default())
-            .output()
-            .expect("Failed to run command.");
+    /// The program runs in place of a forked copy of the shell, which is waited for in the foreground.
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let pid = shell.fork(true, |shell| self.exec(shell).unwrap_or(NOT_EXECUTABLE))?;
+        let job = Job::new(vec![pid], shell.command_line.clone());
+        Ok(shell.foreground(job))
+    }
 
-        out.write_all(&output.stdout)
+    /// There is no need to fork again, as the program can take the place of the copy.
+    fn run_forked(&self, shell: &mut Shell) -> i32 {
+        self.exec(shell).unwrap_or(NOT_EXECUTABLE)
     }
 }
 
@@ -139,39 +412,60 @@ impl Execute for RunCommand {
  ------------ Type ------------
 *******************************/
 impl TypeCommand {
-    pub fn new(options: ParsedCommand, valid_commands: HashMap<String, OsString>) -> Self {
-        TypeCommand {
-            options,
-            valid_commands,
-        }
+    pub fn new(options: ParsedCommand) -> Self {
+        TypeCommand { options }
     }
 }
 
 impl Execute for TypeCommand {
-    fn execute(&self) -> Result<(), Error> {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
         let mut out = self
             .options
             .get_output()
             .expect("Failed to get write output.");
         match &self.options.args.clone().unwrap().first() {
             Some(bin)
-                if !BUILTINS.contains(&bin.as_str()) && !self.valid_commands.contains_key(*bin) =>
+                if bin
+                    .to_str()
+                    .is_some_and(|name| shell.aliases.get(name).is_some()) =>
             {
-                writeln!(out, "{}: not found", bin)
+                let name = bin.to_str().expect("it is an alias");
+                let value = shell.aliases.get(name).expect("it is an alias");
+                writeln!(out, "{} is aliased to `{}'", name, value)
             }
-            Some(bin) if BUILTINS.contains(&bin.as_str()) => {
-                writeln!(out, "{} is a shell builtin", bin)
+            Some(bin)
+                if bin
+                    .to_str()
+                    .is_some_and(|name| shell.functions.contains_key(name)) =>
+            {
+                let name = bin.to_string_lossy();
+                writeln!(out, "{} is a function", name)?;
+                writeln!(
+                    out,
+                    "{}",
+                    shell.function_text(&name).expect("it is a function")
+                )
+            }
+            Some(bin) if is_builtin(bin) => {
+                writeln!(out, "{} is a shell builtin", bin.to_string_lossy())
+            }
+            Some(bin) => {
+                let hashed = shell.commands.get(bin).is_some();
+                let name = bin.to_string_lossy();
+                match shell.lookup_command(bin) {
+                    Some(path) if hashed => {
+                        writeln!(out, "{} is hashed ({})", name, path.display())
+                    }
+                    Some(path) => writeln!(out, "{} is {}", name, path.display()),
+                    None => {
+                        writeln!(out, "{}: not found", name)?;
+                        return Ok(1);
+                    }
+                }
             }
-
-            Some(bin) if self.valid_commands.contains_key(*bin) => writeln!(
-                out,
-                "{} is {}",
-                bin,
-                self.valid_commands.get(*bin).unwrap().to_str().unwrap()
-            ),
             None => writeln!(out, "Wrong usage"), //this right here is the entry point for a manpage message
-            Some(_) => writeln!(out),
-        }
+        }?;
+        Ok(exitcode::OK)
     }
 }
 
@@ -185,12 +479,12 @@ impl PwdCommand {
 }
 
 impl Execute for PwdCommand {
-    fn execute(&self) -> Result<(), Error> {
-        writeln!(
-            self.options.get_output().expect("Failed to get write"),
-            "{}",
-            env::current_dir().expect("No current dir").display()
-        )
+    fn execute(&self, _shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output().expect("Failed to get write");
+        let dir = env::current_dir().expect("No current dir");
+        out.write_all(dir.as_os_str().as_bytes())?;
+        writeln!(out)?;
+        Ok(exitcode::OK)
     }
 }
 
@@ -204,23 +498,1933 @@ impl CdCommand {
 }
 
 impl Execute for CdCommand {
-    fn execute(&self) -> Result<(), Error> {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
         let mut out = self.options.get_output().expect("Failed to get write.");
-        if let Some(path) = &self.options.args.clone().unwrap().first() {
+        if let Some(path) = self.options.args.clone().unwrap_or_default().first() {
             match path {
-                path if *path == &"~".to_string() => {
-                    env::set_current_dir(env::var_os("HOME").unwrap_or_else(|| {
-                        writeln!(out, "No HOME directory found.")
-                            .expect("Failed to write to output.");
-                        Path::new("").into()
-                    }))?
+                path if path == "~" => {
+                    let Some(home) = env::var_os("HOME") else {
+                        writeln!(out, "No HOME directory found.")?;
+                        return Ok(1);
+                    };
+                    if change_dir(Path::new(&home)).is_err() {
+                        println!("cd: {}: No such file or directory", home.to_string_lossy());
+                        return Ok(1);
+                    }
+                }
+                path if shell.options.cdspell && !Path::new(path).is_dir() => {
+                    match spell::correct_dir(Path::new(path)) {
+                        Some(corrected) => {
+                            writeln!(out, "{}", corrected.display())?;
+                            change_dir(&corrected)?
+                        }
+                        None => {
+                            println!("cd: {}: No such file or directory", path.to_string_lossy());
+                            return Ok(1);
+                        }
+                    }
                 }
                 path => {
-                    env::set_current_dir(Path::new(path))
-                        .unwrap_or_else(|_err| println!("cd: {}: No such file or directory", path));
+                    if change_dir(Path::new(path)).is_err() {
+                        println!("cd: {}: No such file or directory", path.to_string_lossy());
+                        return Ok(1);
+                    }
+                }
+            }
+        }
+        Ok(exitcode::OK)
+    }
+}
+
+/// Changes the working directory and records the visit for `z`.
+fn change_dir(path: &Path) -> Result<(), Error> {
+    env::set_current_dir(path)?;
+    frecency::record(&env::current_dir()?);
+    Ok(())
+}
+
+/*******************************
+ ------------ Z ------------
+*******************************/
+impl ZCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        ZCommand { options }
+    }
+
+    fn list(&self, keywords: &[String], database: &Database) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let now = data::now();
+        // Best match last, so it ends up right above the prompt.
+        for entry in database.matches(keywords, now).iter().rev() {
+            writeln!(out, "{:<10} {}", entry.frecency(now), entry.path.display())?;
+        }
+        Ok(exitcode::OK)
+    }
+
+    fn jump(&self, keywords: &[String], database: &Database) -> Result<i32, Error> {
+        let best = database
+            .matches(keywords, data::now())
+            .into_iter()
+            .find(|entry| entry.path.is_dir());
+        match best {
+            Some(entry) => change_dir(&entry.path).map(|_| exitcode::OK),
+            None => {
+                eprintln!("z: no match for {}", keywords.join(" "));
+                Ok(1)
+            }
+        }
+    }
+}
+
+impl Execute for ZCommand {
+    fn execute(&self, _shell: &mut Shell) -> Result<i32, Error> {
+        let Some(location) = Database::location() else {
+            eprintln!("z: cannot locate database: neither XDG_DATA_HOME nor HOME is set");
+            return Ok(1);
+        };
+        let mut database = Database::load(&location)?;
+        let args: Vec<String> = self
+            .options
+            .args
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        match args.first().map(String::as_str) {
+            None => self.list(&[], &database),
+            Some("-l") => self.list(&args[1..], &database),
+            Some("-x") => {
+                let dir = match self.options.args.as_ref().and_then(|args| args.get(1)) {
+                    Some(path) => PathBuf::from(path),
+                    None => env::current_dir()?,
+                };
+                let dir = dir.canonicalize().unwrap_or(dir);
+                if database.remove(&dir) {
+                    database.save(&location)?;
+                    Ok(exitcode::OK)
+                } else {
+                    eprintln!("z: {}: not in database", dir.display());
+                    Ok(1)
+                }
+            }
+            Some(_) => self.jump(&args, &database),
+        }
+    }
+}
+
+/*******************************
+ ------------ Shopt ------------
+*******************************/
+impl ShoptCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        ShoptCommand { options }
+    }
+}
+
+impl Execute for ShoptCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let args: Vec<String> = self
+            .options
+            .args
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let (setting, names) = match args.first().map(String::as_str) {
+            Some("-s") => (Some(true), &args[1..]),
+            Some("-u") => (Some(false), &args[1..]),
+            _ => (None, &args[..]),
+        };
+
+        let names: Vec<&str> = if names.is_empty() {
+            ShellOptions::NAMES.to_vec()
+        } else {
+            names.iter().map(String::as_str).collect()
+        };
+
+        let mut status = exitcode::OK;
+        for name in names {
+            let Some(option) = shell.options.get_mut(name) else {
+                eprintln!("shopt: {}: invalid shell option name", name);
+                status = 1;
+                continue;
+            };
+            match setting {
+                Some(value) => *option = value,
+                None => writeln!(out, "{:<15}{}", name, if *option { "on" } else { "off" })?,
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Set ------------
+*******************************/
+impl SetCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        SetCommand { options }
+    }
+
+    /// `set -o` lists the options as on or off, while `set +o` lists the commands that would set them again.
+    fn list(out: &mut dyn Write, shell: &Shell, on: bool) -> Result<(), Error> {
+        for mode in Mode::ALL {
+            let enabled = shell.editing_mode == Some(mode);
+            match on {
+                true => writeln!(
+                    out,
+                    "{:<15}{}",
+                    mode.name(),
+                    if enabled { "on" } else { "off" }
+                )?,
+                false => writeln!(
+                    out,
+                    "set {}o {}",
+                    if enabled { '-' } else { '+' },
+                    mode.name()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Execute for SetCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let args: Vec<String> = self
+            .options
+            .args
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        if args.is_empty() {
+            Self::list(&mut out, shell, true)?;
+            return Ok(exitcode::OK);
+        }
+
+        let mut status = exitcode::OK;
+        let mut args = args.iter().map(String::as_str);
+        while let Some(flag) = args.next() {
+            let on = match flag {
+                "-o" => true,
+                "+o" => false,
+                _ => {
+                    eprintln!("set: {}: invalid option", flag);
+                    eprintln!("set: usage: set [-o option-name] [+o option-name]");
+                    return Ok(exitcode::USAGE);
+                }
+            };
+            let Some(name) = args.next() else {
+                Self::list(&mut out, shell, on)?;
+                continue;
+            };
+            // Turning one editing mode on turns the other off; turning it off leaves lines unedited.
+            match Mode::from_name(name) {
+                Some(mode) if on => shell.editing_mode = Some(mode),
+                Some(mode) if shell.editing_mode == Some(mode) => shell.editing_mode = None,
+                Some(_) => (),
+                None => {
+                    eprintln!("set: {}: invalid option name", name);
+                    status = 1;
                 }
             }
         }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ History ------------
+*******************************/
+impl HistoryCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        HistoryCommand { options }
+    }
+
+    /// Lists the last `count` entries, or all of them, by number.
+    fn list(out: &mut dyn Write, shell: &Shell, count: Option<usize>) -> Result<(), Error> {
+        let entries = shell.history.entries();
+        let skipped = count.map_or(0, |count| entries.len().saturating_sub(count));
+        for (index, entry) in entries.iter().enumerate().skip(skipped) {
+            writeln!(out, "{:5}  {}", shell.history.base() + index, entry)?;
+        }
         Ok(())
     }
+
+    fn invalid_option(option: &str) -> i32 {
+        eprintln!("history: {}: invalid option", option);
+        eprintln!("history: usage: history [-c] [-d offset] [n] or history -awr [filename]");
+        eprintln!(
+            "       or history [--json] [--failed] [--cwd dir] [--since age] [--until age] [n]"
+        );
+        exitcode::USAGE
+    }
+
+    /**
+    Lists the last `n` records of the log of commands, or all of them, that pass the filters:
+    `--failed`, `--cwd dir` for commands run in or below a directory, and `--since age` and
+    `--until age` for those started within or before an age like `2h`. `--json` lists them as JSON.
+    **/
+    fn log(out: &mut dyn Write, args: &[String]) -> Result<i32, Error> {
+        let mut filter = Filter::default();
+        let mut json = false;
+        let mut count = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => json = true,
+                "--failed" => filter.failed = true,
+                option @ ("--cwd" | "--since" | "--until") => {
+                    let Some(value) = args.next() else {
+                        eprintln!("history: {}: option requires an argument", option);
+                        return Ok(exitcode::USAGE);
+                    };
+                    if option == "--cwd" {
+                        // A relative directory needs the current one, which may have been removed.
+                        let dir = match env::current_dir() {
+                            Ok(current) => current.join(value),
+                            Err(_) if Path::new(value).is_absolute() => PathBuf::from(value),
+                            Err(err) => {
+                                eprintln!("history: {}: {}", value, crate::strerror(&err));
+                                return Ok(1);
+                            }
+                        };
+                        filter.cwd = Some(dir.canonicalize().unwrap_or(dir));
+                        continue;
+                    }
+                    let Some(age) = record::parse_age(value) else {
+                        eprintln!("history: {}: invalid age, like 30m, 2h or 1d", value);
+                        return Ok(1);
+                    };
+                    let time = Some(data::now().saturating_sub(age));
+                    match option {
+                        "--since" => filter.since = time,
+                        _ => filter.until = time,
+                    }
+                }
+                number if number.parse::<usize>().is_ok() => count = number.parse().ok(),
+                option => return Ok(Self::invalid_option(option)),
+            }
+        }
+
+        let Some(path) = record::location() else {
+            return Ok(exitcode::OK);
+        };
+        let records = match record::read(&path) {
+            Ok(records) => records,
+            Err(err) => {
+                eprintln!("history: {}: {}", path.display(), err);
+                return Ok(1);
+            }
+        };
+        let matching: Vec<&Record> = records.iter().filter(|r| filter.matches(r)).collect();
+        let skipped = count.map_or(0, |count| matching.len().saturating_sub(count));
+        for record in &matching[skipped..] {
+            match json {
+                true => writeln!(out, "{}", record.to_json())?,
+                false => writeln!(out, "{}", record)?,
+            }
+        }
+        Ok(exitcode::OK)
+    }
+
+    /// `-a`, `-r` and `-w`, on the file given or else the history file.
+    fn file(&self, shell: &mut Shell, flag: &str, path: Option<&String>) -> i32 {
+        let Some(path) = path.map(PathBuf::from).or_else(|| shell.history_file()) else {
+            return exitcode::OK;
+        };
+        let file_limit = shell.history_limit("HISTFILESIZE");
+        let result = match flag {
+            "-a" => history::append(&path, shell.history.unsaved(), file_limit)
+                .map(|()| shell.history.mark_saved()),
+            "-w" => history::write(&path, shell.history.entries(), file_limit)
+                .map(|()| shell.history.mark_saved()),
+            _ => history::read(&path).map(|entries| {
+                shell.history.extend_saved(entries);
+                let limit = shell.history_limit("HISTSIZE");
+                shell.history.truncate(limit);
+            }),
+        };
+        match result {
+            Ok(()) => exitcode::OK,
+            Err(err) => {
+                eprintln!("history: {}: {}", path.display(), err);
+                1
+            }
+        }
+    }
+}
+
+impl Execute for HistoryCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let args: Vec<String> = self
+            .options
+            .args
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        match args.first().map(String::as_str) {
+            None => Self::list(&mut out, shell, None)?,
+            Some("-c") => shell.history.clear(),
+            Some("-d") => {
+                let Some(offset) = args.get(1) else {
+                    eprintln!("history: -d: option requires an argument");
+                    return Ok(exitcode::USAGE);
+                };
+                // A negative offset counts back from the end, `-1` being the newest entry.
+                let end = shell.history.base() + shell.history.entries().len();
+                let number = match offset.parse::<i64>() {
+                    Ok(offset) if offset < 0 => usize::try_from(end as i64 + offset).ok(),
+                    Ok(offset) => usize::try_from(offset).ok(),
+                    Err(_) => None,
+                };
+                if !number.is_some_and(|number| shell.history.remove(number)) {
+                    eprintln!("history: {}: history position out of range", offset);
+                    return Ok(1);
+                }
+            }
+            Some(flag @ ("-a" | "-r" | "-w")) => return Ok(self.file(shell, flag, args.get(1))),
+            Some(option) if option.starts_with("--") => return Self::log(&mut out, &args),
+            Some(option) if option.starts_with('-') && option != "-" => {
+                return Ok(Self::invalid_option(option))
+            }
+            Some(count) => match count.parse() {
+                Ok(count) => Self::list(&mut out, shell, Some(count))?,
+                Err(_) => {
+                    eprintln!("history: {}: numeric argument required", count);
+                    return Ok(1);
+                }
+            },
+        }
+        Ok(exitcode::OK)
+    }
+}
+
+/*******************************
+ ------------ Hash ------------
+*******************************/
+impl HashCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        HashCommand { options }
+    }
+
+    fn list(&self, shell: &Shell, reusable: bool) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let mut entries = shell.commands.entries().peekable();
+        if entries.peek().is_none() {
+            eprintln!("hash: hash table empty");
+            return Ok(exitcode::OK);
+        }
+        if !reusable {
+            writeln!(out, "hits\tcommand")?;
+        }
+        for (name, entry) in entries {
+            if reusable {
+                writeln!(
+                    out,
+                    "builtin hash -p {} {}",
+                    entry.path.display(),
+                    name.to_string_lossy()
+                )?;
+            } else {
+                writeln!(out, "{:>4}\t{}", entry.hits, entry.path.display())?;
+            }
+        }
+        Ok(exitcode::OK)
+    }
+}
+
+impl Execute for HashCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let args = self.options.args.clone().unwrap_or_default();
+        match args.first().and_then(|arg| arg.to_str()) {
+            None if args.is_empty() => self.list(shell, false),
+            Some("-l") => self.list(shell, true),
+            Some("-r") => {
+                shell.commands.clear();
+                Ok(exitcode::OK)
+            }
+            Some("-p") => match &args[1..] {
+                [path, names @ ..] if !names.is_empty() => {
+                    for name in names {
+                        shell.commands.pin(name, PathBuf::from(path));
+                    }
+                    Ok(exitcode::OK)
+                }
+                _ => {
+                    eprintln!("hash: usage: hash -p path name [name ...]");
+                    Ok(exitcode::USAGE)
+                }
+            },
+            Some("-d") => {
+                let mut status = exitcode::OK;
+                for name in &args[1..] {
+                    if !shell.commands.remove(name) {
+                        eprintln!("hash: {}: not found", name.to_string_lossy());
+                        status = 1;
+                    }
+                }
+                Ok(status)
+            }
+            _ => {
+                let mut status = exitcode::OK;
+                for name in &args {
+                    if is_builtin(name) {
+                        continue;
+                    }
+                    if shell.lookup_command(name).is_none() {
+                        eprintln!("hash: {}: not found", name.to_string_lossy());
+                        status = 1;
+                    }
+                }
+                Ok(status)
+            }
+        }
+    }
+}
+
+/*******************************
+ ------------ Assign ------------
+*******************************/
+impl AssignCommand {
+    pub fn new(assignments: Vec<(String, OsString)>) -> Self {
+        AssignCommand { assignments }
+    }
+}
+
+impl Execute for AssignCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        shell.assign(self.assignments.clone());
+        Ok(exitcode::OK)
+    }
+}
+
+impl AssignedCommand {
+    pub fn new(assignments: Vec<(String, OsString)>, command: Box<dyn Execute>) -> Self {
+        AssignedCommand {
+            assignments,
+            command,
+        }
+    }
+}
+
+impl Execute for AssignedCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        shell.with_assignments(self.assignments.clone(), |shell| {
+            self.command.execute(shell)
+        })
+    }
+
+    fn run_forked(&self, shell: &mut Shell) -> i32 {
+        shell.with_assignments(self.assignments.clone(), |shell| {
+            self.command.run_forked(shell)
+        })
+    }
+}
+
+/*******************************
+ ------------ Export ------------
+*******************************/
+impl ExportCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        ExportCommand { options }
+    }
+
+    /// Lists the exported variables in a form that can be read back in.
+    fn list(&self, vars: &Variables) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        for (name, var) in vars.iter().filter(|(_, var)| var.exported) {
+            write!(out, "export {}=", name)?;
+            out.write_all(&single_quote(&var.value))?;
+            writeln!(out)?;
+        }
+        Ok(exitcode::OK)
+    }
+}
+
+impl Execute for ExportCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let args = self.options.args.clone().unwrap_or_default();
+        if args.is_empty() || args.len() == 1 && args[0] == "-p" {
+            return self.list(&shell.vars);
+        }
+
+        let mut status = exitcode::OK;
+        for arg in &args {
+            if let Some((name, value)) = vars::parse_assignment(arg) {
+                shell.vars.set(&name, value);
+                shell.vars.export(&name);
+            } else if arg.to_str().is_some_and(vars::is_valid_name) {
+                shell.vars.export(arg.to_str().unwrap());
+            } else {
+                eprintln!(
+                    "export: `{}': not a valid identifier",
+                    arg.to_string_lossy()
+                );
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Quotes `value` so the shell reads it back as exactly the same word.
+fn single_quote(value: &OsStr) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in value.as_bytes() {
+        match b {
+            b'\'' => quoted.extend_from_slice(br"'\''"),
+            _ => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/*******************************
+ ------------ Unset ------------
+*******************************/
+impl UnsetCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        UnsetCommand { options }
+    }
+}
+
+impl Execute for UnsetCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut status = exitcode::OK;
+        // Without `-v` or `-f`, a name that is not a variable may still be a function.
+        let mut functions = None;
+        for arg in self.options.args.clone().unwrap_or_default() {
+            match arg.to_str() {
+                Some("-v") => functions = Some(false),
+                Some("-f") => functions = Some(true),
+                Some(name) if functions == Some(true) => {
+                    shell.functions.remove(name);
+                }
+                Some(name) if functions.is_none() && shell.vars.get(name).is_none() => {
+                    shell.functions.remove(name);
+                }
+                Some(name) if vars::is_valid_name(name) => shell.vars.unset(name),
+                _ => {
+                    eprintln!("unset: `{}': not a valid identifier", arg.to_string_lossy());
+                    status = 1;
+                }
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Command ------------
+*******************************/
+impl CommandCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        CommandCommand { options }
+    }
+
+    /// `command -v` and `command -V`: describe how `name` would be run.
+    fn describe(
+        &self,
+        shell: &mut Shell,
+        name: &OsStr,
+        default_path: bool,
+        verbose: bool,
+    ) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let alias = name.to_str().and_then(|name| shell.aliases.get(name));
+        if let Some(value) = alias {
+            let name = name.to_string_lossy();
+            if verbose {
+                writeln!(out, "{} is aliased to `{}'", name, value)?;
+            } else {
+                AliasCommand::print(&mut out, &name, value)?;
+            }
+            return Ok(exitcode::OK);
+        }
+        if let Some(text) = name.to_str().and_then(|name| shell.function_text(name)) {
+            let name = name.to_string_lossy();
+            if verbose {
+                writeln!(out, "{} is a function", name)?;
+                writeln!(out, "{}", text)?;
+            } else {
+                writeln!(out, "{}", name)?;
+            }
+            return Ok(exitcode::OK);
+        }
+        let found = if is_builtin(name) {
+            Some(None)
+        } else if name.as_bytes().contains(&b'/') {
+            crate::lookup_path_command(name)
+                .ok()
+                .map(|command| Some(PathBuf::from(command.path)))
+        } else {
+            self.find(shell, name, default_path).map(Some)
+        };
+        let name = name.to_string_lossy();
+        match found {
+            Some(None) if verbose => writeln!(out, "{} is a shell builtin", name)?,
+            Some(None) => writeln!(out, "{}", name)?,
+            Some(Some(path)) if verbose => writeln!(out, "{} is {}", name, path.display())?,
+            Some(Some(path)) => writeln!(out, "{}", path.display())?,
+            None if verbose => {
+                eprintln!("command: {}: not found", name);
+                return Ok(1);
+            }
+            None => return Ok(1),
+        }
+        Ok(exitcode::OK)
+    }
+
+    /// `-p` searches the system default path, so standard utilities are found whatever PATH is.
+    fn find(&self, shell: &mut Shell, name: &OsStr, default_path: bool) -> Option<PathBuf> {
+        if default_path {
+            // Not through the table, which would otherwise be invalidated by the different path.
+            shell.commands.search(name, &vars::default_path())
+        } else {
+            shell.lookup_command(name)
+        }
+    }
+}
+
+impl Execute for CommandCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let args = self.options.args.clone().unwrap_or_default();
+        let mut default_path = false;
+        let mut describe = None;
+        let mut words = args.iter().peekable();
+        while let Some(flags) = words.next_if(|arg| arg.len() > 1 && arg.as_bytes()[0] == b'-') {
+            if flags == "--" {
+                break;
+            }
+            for flag in &flags.as_bytes()[1..] {
+                match flag {
+                    b'p' => default_path = true,
+                    b'v' => describe = Some(false),
+                    b'V' => describe = Some(true),
+                    _ => {
+                        eprintln!("command: -{}: invalid option", *flag as char);
+                        return Ok(exitcode::USAGE);
+                    }
+                }
+            }
+        }
+        let Some(name) = words.next() else {
+            return Ok(exitcode::OK);
+        };
+
+        if let Some(verbose) = describe {
+            return self.describe(shell, name, default_path, verbose);
+        }
+
+        let rest: Vec<OsString> = words.cloned().collect();
+        let options = ParsedCommand {
+            cmd: Some(name.clone()),
+            args: if rest.is_empty() { None } else { Some(rest) },
+            ..self.options.clone()
+        };
+        if !default_path || is_builtin(name) || name.as_bytes().contains(&b'/') {
+            return shell.dispatch_command(options).execute(shell);
+        }
+        match self.find(shell, name, default_path) {
+            Some(found) => RunCommand::new(
+                options,
+                CommandInfo {
+                    bin: name.clone(),
+                    path: found.into_os_string(),
+                },
+                Vec::new(),
+            )
+            .execute(shell),
+            None => InvalidCommand::new(options).execute(shell),
+        }
+    }
+}
+
+/*******************************
+ ------------ Jobs ------------
+*******************************/
+
+/// The job a builtin's job spec refers to, or the current job without one. Complains if there is no such job.
+fn job_argument(shell: &Shell, builtin: &str, spec: Option<&OsString>) -> Option<usize> {
+    let spec = spec.map(|spec| spec.to_string_lossy());
+    match shell.jobs.resolve(spec.as_deref().unwrap_or("%+")) {
+        Ok(id) => Some(id),
+        Err(err) => {
+            eprintln!(
+                "{}: {}: {}",
+                builtin,
+                spec.as_deref().unwrap_or("current"),
+                err
+            );
+            None
+        }
+    }
+}
+
+impl JobsCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        JobsCommand { options }
+    }
+}
+
+impl Execute for JobsCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let (mut long, mut pids_only) = (false, false);
+        let mut specs = Vec::new();
+        for arg in self.options.args.clone().unwrap_or_default() {
+            match arg.as_bytes().strip_prefix(b"-") {
+                Some(flags) if !flags.is_empty() => {
+                    for flag in flags {
+                        match flag {
+                            b'l' => long = true,
+                            b'p' => pids_only = true,
+                            _ => {
+                                eprintln!("jobs: -{}: invalid option", *flag as char);
+                                return Ok(exitcode::USAGE);
+                            }
+                        }
+                    }
+                }
+                _ => specs.push(arg),
+            }
+        }
+
+        shell.jobs.reap();
+        let ids: Vec<usize> = if specs.is_empty() {
+            shell.jobs.iter().map(|job| job.id).collect()
+        } else {
+            let ids: Vec<usize> = specs
+                .iter()
+                .filter_map(|spec| job_argument(shell, "jobs", Some(spec)))
+                .collect();
+            if ids.len() < specs.len() {
+                return Ok(1);
+            }
+            ids
+        };
+        for id in ids {
+            let job = shell.jobs.get(id).expect("resolved jobs exist");
+            if pids_only {
+                writeln!(out, "{}", job.pgid())?;
+            } else {
+                writeln!(
+                    out,
+                    "{}",
+                    jobs::format_job(job, shell.jobs.marker(id), long)
+                )?;
+            }
+            // Finished jobs have now been reported.
+            if job.is_done() {
+                shell.jobs.retire(id);
+            }
+        }
+        Ok(exitcode::OK)
+    }
+}
+
+impl FgCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        FgCommand { options }
+    }
+}
+
+impl Execute for FgCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        if !shell.job_control {
+            eprintln!("fg: no job control");
+            return Ok(1);
+        }
+        let args = self.options.args.clone().unwrap_or_default();
+        let Some(id) = job_argument(shell, "fg", args.first()) else {
+            return Ok(1);
+        };
+        let mut job = shell.jobs.remove(id).expect("resolved jobs exist");
+        writeln!(self.options.get_output()?, "{}", job.command)?;
+        jobs::signal_group(job.pgid(), libc::SIGCONT)?;
+        job.set_running();
+        Ok(shell.foreground(job))
+    }
+}
+
+impl BgCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        BgCommand { options }
+    }
+}
+
+impl Execute for BgCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        if !shell.job_control {
+            eprintln!("bg: no job control");
+            return Ok(1);
+        }
+        let mut out = self.options.get_output()?;
+        let args = self.options.args.clone().unwrap_or_default();
+        let specs: Vec<Option<&OsString>> = if args.is_empty() {
+            vec![None]
+        } else {
+            args.iter().map(Some).collect()
+        };
+        let mut status = exitcode::OK;
+        for spec in specs {
+            let Some(id) = job_argument(shell, "bg", spec) else {
+                status = 1;
+                continue;
+            };
+            let marker = shell.jobs.marker(id);
+            let job = shell.jobs.get_mut(id).expect("resolved jobs exist");
+            if !job.is_stopped() {
+                eprintln!("bg: job {} already in background", id);
+                continue;
+            }
+            jobs::signal_group(job.pgid(), libc::SIGCONT)?;
+            job.set_running();
+            writeln!(out, "[{}]{} {} &", id, marker, job.command)?;
+        }
+        Ok(status)
+    }
+}
+
+impl DisownCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        DisownCommand { options }
+    }
+}
+
+impl Execute for DisownCommand {
+    /// `disown [-a] [-r] [jobspec...]`: forgets jobs, so the shell no longer reports or waits for them.
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let (mut all, mut running_only) = (false, false);
+        let mut specs = Vec::new();
+        for arg in self.options.args.clone().unwrap_or_default() {
+            match arg.to_str() {
+                Some("-a") => all = true,
+                Some("-r") => running_only = true,
+                _ => specs.push(arg),
+            }
+        }
+
+        let ids: Vec<usize> = if specs.is_empty() && (all || running_only) {
+            shell
+                .jobs
+                .iter()
+                .filter(|job| !running_only || !(job.is_stopped() || job.is_done()))
+                .map(|job| job.id)
+                .collect()
+        } else if specs.is_empty() {
+            match job_argument(shell, "disown", None) {
+                Some(id) => vec![id],
+                None => return Ok(1),
+            }
+        } else {
+            let ids: Vec<usize> = specs
+                .iter()
+                .filter_map(|spec| job_argument(shell, "disown", Some(spec)))
+                .collect();
+            if ids.len() < specs.len() {
+                return Ok(1);
+            }
+            ids
+        };
+        for id in ids {
+            shell.jobs.remove(id);
+        }
+        Ok(exitcode::OK)
+    }
+}
+
+impl WaitCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        WaitCommand { options }
+    }
+
+    /// `wait -n`: waits until one of the given jobs, or any job, finishes, unless one already has.
+    fn wait_next(shell: &mut Shell, ids: &[usize]) -> i32 {
+        let given = |job: &Job| ids.is_empty() || ids.contains(&job.id);
+        let done = shell
+            .jobs
+            .iter()
+            .find(|job| given(job) && job.is_done())
+            .map(|job| job.id);
+        if let Some(id) = done {
+            let job = shell.jobs.remove(id).expect("found jobs exist");
+            return job.status();
+        }
+        if !shell.jobs.iter().any(given) {
+            return NOT_FOUND;
+        }
+        while let Some((pid, status)) = jobs::waitpid_any(0) {
+            let Some(id) = shell.jobs.update(pid, status) else {
+                continue;
+            };
+            if (ids.is_empty() || ids.contains(&id))
+                && shell.jobs.get(id).is_some_and(|job| job.is_done())
+            {
+                let job = shell.jobs.remove(id).expect("updated jobs exist");
+                return job.status();
+            }
+        }
+        NOT_FOUND
+    }
+}
+
+impl Execute for WaitCommand {
+    /// `wait [-n] [%job|pid...]`: waits for jobs to finish, returning the status of the last one waited for.
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut next = false;
+        let mut ids = Vec::new();
+        let mut status = exitcode::OK;
+        for arg in self.options.args.clone().unwrap_or_default() {
+            let word = arg.to_string_lossy();
+            if word == "-n" {
+                next = true;
+            } else if word.starts_with('%') {
+                match job_argument(shell, "wait", Some(&arg)) {
+                    Some(id) => ids.push(id),
+                    None => status = NOT_FOUND,
+                }
+            } else {
+                let pid = word.parse().ok();
+                match pid.and_then(|pid| shell.jobs.find_pid(pid)) {
+                    Some(id) => ids.push(id),
+                    None => match pid.and_then(|pid| shell.jobs.take_finished(pid)) {
+                        // A job that finished and was reported already; a script only asks now.
+                        Some(finished) => status = finished,
+                        None => {
+                            eprintln!("wait: pid {} is not a child of this shell", word);
+                            status = NOT_FOUND;
+                        }
+                    },
+                }
+            }
+        }
+
+        if next {
+            return Ok(WaitCommand::wait_next(shell, &ids));
+        }
+        if self.options.args.as_ref().map_or(true, Vec::is_empty) {
+            let all: Vec<usize> = shell.jobs.iter().map(|job| job.id).collect();
+            for id in all {
+                let Some(job) = shell.jobs.get_mut(id) else {
+                    continue;
+                };
+                match job.wait() {
+                    Ok(()) if job.is_done() => {
+                        shell.jobs.remove(id);
+                    }
+                    Ok(()) => (),
+                    Err(err) => eprintln!("wait: %{}: {}", id, crate::strerror(&err)),
+                }
+            }
+            return Ok(exitcode::OK);
+        }
+        for id in ids {
+            let Some(job) = shell.jobs.get_mut(id) else {
+                continue;
+            };
+            if let Err(err) = job.wait() {
+                eprintln!("wait: %{}: {}", id, crate::strerror(&err));
+                status = NOT_FOUND;
+                continue;
+            }
+            status = job.status();
+            if job.is_done() {
+                shell.jobs.remove(id);
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Trap ------------
+*******************************/
+
+/// Lists the signals like `kill -l` does, five to a line: ` 1) SIGHUP\t 2) SIGINT...`.
+fn write_signal_list(out: &mut dyn Write) -> Result<(), Error> {
+    for (index, (name, number)) in signals::SIGNALS.iter().enumerate() {
+        let separator = if index % 5 == 4 || index + 1 == signals::SIGNALS.len() {
+            "\n"
+        } else {
+            "\t"
+        };
+        write!(out, "{:>2}) SIG{}{}", number, name, separator)?;
+    }
+    Ok(())
+}
+
+impl TrapCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        TrapCommand { options }
+    }
+
+    /// `trap -p`: prints the given traps, or all of them, so they can be read back in.
+    fn print(shell: &Shell, out: &mut dyn Write, conditions: &[Condition]) -> Result<(), Error> {
+        for (condition, action) in shell.traps.iter() {
+            if conditions.is_empty() || conditions.contains(condition) {
+                out.write_all(b"trap -- ")?;
+                out.write_all(&single_quote(OsStr::from_bytes(action)))?;
+                writeln!(out, " {}", condition.name())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Execute for TrapCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let args = self.options.args.clone().unwrap_or_default();
+        let mut args = args.as_slice();
+        let mut print = false;
+        match args.first().and_then(|arg| arg.to_str()) {
+            Some("-l") => {
+                write_signal_list(&mut out)?;
+                return Ok(exitcode::OK);
+            }
+            Some("-p") => {
+                print = true;
+                args = &args[1..];
+            }
+            Some("--") => args = &args[1..],
+            _ => (),
+        }
+
+        // Without an action, or with a number where it would be, the conditions are reset.
+        let (action, specs) = match args {
+            [] => (None, args),
+            [first, ..]
+                if print
+                    || first
+                        .to_str()
+                        .is_some_and(|first| first.parse::<u32>().is_ok()) =>
+            {
+                (None, args)
+            }
+            [_] => (None, args),
+            [first, rest @ ..] if first == "-" => (None, rest),
+            [first, rest @ ..] => (Some(first.as_bytes().to_vec()), rest),
+        };
+
+        let mut status = exitcode::OK;
+        let mut conditions = Vec::new();
+        for spec in specs {
+            match Condition::parse(&spec.to_string_lossy()) {
+                Some(condition) => conditions.push(condition),
+                None => {
+                    eprintln!(
+                        "trap: {}: invalid signal specification",
+                        spec.to_string_lossy()
+                    );
+                    status = 1;
+                }
+            }
+        }
+
+        if print || specs.is_empty() {
+            TrapCommand::print(shell, &mut out, &conditions)?;
+            return Ok(status);
+        }
+        for condition in conditions {
+            match &action {
+                Some(_)
+                    if matches!(condition, Condition::Signal(libc::SIGKILL | libc::SIGSTOP)) =>
+                {
+                    eprintln!("trap: {}: cannot be trapped", condition.name());
+                    status = 1;
+                }
+                Some(action) => shell.traps.set(condition, action.clone()),
+                None => shell.traps.reset(condition, shell.interactive),
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Kill ------------
+*******************************/
+impl KillCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        KillCommand { options }
+    }
+
+    /// `kill -l [sigspec...]`: lists the signals, or translates between names and numbers.
+    fn list(&self, out: &mut dyn Write, specs: &[OsString]) -> Result<i32, Error> {
+        if specs.is_empty() {
+            write_signal_list(out)?;
+            return Ok(exitcode::OK);
+        }
+        let mut status = exitcode::OK;
+        for spec in specs {
+            let spec = spec.to_string_lossy();
+            let found = match spec.parse::<i32>() {
+                // An exit status of a command killed by a signal names that signal.
+                Ok(number) => {
+                    signals::signal_name(if number > 128 { number - 128 } else { number })
+                        .map(str::to_string)
+                }
+                Err(_) => signals::signal_number(&spec).map(|number| number.to_string()),
+            };
+            match found {
+                Some(found) => writeln!(out, "{}", found)?,
+                None => {
+                    eprintln!("kill: {}: invalid signal specification", spec);
+                    status = 1;
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// A signal to send: any `signal_number` spec, or 0 to only check that the target exists.
+    fn signal(spec: &str) -> Option<libc::c_int> {
+        match spec {
+            "0" => Some(0),
+            _ => signals::signal_number(spec),
+        }
+    }
+
+    /// Sends `signal` to a job, or to a single process.
+    fn send(shell: &Shell, signal: libc::c_int, target: &str) -> Result<(), String> {
+        if target.starts_with('%') {
+            let id = shell
+                .jobs
+                .resolve(target)
+                .map_err(|err| format!("{}: {}", target, err))?;
+            let job = shell.jobs.get(id).expect("resolved jobs exist");
+            job.signal(signal)
+                .map_err(|err| format!("{}: {}", target, crate::strerror(&err)))?;
+            // A stopped job has to be woken up to act on being asked to terminate.
+            if job.is_stopped() && matches!(signal, libc::SIGTERM | libc::SIGHUP) {
+                let _ = job.signal(libc::SIGCONT);
+            }
+            return Ok(());
+        }
+        let pid: libc::pid_t = target
+            .parse()
+            .map_err(|_| format!("{}: arguments must be process or job IDs", target))?;
+        // SAFETY: kill has no effect on memory.
+        if unsafe { libc::kill(pid, signal) } == -1 {
+            let err = Error::last_os_error();
+            return Err(format!("({}) - {}", pid, crate::strerror(&err)));
+        }
+        Ok(())
+    }
+}
+
+impl Execute for KillCommand {
+    /// `kill [-s sigspec | -n signum | -sigspec] pid|jobspec...` and `kill -l [sigspec...]`.
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let args = self.options.args.clone().unwrap_or_default();
+        let mut signal = libc::SIGTERM;
+        let mut rest = args.as_slice();
+
+        if let Some(first) = rest
+            .first()
+            .map(|first| first.to_string_lossy().into_owned())
+        {
+            match first.as_str() {
+                "-l" | "-L" => return self.list(&mut out, &rest[1..]),
+                "--" => rest = &rest[1..],
+                "-s" | "-n" => {
+                    let Some(spec) = rest.get(1) else {
+                        eprintln!("kill: {}: option requires an argument", first);
+                        return Ok(exitcode::USAGE);
+                    };
+                    let spec = spec.to_string_lossy();
+                    match KillCommand::signal(&spec) {
+                        Some(number) => signal = number,
+                        None => {
+                            eprintln!("kill: {}: invalid signal specification", spec);
+                            return Ok(1);
+                        }
+                    }
+                    rest = &rest[2..];
+                }
+                _ if first.len() > 1 && first.starts_with('-') => {
+                    match KillCommand::signal(&first[1..]) {
+                        Some(number) => signal = number,
+                        None => {
+                            eprintln!("kill: {}: invalid signal specification", &first[1..]);
+                            return Ok(1);
+                        }
+                    }
+                    rest = &rest[1..];
+                }
+                _ => (),
+            }
+        }
+        if rest.is_empty() {
+            eprintln!("kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]");
+            return Ok(exitcode::USAGE);
+        }
+
+        let mut status = exitcode::OK;
+        for target in rest {
+            if let Err(err) = KillCommand::send(shell, signal, &target.to_string_lossy()) {
+                eprintln!("kill: {}", err);
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Break / Continue ------------
+*******************************/
+impl LoopCommand {
+    pub fn new_break(options: ParsedCommand) -> Self {
+        LoopCommand {
+            options,
+            name: "break",
+            control: Control::Break,
+        }
+    }
+
+    pub fn new_continue(options: ParsedCommand) -> Self {
+        LoopCommand {
+            options,
+            name: "continue",
+            control: Control::Continue,
+        }
+    }
+}
+
+impl Execute for LoopCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let count = match self.options.args.as_deref() {
+            None | Some([]) => 1,
+            Some([count]) => match count.to_str().and_then(|count| count.parse::<i64>().ok()) {
+                Some(count) if count >= 1 => count as usize,
+                Some(_) => {
+                    eprintln!(
+                        "{}: {}: loop count out of range",
+                        self.name,
+                        count.to_string_lossy()
+                    );
+                    return Ok(1);
+                }
+                None => {
+                    eprintln!(
+                        "{}: {}: numeric argument required",
+                        self.name,
+                        count.to_string_lossy()
+                    );
+                    return Ok(SYNTAX_ERROR);
+                }
+            },
+            Some(_) => {
+                eprintln!("{}: too many arguments", self.name);
+                return Ok(1);
+            }
+        };
+        if shell.loop_depth == 0 {
+            eprintln!(
+                "{}: only meaningful in a `for', `while', or `until' loop",
+                self.name
+            );
+            return Ok(exitcode::OK);
+        }
+        // Asking to leave more loops than there are leaves all of them.
+        shell.control = Some((self.control)(count.min(shell.loop_depth)));
+        Ok(exitcode::OK)
+    }
+}
+
+/*******************************
+ ------------ Function ------------
+*******************************/
+impl FunctionCommand {
+    pub fn new(
+        options: ParsedCommand,
+        body: Rc<syntax::Command>,
+        env: Vec<(String, OsString)>,
+    ) -> Self {
+        FunctionCommand { options, body, env }
+    }
+}
+
+impl Execute for FunctionCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let name = self.options.cmd.clone().unwrap_or_default();
+        let arguments = self.options.args.clone().unwrap_or_default();
+        shell
+            .call_function(
+                &name.to_string_lossy(),
+                &self.body,
+                arguments,
+                self.env.clone(),
+            )
+            .map_err(|err| Error::other(err.to_string()))
+    }
+}
+
+/*******************************
+ ------------ Redirected ------------
+*******************************/
+impl RedirectedCommand {
+    pub fn new(redirects: ParsedCommand, command: Box<dyn Execute>) -> Self {
+        RedirectedCommand { redirects, command }
+    }
+}
+
+impl Execute for RedirectedCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        match with_redirects(&self.redirects, || self.command.execute(shell)) {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!("shelly: {}{}", shell.location_prefix(), err);
+                Ok(1)
+            }
+        }
+    }
+
+    fn run_forked(&self, shell: &mut Shell) -> i32 {
+        match with_redirects(&self.redirects, || self.command.run_forked(shell)) {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!("shelly: {}{}", shell.location_prefix(), err);
+                1
+            }
+        }
+    }
+}
+
+/// Opens the file a redirection names, replacing what is in it or adding to the end. Errors name the file.
+fn open_redirect(path: &Path, append: bool) -> Result<File, Error> {
+    let file = match append {
+        true => OpenOptions::new().append(true).create(true).open(path),
+        false => File::create(path),
+    };
+    file.map_err(|err| {
+        let message = format!("{}: {}", path.display(), crate::strerror(&err));
+        Error::new(err.kind(), message)
+    })
+}
+
+/**
+Points standard output and error where `redirects` says while `run` runs, for commands that run in
+the shell itself and may start other programs, which could not be handed a writer. Redirections are
+made from left to right, so `2>&1 > log` leaves errors where the output was before it went to `log`.
+**/
+pub fn with_redirects<T>(redirects: &ParsedCommand, run: impl FnOnce() -> T) -> Result<T, Error> {
+    std::io::stdout().flush()?;
+    let mut saved = Vec::new();
+    for redirect in &redirects.redirects {
+        let (file, to) = match redirect {
+            Redirect::File {
+                descriptor,
+                path,
+                append,
+            } => match open_redirect(path, *append) {
+                Ok(file) => (Some(file), i32::from(*descriptor)),
+                Err(err) => {
+                    restore(&saved);
+                    return Err(err);
+                }
+            },
+            Redirect::ErrorsToOutput => (None, libc::STDERR_FILENO),
+        };
+        let from = file
+            .as_ref()
+            .map_or(libc::STDOUT_FILENO, |file| file.as_raw_fd());
+        // SAFETY: fcntl and dup2 only work on descriptors. The copy is closed once it is put back,
+        // and is not passed on to programs meanwhile.
+        let copy = unsafe { libc::fcntl(to, libc::F_DUPFD_CLOEXEC, 10) };
+        if copy == -1 {
+            let err = Error::last_os_error();
+            restore(&saved);
+            return Err(err);
+        }
+        saved.push((copy, to));
+        // SAFETY: as above; `file` is open until the end of this iteration, after which `to` keeps
+        // its own copy.
+        unsafe { libc::dup2(from, to) };
+    }
+    let result = run();
+    let _ = std::io::stdout().flush();
+    restore(&saved);
+    Ok(result)
+}
+
+/// Puts back the descriptors `with_redirects` saved, the last first.
+fn restore(saved: &[(i32, i32)]) {
+    for &(copy, to) in saved.iter().rev() {
+        // SAFETY: the copies were made by `with_redirects`, and are not used after this.
+        unsafe {
+            libc::dup2(copy, to);
+            libc::close(copy);
+        }
+    }
+}
+
+/*******************************
+ ------------ Return ------------
+*******************************/
+impl ReturnCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        ReturnCommand { options }
+    }
+}
+
+impl Execute for ReturnCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        if shell.function_depth == 0 && shell.source_depth == 0 {
+            eprintln!("return: can only `return' from a function or sourced script");
+            return Ok(1);
+        }
+        let status = match self.options.args.as_deref() {
+            None | Some([]) => shell.last_status,
+            Some([status, ..]) => match status
+                .to_str()
+                .and_then(|status| status.parse::<i64>().ok())
+            {
+                // Only the low eight bits make it into an exit status.
+                Some(status) => status.rem_euclid(256) as i32,
+                None => {
+                    eprintln!(
+                        "return: {}: numeric argument required",
+                        status.to_string_lossy()
+                    );
+                    SYNTAX_ERROR
+                }
+            },
+        };
+        shell.control = Some(Control::Return);
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Local ------------
+*******************************/
+impl LocalCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        LocalCommand { options }
+    }
+}
+
+impl Execute for LocalCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        if shell.function_depth == 0 {
+            eprintln!("local: can only be used in a function");
+            return Ok(1);
+        }
+        let mut status = exitcode::OK;
+        for arg in self.options.args.clone().unwrap_or_default() {
+            let (name, value) = match vars::parse_assignment(&arg) {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.to_string_lossy().into_owned(), None),
+            };
+            if !vars::is_valid_name(&name) {
+                eprintln!("local: `{}': not a valid identifier", arg.to_string_lossy());
+                status = 1;
+                continue;
+            }
+            shell.vars.make_local(&name);
+            if let Some(value) = value {
+                shell.vars.set(&name, value);
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Test ------------
+*******************************/
+impl TestCommand {
+    pub fn new_test(options: ParsedCommand) -> Self {
+        TestCommand {
+            options,
+            name: "test",
+            bracket: false,
+        }
+    }
+
+    pub fn new_bracket(options: ParsedCommand) -> Self {
+        TestCommand {
+            options,
+            name: "[",
+            bracket: true,
+        }
+    }
+}
+
+impl Execute for TestCommand {
+    fn execute(&self, _shell: &mut Shell) -> Result<i32, Error> {
+        let mut args = self.options.args.as_deref().unwrap_or_default();
+        if self.bracket {
+            match args.split_last() {
+                Some((last, rest)) if last == "]" => args = rest,
+                _ => {
+                    eprintln!("[: missing `]'");
+                    return Ok(SYNTAX_ERROR);
+                }
+            }
+        }
+        match conditional::test(args) {
+            Ok(true) => Ok(exitcode::OK),
+            Ok(false) => Ok(1),
+            Err(err) => {
+                eprintln!("{}: {}", self.name, err);
+                Ok(SYNTAX_ERROR)
+            }
+        }
+    }
+}
+
+/*******************************
+ ------------ Alias ------------
+*******************************/
+impl AliasCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        AliasCommand { options }
+    }
+
+    /// Writes an alias in a form that can be read back in.
+    fn print(out: &mut dyn Write, name: &str, value: &str) -> Result<(), Error> {
+        write!(out, "alias {}=", name)?;
+        out.write_all(&single_quote(OsStr::new(value)))?;
+        writeln!(out)
+    }
+}
+
+impl Execute for AliasCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut out = self.options.get_output()?;
+        let mut args = self.options.args.clone().unwrap_or_default();
+        if args.first().is_some_and(|arg| arg == "-p") {
+            args.remove(0);
+        }
+        if args.is_empty() {
+            for (name, value) in shell.aliases.iter() {
+                Self::print(&mut out, name, value)?;
+            }
+            return Ok(exitcode::OK);
+        }
+
+        let mut status = exitcode::OK;
+        for arg in &args {
+            let arg = arg.to_string_lossy();
+            match arg.split_once('=') {
+                Some((name, value)) if aliases::is_valid_name(name) => {
+                    shell.aliases.set(name, value.to_string());
+                }
+                Some(_) => {
+                    eprintln!("alias: `{}': invalid alias name", arg);
+                    status = 1;
+                }
+                None => match shell.aliases.get(&arg) {
+                    Some(value) => Self::print(&mut out, &arg, value)?,
+                    None => {
+                        eprintln!("alias: {}: not found", arg);
+                        status = 1;
+                    }
+                },
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Unalias ------------
+*******************************/
+impl UnaliasCommand {
+    pub fn new(options: ParsedCommand) -> Self {
+        UnaliasCommand { options }
+    }
+}
+
+impl Execute for UnaliasCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let args = self.options.args.clone().unwrap_or_default();
+        if args.first().is_some_and(|arg| arg == "-a") {
+            shell.aliases.clear();
+            return Ok(exitcode::OK);
+        }
+        if args.is_empty() {
+            eprintln!("unalias: usage: unalias [-a] name [name ...]");
+            return Ok(exitcode::USAGE);
+        }
+        let mut status = exitcode::OK;
+        for arg in &args {
+            if !shell.aliases.remove(&arg.to_string_lossy()) {
+                eprintln!("unalias: {}: not found", arg.to_string_lossy());
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/*******************************
+ ------------ Source ------------
+*******************************/
+impl SourceCommand {
+    pub fn new_source(options: ParsedCommand) -> Self {
+        SourceCommand {
+            options,
+            name: "source",
+        }
+    }
+
+    pub fn new_dot(options: ParsedCommand) -> Self {
+        SourceCommand { options, name: "." }
+    }
+
+    /// A name without a slash is looked for in PATH first, then in the current directory.
+    fn find(shell: &Shell, file: &OsStr) -> PathBuf {
+        if file.as_bytes().contains(&b'/') {
+            return PathBuf::from(file);
+        }
+        env::split_paths(&shell.search_path())
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| PathBuf::from(file))
+    }
+}
+
+impl Execute for SourceCommand {
+    fn execute(&self, shell: &mut Shell) -> Result<i32, Error> {
+        let mut args = self.options.args.clone().unwrap_or_default();
+        if args.is_empty() {
+            eprintln!("{}: filename argument required", self.name);
+            eprintln!("{}: usage: {} filename [arguments]", self.name, self.name);
+            return Ok(exitcode::USAGE);
+        }
+        let file = args.remove(0);
+        let path = Self::find(shell, &file);
+        let input = match std::fs::read(&path) {
+            Ok(input) => input,
+            Err(err) => {
+                eprintln!(
+                    "{}: {}: {}",
+                    self.name,
+                    file.to_string_lossy(),
+                    crate::strerror(&err)
+                );
+                return Ok(1);
+            }
+        };
+        let arguments = if args.is_empty() { None } else { Some(args) };
+        shell
+            .source(&path, &input, arguments)
+            .map_err(|err| Error::other(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod redirection {
+    use super::*;
+    use crate::testing::ScratchDir;
+    use std::fs;
+
+    #[test]
+    fn group_errors_to_a_file() {
+        let dir = ScratchDir::new("redirect");
+        let err = dir.join("err");
+        let all = dir.join("all");
+        let input = format!(
+            "{{ ls /nonexistent; }} 2> {}\n( echo out; ls /nonexistent ) > {} 2>&1",
+            err.display(),
+            all.display()
+        );
+        let mut shell = Shell::init();
+        shell.run(input.as_bytes()).unwrap();
+
+        let err = fs::read_to_string(&err).unwrap();
+        assert!(err.contains("/nonexistent"), "{:?}", err);
+        let all = fs::read_to_string(&all).unwrap();
+        assert!(
+            all.starts_with("out\n") && all.contains("/nonexistent"),
+            "{:?}",
+            all
+        );
+    }
+
+    #[test]
+    fn made_from_left_to_right() {
+        let dir = ScratchDir::new("order");
+        let out = dir.join("out");
+        let group = dir.join("group");
+        // Errors go where the group's output goes, as the command's own output is only redirected after.
+        let input = format!(
+            "{{ ls /nonexistent 2>&1 > {}; }} > {}",
+            out.display(),
+            group.display()
+        );
+        let mut shell = Shell::init();
+        shell.run(input.as_bytes()).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap(), "");
+        let group = fs::read_to_string(&group).unwrap();
+        assert!(group.contains("/nonexistent"), "{:?}", group);
+    }
+}
+
+#[cfg(test)]
+mod location {
+    use super::*;
+    use crate::testing::ScratchDir;
+    use std::fs;
+
+    #[test]
+    fn runtime_errors_in_files() {
+        let dir = ScratchDir::new("location");
+        let script = dir.join("script");
+        let out = dir.join("out");
+        let text = format!("true\nshelly-nonexistent > {}\n", out.display());
+        fs::write(&script, text).unwrap();
+        let mut shell = Shell::init();
+        shell.run_file(&script).unwrap();
+
+        assert_eq!(shell.last_status, NOT_FOUND);
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            format!(
+                "{}:2: shelly-nonexistent: command not found\n",
+                script.display()
+            )
+        );
+        assert_eq!(shell.location_prefix(), "");
+    }
+}
+
+#[cfg(test)]
+mod assignments {
+    use super::*;
+    use crate::testing::ScratchDir;
+    use std::fs;
+
+    #[test]
+    fn kept_only_after_special_builtins() {
+        let mut shell = Shell::init();
+        shell.run(b"SHELLY_A=1 cd .").unwrap();
+        assert_eq!(shell.parameter("SHELLY_A"), None);
+        shell.run(b"SHELLY_B=1 export SHELLY_C=2").unwrap();
+        assert_eq!(shell.parameter("SHELLY_B"), Some("1".into()));
+        assert_eq!(shell.parameter("SHELLY_C"), Some("2".into()));
+    }
+
+    #[test]
+    fn seen_by_regular_builtins() {
+        let dir = ScratchDir::new("assign");
+        let out = dir.join("out");
+        let input = format!("SHELLY_D=set command env > {}", out.display());
+        let mut shell = Shell::init();
+        shell.run(input.as_bytes()).unwrap();
+        let env = fs::read_to_string(&out).unwrap();
+        assert!(env.lines().any(|line| line == "SHELLY_D=set"), "{:?}", env);
+        assert_eq!(shell.parameter("SHELLY_D"), None);
+    }
+
+    #[test]
+    fn quoted_words_are_commands() {
+        let mut shell = Shell::init();
+        shell.run(b"'SHELLY_E=1'").unwrap();
+        assert_eq!(shell.last_status, NOT_FOUND);
+        assert_eq!(shell.parameter("SHELLY_E"), None);
+    }
+}
+
+#[cfg(test)]
+mod kill {
+    use super::*;
+    use crate::testing::ScratchDir;
+    use std::fs;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn lists_and_translates() {
+        let dir = ScratchDir::new("kill");
+        let out = dir.join("out");
+        let input = format!("kill -l 9 TERM 130 > {}", out.display());
+        let mut shell = Shell::init();
+        shell.run(input.as_bytes()).unwrap();
+        assert_eq!(shell.last_status, exitcode::OK);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "KILL\n15\nINT\n");
+    }
+
+    #[test]
+    fn sends_named_and_numbered_signals() {
+        let mut shell = Shell::init();
+        for (args, signal) in [("-s TERM", libc::SIGTERM), ("-9", libc::SIGKILL)] {
+            let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+            let input = format!("kill {} {}", args, child.id());
+            shell.run(input.as_bytes()).unwrap();
+            assert_eq!(shell.last_status, exitcode::OK);
+            assert_eq!(child.wait().unwrap().signal(), Some(signal));
+        }
+    }
+
+    #[test]
+    fn signal_zero_checks_the_process_exists() {
+        let mut shell = Shell::init();
+        for args in ["-0", "-s 0"] {
+            let input = format!("kill {} {}", args, std::process::id());
+            shell.run(input.as_bytes()).unwrap();
+            assert_eq!(shell.last_status, exitcode::OK);
+        }
+    }
+
+    #[test]
+    fn invalid_signals() {
+        let mut shell = Shell::init();
+        for input in ["kill -s NOPE 1", "kill -NOPE 1", "kill -l NOPE"] {
+            shell.run(input.as_bytes()).unwrap();
+            assert_eq!(shell.last_status, 1, "{}", input);
+        }
+    }
+}
+
+#[cfg(test)]
+mod trap {
+    use super::*;
+
+    #[test]
+    fn uncatchable_signals_are_refused() {
+        let mut shell = Shell::init();
+        for input in ["trap 'echo caught' KILL", "trap 'echo caught' STOP"] {
+            shell.run(input.as_bytes()).unwrap();
+            assert_eq!(shell.last_status, 1, "{}", input);
+        }
+        assert!(shell.traps.iter().next().is_none());
+    }
+}
+
+#[cfg(test)]
+mod wait {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn job_finished_before_waiting() {
+        let mut shell = Shell::init();
+        shell.run(b"sh -c 'exit 7' &").unwrap();
+        thread::sleep(Duration::from_millis(300));
+        // The next line starts by reaping the job.
+        shell.run(b"true").unwrap();
+        shell.run(b"wait $!").unwrap();
+        assert_eq!(shell.last_status, 7);
+        shell.run(b"wait $!").unwrap();
+        assert_eq!(shell.last_status, NOT_FOUND);
+    }
 }
@@ -0,0 +1,355 @@
+use std::cmp::Ordering;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{self, Metadata};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+use crate::parse::syntax::Conditional;
+use crate::pattern;
+use crate::Shell;
+
+/// Operators taking one operand, shared by `test` and `[[ ]]`.
+const UNARY_OPERATORS: [&str; 23] = [
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-n", "-p", "-r", "-s", "-t", "-u", "-w",
+    "-x", "-z", "-G", "-L", "-N", "-O", "-S",
+];
+/// Operators taking two operands. `[[ ]]` reads `=`, `==` and `!=` as pattern matches, and adds `=~`.
+const BINARY_OPERATORS: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+pub fn is_unary_operator(word: &[u8]) -> bool {
+    UNARY_OPERATORS.iter().any(|op| op.as_bytes() == word)
+}
+
+pub fn is_binary_operator(word: &[u8]) -> bool {
+    word == b"=~" || BINARY_OPERATORS.iter().any(|op| op.as_bytes() == word)
+}
+
+fn integer(operand: &OsStr) -> Result<i64, String> {
+    let text = operand.to_string_lossy();
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", text))
+}
+
+/// Whether the current user may access `path` in `mode`, going by the effective ids like `test` should.
+fn accessible(path: &OsStr, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path.as_bytes()) else {
+        return false;
+    };
+    // SAFETY: eaccess only reads the nul-terminated path.
+    unsafe { libc::eaccess(path.as_ptr(), mode) == 0 }
+}
+
+/// Tests a file, or a string for `-n` and `-z`, with a unary operator.
+pub fn unary(operator: &str, operand: &OsStr) -> Result<bool, String> {
+    let metadata = || fs::metadata(operand).ok();
+    let check = |test: fn(&Metadata) -> bool| metadata().is_some_and(|metadata| test(&metadata));
+    Ok(match operator {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-a" | "-e" => metadata().is_some(),
+        "-f" => check(Metadata::is_file),
+        "-d" => check(Metadata::is_dir),
+        "-b" => check(|metadata| metadata.file_type().is_block_device()),
+        "-c" => check(|metadata| metadata.file_type().is_char_device()),
+        "-p" => check(|metadata| metadata.file_type().is_fifo()),
+        "-S" => check(|metadata| metadata.file_type().is_socket()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|metadata| metadata.is_symlink()),
+        "-s" => check(|metadata| metadata.len() > 0),
+        "-g" => check(|metadata| metadata.permissions().mode() & 0o2000 != 0),
+        "-u" => check(|metadata| metadata.permissions().mode() & 0o4000 != 0),
+        "-k" => check(|metadata| metadata.permissions().mode() & 0o1000 != 0),
+        // SAFETY: geteuid and getegid cannot fail.
+        "-O" => check(|metadata| metadata.uid() == unsafe { libc::geteuid() }),
+        "-G" => check(|metadata| metadata.gid() == unsafe { libc::getegid() }),
+        "-N" => check(|metadata| metadata.mtime() > metadata.atime()),
+        "-r" => accessible(operand, libc::R_OK),
+        "-w" => accessible(operand, libc::W_OK),
+        "-x" => accessible(operand, libc::X_OK),
+        "-t" => {
+            let fd = integer(operand)?;
+            // SAFETY: isatty only looks at the descriptor.
+            i32::try_from(fd).is_ok_and(|fd| unsafe { libc::isatty(fd) } == 1)
+        }
+        _ => return Err(format!("{}: unary operator expected", operator)),
+    })
+}
+
+/// Compares two strings, integers or files.
+pub fn binary(left: &OsStr, operator: &str, right: &OsStr) -> Result<bool, String> {
+    let modified = |path: &OsStr| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    Ok(match operator {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left.as_bytes() < right.as_bytes(),
+        ">" => left.as_bytes() > right.as_bytes(),
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            let ordering = integer(left)?.cmp(&integer(right)?);
+            match operator {
+                "-eq" => ordering == Ordering::Equal,
+                "-ne" => ordering != Ordering::Equal,
+                "-lt" => ordering == Ordering::Less,
+                "-le" => ordering != Ordering::Greater,
+                "-gt" => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            }
+        }
+        // A file that exists is newer than one that does not.
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, _) => left.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (_, right) => right.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+            _ => false,
+        },
+        // Only `test` has these, as `[[ ]]` has `&&` and `||`.
+        "-a" => !left.is_empty() && !right.is_empty(),
+        "-o" => !left.is_empty() || !right.is_empty(),
+        _ => return Err(format!("{}: binary operator expected", operator)),
+    })
+}
+
+/**
+Evaluates the arguments of `test` or `[`. Up to four arguments are read the way POSIX lays down,
+so that operands looking like operators are still taken as operands; longer expressions are
+parsed with `!`, `-a`, `-o` and parentheses.
+**/
+pub fn test(args: &[OsString]) -> Result<bool, String> {
+    let is = |arg: &OsString, text: &str| arg.as_bytes() == text.as_bytes();
+    match args {
+        [] => Ok(false),
+        [operand] => Ok(!operand.is_empty()),
+        [not, rest @ ..]
+            if args.len() <= 4
+                && is(not, "!")
+                && !(args.len() == 3 && is_test_binary(&args[1])) =>
+        {
+            test(rest).map(|result| !result)
+        }
+        [operator, operand] => match operator.to_str() {
+            Some(operator) if is_unary_operator(operator.as_bytes()) => unary(operator, operand),
+            _ => Err(format!(
+                "{}: unary operator expected",
+                operator.to_string_lossy()
+            )),
+        },
+        [left, operator, right] if is_test_binary(operator) => {
+            binary(left, &operator.to_string_lossy(), right)
+        }
+        [open, operand, close] if is(open, "(") && is(close, ")") => Ok(!operand.is_empty()),
+        [_, operator, _] => Err(format!(
+            "{}: binary operator expected",
+            operator.to_string_lossy()
+        )),
+        [open, inner @ .., close] if args.len() == 4 && is(open, "(") && is(close, ")") => {
+            test(inner)
+        }
+        _ => {
+            let mut parser = TestParser { args, position: 0 };
+            let result = parser.or()?;
+            match args.get(parser.position) {
+                None => Ok(result),
+                Some(_) => Err("too many arguments".to_string()),
+            }
+        }
+    }
+}
+
+fn is_test_binary(word: &OsString) -> bool {
+    let word = word.as_bytes();
+    word != b"=~" && (is_binary_operator(word) || word == b"-a" || word == b"-o")
+}
+
+/// Reads longer `test` expressions, where `-o` binds more loosely than `-a`, and `-a` than `!`.
+struct TestParser<'a> {
+    args: &'a [OsString],
+    position: usize,
+}
+
+impl<'a> TestParser<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a OsString> {
+        self.args.get(self.position + offset)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek(0)
+            .is_some_and(|arg| arg.as_bytes() == text.as_bytes())
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek_is("-o") {
+            self.position += 1;
+            // Both sides are read, even when the left already decides.
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek_is("-a") {
+            self.position += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek_is("!") {
+            self.position += 1;
+            return self.not().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(first) = self.peek(0) else {
+            return Err("argument expected".to_string());
+        };
+        if let (Some(operator), Some(right)) = (self.peek(1), self.peek(2)) {
+            if is_test_binary(operator) && operator != "-a" && operator != "-o" {
+                self.position += 3;
+                return binary(first, &operator.to_string_lossy(), right);
+            }
+        }
+        if first == "(" {
+            self.position += 1;
+            let result = self.or()?;
+            if !self.peek_is(")") {
+                return Err("`)' expected".to_string());
+            }
+            self.position += 1;
+            return Ok(result);
+        }
+        if let (Some(operator), Some(operand)) = (first.to_str(), self.peek(1)) {
+            if is_unary_operator(operator.as_bytes()) {
+                self.position += 2;
+                return unary(operator, operand);
+            }
+        }
+        self.position += 1;
+        Ok(!first.is_empty())
+    }
+}
+
+/**
+Evaluates the expression of `[[ ]]`. Its words are expanded without being split, and the right of
+`==` and `!=` is a glob pattern, or with `=~` an extended regular expression whose matches go to
+`BASH_REMATCH`. Quoted parts of patterns stand for themselves.
+**/
+pub fn evaluate(shell: &mut Shell, expression: &Conditional) -> Result<bool, String> {
+    Ok(match expression {
+        Conditional::Word(word) => !shell.expand_word(word).is_empty(),
+        Conditional::Not(inner) => !evaluate(shell, inner)?,
+        Conditional::And(left, right) => evaluate(shell, left)? && evaluate(shell, right)?,
+        Conditional::Or(left, right) => evaluate(shell, left)? || evaluate(shell, right)?,
+        Conditional::Unary(operator, word) => unary(operator, &shell.expand_word(word))?,
+        Conditional::Binary(left, operator, right) => {
+            let left = shell.expand_word(left);
+            match operator.as_str() {
+                "=" | "==" | "!=" => {
+                    let pattern =
+                        shell.expand_word(&pattern::quote_specials(right, pattern::GLOB_SPECIALS));
+                    let matched = pattern::matches(pattern.as_bytes(), left.as_bytes());
+                    matched == (operator != "!=")
+                }
+                "=~" => {
+                    let regex =
+                        shell.expand_word(&pattern::quote_specials(right, pattern::REGEX_SPECIALS));
+                    let captures = pattern::regex_captures(regex.as_bytes(), left.as_bytes())
+                        .map_err(|err| err.to_string())?;
+                    let matched = captures.is_some();
+                    shell.rematch = captures
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|capture| OsStr::from_bytes(&capture).to_os_string())
+                        .collect();
+                    matched
+                }
+                _ => binary(&left, operator, &shell.expand_word(right))?,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod expressions {
+    use super::*;
+
+    fn run(args: &str) -> Result<bool, String> {
+        let args: Vec<OsString> = args.split_whitespace().map(OsString::from).collect();
+        test(&args)
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(run(""), Ok(false));
+        assert_eq!(run("x"), Ok(true));
+        assert_eq!(run("-n x"), Ok(true));
+        assert_eq!(run("-z x"), Ok(false));
+        assert_eq!(run("a = a"), Ok(true));
+        assert_eq!(run("a != a"), Ok(false));
+        assert_eq!(run("a < b"), Ok(true));
+        // With three arguments, a binary operator in the middle wins over `!`.
+        assert_eq!(run("! = !"), Ok(true));
+        assert_eq!(run("! -z x"), Ok(true));
+        assert_eq!(run("-n"), Ok(true));
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(run("10 -gt 9"), Ok(true));
+        assert_eq!(run("-3 -le -3"), Ok(true));
+        assert_eq!(run("1 -eq 2"), Ok(false));
+        assert_eq!(
+            run("a -eq 1"),
+            Err("a: integer expression expected".to_string())
+        );
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(run("-d /"), Ok(true));
+        assert_eq!(run("-f /"), Ok(false));
+        assert_eq!(run("-e /nonexistent/file"), Ok(false));
+        assert_eq!(run("/ -ef /."), Ok(true));
+    }
+
+    #[test]
+    fn sockets() {
+        let path = std::env::temp_dir().join(format!("shelly-socket-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let socket = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let test = |operator: &str| test(&[operator.into(), path.clone().into_os_string()]);
+        assert_eq!(test("-S"), Ok(true));
+        assert_eq!(test("-f"), Ok(false));
+        assert!(is_unary_operator(b"-S"));
+        drop(socket);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(test("-S"), Ok(false));
+    }
+
+    #[test]
+    fn longer_expressions() {
+        assert_eq!(run("a = a -a ( b = c -o -n x )"), Ok(true));
+        assert_eq!(run("! a = a -o x"), Ok(true));
+        assert_eq!(run("( a = b ) -a x"), Ok(false));
+        assert_eq!(run("a b c d e"), Err("too many arguments".to_string()));
+        assert_eq!(
+            run("x -q y"),
+            Err("-q: binary operator expected".to_string())
+        );
+    }
+}
@@ -0,0 +1,21 @@
+//! The files the shell keeps between sessions, like the `z` database and the command log, and the clock they are stamped with.
+use std::env;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `$XDG_DATA_HOME/shelly`, falling back to `~/.local/share/shelly`.
+pub fn dir() -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data_home.join("shelly"))
+}
+
+/// The time in seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,182 @@
+use std::io;
+
+/// A key as the terminal sends it, decoded from one or more bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// A control character, by the lowercase letter or symbol it goes with: Ctrl-A is `Ctrl('a')`.
+    Ctrl(char),
+    /// Alt, or Escape pressed before the key, which in vi mode is the key after leaving insert mode.
+    Meta(char),
+    /// Escape on its own, with nothing following soon after.
+    Escape,
+    MetaBackspace,
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    /// An escape sequence that is not bound to anything.
+    Unknown,
+}
+
+const ESCAPE: u8 = 0x1b;
+
+/// Where the bytes of keys come from.
+pub trait Input {
+    /// The next byte, waiting for as long as it takes.
+    fn next(&mut self) -> io::Result<u8>;
+
+    /// The next byte if it comes soon, which tells a lone Escape from the start of an escape sequence.
+    fn next_soon(&mut self) -> io::Result<Option<u8>>;
+}
+
+/// Reads the bytes of one key.
+pub fn read_key(input: &mut impl Input) -> io::Result<Key> {
+    let byte = input.next()?;
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        ESCAPE => match input.next_soon()? {
+            Some(byte) => read_escape(byte, input)?,
+            None => Key::Escape,
+        },
+        0x00 => Key::Ctrl(' '),
+        0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+        0x1c..=0x1f => Key::Ctrl((byte + 0x40) as char),
+        0x80.. => read_utf8(byte, input)?,
+        _ => Key::Char(byte as char),
+    })
+}
+
+/// What follows an escape: a CSI or SS3 sequence for the special keys, or else a key pressed with Meta.
+fn read_escape(byte: u8, input: &mut impl Input) -> io::Result<Key> {
+    Ok(match byte {
+        b'[' => {
+            // Parameters, then a final byte from `@` to `~`.
+            let mut parameters = Vec::new();
+            let last = loop {
+                match input.next()? {
+                    final_byte @ 0x40..=0x7e => break final_byte,
+                    parameter => parameters.push(parameter),
+                }
+            };
+            csi_key(&parameters, last)
+        }
+        b'O' => match input.next()? {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            _ => Key::Unknown,
+        },
+        0x7f | 0x08 => Key::MetaBackspace,
+        0x20..=0x7e => Key::Meta(byte as char),
+        _ => Key::Unknown,
+    })
+}
+
+fn csi_key(parameters: &[u8], last: u8) -> Key {
+    // With a modifier, like `1;5C` for Ctrl-Right, arrows move by words.
+    let modified = parameters.contains(&b';');
+    match (parameters, last) {
+        (_, b'C') if modified => Key::WordRight,
+        (_, b'D') if modified => Key::WordLeft,
+        (_, b'A') => Key::Up,
+        (_, b'B') => Key::Down,
+        (_, b'C') => Key::Right,
+        (_, b'D') => Key::Left,
+        (_, b'H') => Key::Home,
+        (_, b'F') => Key::End,
+        (b"1" | b"7", b'~') => Key::Home,
+        (b"4" | b"8", b'~') => Key::End,
+        (b"3", b'~') => Key::Delete,
+        _ => Key::Unknown,
+    }
+}
+
+/// The rest of a character encoded in UTF-8, from its first byte.
+fn read_utf8(first: u8, input: &mut impl Input) -> io::Result<Key> {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Key::Unknown),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..length {
+        bytes.push(input.next()?);
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| text.chars().next())
+        .map_or(Key::Unknown, Key::Char))
+}
+
+#[cfg(test)]
+mod decoding {
+    use super::*;
+
+    impl<I: Iterator<Item = u8>> Input for I {
+        fn next(&mut self) -> io::Result<u8> {
+            Iterator::next(self).ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+        }
+
+        fn next_soon(&mut self) -> io::Result<Option<u8>> {
+            Ok(Iterator::next(self))
+        }
+    }
+
+    fn keys(input: &[u8]) -> Vec<Key> {
+        let mut bytes = input.iter().copied();
+        let mut keys = Vec::new();
+        while let Ok(key) = read_key(&mut bytes) {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn plain_and_control() {
+        assert_eq!(
+            keys(b"a\x01\x17\x1f\x7f\r"),
+            [
+                Key::Char('a'),
+                Key::Ctrl('a'),
+                Key::Ctrl('w'),
+                Key::Ctrl('_'),
+                Key::Backspace,
+                Key::Enter
+            ]
+        );
+        assert_eq!(keys("é字".as_bytes()), [Key::Char('é'), Key::Char('字')]);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(
+            keys(b"\x1b[D\x1bOH\x1b[4~\x1b[3~\x1b[1;5C\x1bb\x1b\x7f\x1b[99X\x1b"),
+            [
+                Key::Left,
+                Key::Home,
+                Key::End,
+                Key::Delete,
+                Key::WordRight,
+                Key::Meta('b'),
+                Key::MetaBackspace,
+                Key::Unknown,
+                Key::Escape
+            ]
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The text being edited, with the cursor as a byte offset that always falls between graphemes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Line {
+    text: String,
+    cursor: usize,
+}
+
+/// Whether a grapheme is part of a word, for moving and killing by words.
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_alphanumeric)
+}
+
+fn is_blank(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+impl Line {
+    /// A line with the cursor at its end.
+    pub fn new(text: &str) -> Self {
+        Line {
+            text: text.to_string(),
+            cursor: text.len(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Where the grapheme before the cursor starts.
+    fn previous(&self, from: usize) -> usize {
+        self.text[..from]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    /// Where the grapheme after the cursor ends.
+    fn next(&self, from: usize) -> usize {
+        self.text[from..]
+            .graphemes(true)
+            .next()
+            .map_or(from, |grapheme| from + grapheme.len())
+    }
+
+    /// Going back from `from`, skips graphemes until one is `inside`, then skips those.
+    fn back_over(&self, from: usize, inside: fn(&str) -> bool) -> usize {
+        let mut graphemes = self.text[..from].grapheme_indices(true).rev().peekable();
+        while graphemes
+            .next_if(|(_, grapheme)| !inside(grapheme))
+            .is_some()
+        {}
+        while graphemes
+            .next_if(|(_, grapheme)| inside(grapheme))
+            .is_some()
+        {}
+        graphemes
+            .next()
+            .map_or(0, |(index, grapheme)| index + grapheme.len())
+    }
+
+    /// Going forward from `from`, skips graphemes until one is `inside`, then skips those.
+    fn forward_over(&self, from: usize, inside: fn(&str) -> bool) -> usize {
+        let mut graphemes = self.text[from..].grapheme_indices(true).peekable();
+        while graphemes
+            .next_if(|(_, grapheme)| !inside(grapheme))
+            .is_some()
+        {}
+        while graphemes
+            .next_if(|(_, grapheme)| inside(grapheme))
+            .is_some()
+        {}
+        graphemes
+            .next()
+            .map_or(self.text.len(), |(index, _)| from + index)
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.previous(self.cursor);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = self.next(self.cursor);
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// To an offset that falls between graphemes.
+    pub fn move_to(&mut self, cursor: usize) {
+        debug_assert!(self.text.is_char_boundary(cursor));
+        self.cursor = cursor.min(self.text.len());
+    }
+
+    /// To the start of the word before the cursor.
+    pub fn move_word_left(&mut self) {
+        self.cursor = self.back_over(self.cursor, is_word);
+    }
+
+    /// To the end of the word after the cursor.
+    pub fn move_word_right(&mut self) {
+        self.cursor = self.forward_over(self.cursor, is_word);
+    }
+
+    /// Takes out a range of the text, leaving the cursor where it was.
+    pub fn remove(&mut self, range: Range<usize>) -> String {
+        if self.cursor >= range.end {
+            self.cursor -= range.len();
+        } else if self.cursor > range.start {
+            self.cursor = range.start;
+        }
+        self.text.drain(range).collect()
+    }
+
+    pub fn delete_back(&mut self) -> bool {
+        let start = self.previous(self.cursor);
+        !self.remove(start..self.cursor).is_empty()
+    }
+
+    pub fn delete_forward(&mut self) -> bool {
+        let end = self.next(self.cursor);
+        !self.remove(self.cursor..end).is_empty()
+    }
+
+    pub fn kill_to_end(&mut self) -> String {
+        self.remove(self.cursor..self.text.len())
+    }
+
+    pub fn kill_to_start(&mut self) -> String {
+        self.remove(0..self.cursor)
+    }
+
+    /// Ctrl-W: back to the blank before the cursor, whatever the characters in between.
+    pub fn kill_blank_word_back(&mut self) -> String {
+        let start = self.back_over(self.cursor, |grapheme| !is_blank(grapheme));
+        self.remove(start..self.cursor)
+    }
+
+    pub fn kill_word_back(&mut self) -> String {
+        let start = self.back_over(self.cursor, is_word);
+        self.remove(start..self.cursor)
+    }
+
+    pub fn kill_word_forward(&mut self) -> String {
+        let end = self.forward_over(self.cursor, is_word);
+        self.remove(self.cursor..end)
+    }
+
+    /**
+    Swaps the graphemes on either side of the cursor and moves past them. At the end of the line
+    it swaps the last two instead, the way Emacs does. Returns whether there was anything to swap.
+    **/
+    pub fn transpose(&mut self) -> bool {
+        if self.cursor == self.text.len() {
+            self.cursor = self.previous(self.cursor);
+        }
+        let start = self.previous(self.cursor);
+        let end = self.next(self.cursor);
+        if start == self.cursor || end == self.cursor {
+            self.cursor = end;
+            return false;
+        }
+        let swapped = format!(
+            "{}{}",
+            &self.text[self.cursor..end],
+            &self.text[start..self.cursor]
+        );
+        self.text.replace_range(start..end, &swapped);
+        self.cursor = end;
+        true
+    }
+}
+
+#[cfg(test)]
+mod editing {
+    use super::*;
+
+    /// A line with the cursor where `|` is.
+    fn line(text: &str) -> Line {
+        let cursor = text.find('|').expect("a cursor");
+        Line {
+            text: text.replacen('|', "", 1),
+            cursor,
+        }
+    }
+
+    fn shown(line: &Line) -> String {
+        let mut text = line.text.clone();
+        text.insert(line.cursor, '|');
+        text
+    }
+
+    #[test]
+    fn moves_over_graphemes() {
+        // An e with a combining accent, and a family emoji made of several code points.
+        let mut edited = line("ae\u{301}👨‍👩‍👧|");
+        edited.move_left();
+        assert_eq!(shown(&edited), "ae\u{301}|👨‍👩‍👧");
+        edited.move_left();
+        assert_eq!(shown(&edited), "a|e\u{301}👨‍👩‍👧");
+        assert!(edited.delete_forward());
+        assert_eq!(shown(&edited), "a|👨‍👩‍👧");
+        edited.move_end();
+        assert!(edited.delete_back());
+        assert_eq!(shown(&edited), "a|");
+    }
+
+    #[test]
+    fn moves_over_words() {
+        let mut edited = line("echo foo-bar  |baz");
+        edited.move_word_left();
+        assert_eq!(shown(&edited), "echo foo-|bar  baz");
+        edited.move_word_left();
+        assert_eq!(shown(&edited), "echo |foo-bar  baz");
+        edited.move_word_right();
+        assert_eq!(shown(&edited), "echo foo|-bar  baz");
+        edited.move_word_right();
+        edited.move_word_right();
+        assert_eq!(shown(&edited), "echo foo-bar  baz|");
+    }
+
+    #[test]
+    fn kills() {
+        let mut edited = line("ls -l /tmp/x|yz");
+        assert_eq!(edited.kill_word_back(), "x");
+        assert_eq!(edited.kill_blank_word_back(), "/tmp/");
+        assert_eq!(shown(&edited), "ls -l |yz");
+        assert_eq!(edited.kill_to_end(), "yz");
+        assert_eq!(edited.kill_to_start(), "ls -l ");
+        assert!(edited.is_empty());
+        let mut edited = line("a| bc d");
+        assert_eq!(edited.kill_word_forward(), " bc");
+        assert_eq!(shown(&edited), "a| d");
+    }
+
+    #[test]
+    fn transposes() {
+        let mut edited = line("ab|c");
+        assert!(edited.transpose());
+        assert_eq!(shown(&edited), "acb|");
+        assert!(edited.transpose());
+        assert_eq!(shown(&edited), "abc|");
+        let mut edited = line("|ab");
+        assert!(!edited.transpose());
+        assert_eq!(shown(&edited), "a|b");
+    }
+}
@@ -0,0 +1,685 @@
+mod keys;
+mod line;
+mod search;
+mod vi;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::signals;
+use keys::Key;
+use line::Line;
+
+/// How many kills the kill ring keeps, after which the oldest are dropped.
+const KILL_RING_SIZE: usize = 16;
+
+/// How long vi mode waits after Escape for the rest of an escape sequence, in milliseconds.
+const ESCAPE_TIMEOUT: i32 = 50;
+
+/// The keybindings lines are edited with, chosen with `set -o emacs` and `set -o vi`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 2] = [Mode::Emacs, Mode::Vi];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Emacs => "emacs",
+            Mode::Vi => "vi",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Mode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+/// Text taken out with Ctrl-K, Ctrl-U, Ctrl-W and the like, newest last, for Ctrl-Y to put back.
+#[derive(Debug, Default)]
+struct KillRing {
+    entries: Vec<String>,
+    /// The entry the last yank put in, counting back from the newest.
+    yanked: usize,
+}
+
+impl KillRing {
+    /**
+    Adds killed text. Kills straight after one another join up into one entry, in the order the
+    text was on the line, so that `before` says whether this text came before what is there.
+    **/
+    fn add(&mut self, text: String, join: bool, before: bool) {
+        match self.entries.last_mut() {
+            Some(last) if join && before => last.insert_str(0, &text),
+            Some(last) if join => last.push_str(&text),
+            _ => {
+                if self.entries.len() == KILL_RING_SIZE {
+                    self.entries.remove(0);
+                }
+                self.entries.push(text);
+            }
+        }
+    }
+
+    fn yank(&mut self) -> Option<&str> {
+        self.yanked = 0;
+        self.entries.last().map(String::as_str)
+    }
+
+    /// The entry before the one yanked last, going round to the newest after the oldest.
+    fn rotate(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yanked = (self.yanked + 1) % self.entries.len();
+        let index = self.entries.len() - 1 - self.yanked;
+        Some(&self.entries[index])
+    }
+}
+
+/**
+Reads command lines from the terminal with Emacs or vi editing, keeping what was killed from one
+line to the next. When standard input is not a terminal, or editing is turned off, lines are read
+as they come.
+**/
+pub struct Editor {
+    stdin: io::BufReader<signals::Stdin>,
+    mode: Option<Mode>,
+    kill_ring: KillRing,
+    vi: vi::Memory,
+    /// Whether Ctrl-R and Ctrl-S list the closest matches rather than looking for the text typed.
+    fuzzy_search: bool,
+    /// What was searched for last, which searching again with nothing typed looks for.
+    last_search: String,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Editor {
+            stdin: io::BufReader::new(signals::Stdin),
+            mode: Some(Mode::Emacs),
+            kill_ring: KillRing::default(),
+            vi: vi::Memory::default(),
+            fuzzy_search: false,
+            last_search: String::new(),
+        }
+    }
+}
+
+impl Editor {
+    /// The keybindings for the lines read from now on, or `None` to read them without editing.
+    pub fn set_mode(&mut self, mode: Option<Mode>) {
+        self.mode = mode;
+    }
+
+    /// Whether history search matches the characters typed in order, anywhere in an entry, and ranks what it finds.
+    pub fn set_fuzzy_search(&mut self, fuzzy: bool) {
+        self.fuzzy_search = fuzzy;
+    }
+
+    /**
+    Shows `prompt` and appends a line to `buf`, newline included, returning how many bytes were
+    added like `BufRead::read_until` does: none at the end of input. Up and Down go through the
+    `history` given, oldest first. Ctrl-C gives up on the line with `signals::Interrupted`.
+    **/
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        history: &[String],
+        buf: &mut Vec<u8>,
+    ) -> io::Result<usize> {
+        // SAFETY: isatty only looks at the descriptor.
+        let terminal = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        let raw = match (terminal, self.mode) {
+            (true, Some(_)) => RawMode::enable().ok(),
+            _ => None,
+        };
+        if raw.is_none() {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+            return self.stdin.read_until(b'\n', buf);
+        }
+
+        let mut session = Session {
+            editor: self,
+            prompt,
+            line: Line::default(),
+            undo: Vec::new(),
+            last: LastAction::Other,
+            insert: true,
+            pending: VecDeque::new(),
+            recording: None,
+            history,
+            history_index: history.len(),
+            typed: Line::default(),
+            search: None,
+            cursor_row: 0,
+            columns: terminal_columns(),
+        };
+        let result = session.run();
+        drop(raw);
+        match result? {
+            Some(text) => {
+                buf.extend_from_slice(text.as_bytes());
+                buf.push(b'\n');
+                Ok(text.len() + 1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Puts the terminal in raw mode, so that keys come in one at a time without echo, until dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        // SAFETY: tcgetattr fills in the termios, which tcsetattr only reads.
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            // Ctrl-C, Ctrl-S and the rest arrive as keys. Output is still processed, so `\n` starts a new line.
+            raw.c_iflag &= !(libc::ICRNL | libc::IXON | libc::BRKINT | libc::INPCK | libc::ISTRIP);
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawMode { original })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: the settings were read from this terminal.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+fn terminal_columns() -> usize {
+    signals::window_size().map_or(80, |(columns, _)| usize::from(columns))
+}
+
+/// How a grapheme is shown: control characters as `^X`, like the terminal would echo them.
+fn shown(grapheme: &str) -> Cow<'_, str> {
+    match grapheme.as_bytes() {
+        [byte @ (0..=0x1f | 0x7f)] => Cow::Owned(format!("^{}", (byte ^ 0x40) as char)),
+        _ => Cow::Borrowed(grapheme),
+    }
+}
+
+/**
+Where the cursor ends up after writing `text` from `(row, column)` on a terminal `columns` wide.
+A character too wide for what is left of a row goes on the next one, and a row that is filled
+up leaves the cursor at the start of the next.
+**/
+fn advance(text: &str, columns: usize, (mut row, mut column): (usize, usize)) -> (usize, usize) {
+    for grapheme in text.graphemes(true) {
+        let width = shown(grapheme).width();
+        if column + width > columns {
+            row += 1;
+            column = 0;
+        }
+        column += width;
+        if column >= columns {
+            row += 1;
+            column = 0;
+        }
+    }
+    (row, column)
+}
+
+/// What the last key did, for the keys that behave differently straight after certain others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LastAction {
+    Other,
+    /// Typing, which undo takes back all at once.
+    Insert,
+    /// Killing, which joins up with the next kill in the kill ring.
+    Kill,
+    /// Yanking the text between these offsets, which Meta-Y can swap for an older kill.
+    Yank {
+        start: usize,
+        end: usize,
+    },
+}
+
+/// The editing of one line.
+struct Session<'a> {
+    editor: &'a mut Editor,
+    prompt: &'a str,
+    line: Line,
+    /// The line as it was before each change, for undo.
+    undo: Vec<Line>,
+    last: LastAction,
+    /// Whether vi mode is inserting text rather than taking commands, as it does when a line starts.
+    insert: bool,
+    /// Keys to handle before reading any more, which vi mode puts here to repeat a change.
+    pending: VecDeque<Key>,
+    /// The keys of the vi change being made, with its count, as `.` will repeat them.
+    recording: Option<(Option<usize>, Vec<Key>)>,
+    history: &'a [String],
+    /// The history entry being shown, or the length of the history for the line being typed.
+    history_index: usize,
+    /// The line being typed, kept while going through the history.
+    typed: Line,
+    search: Option<search::Search>,
+    /// The row the terminal cursor is on, counted from the row the prompt starts on.
+    cursor_row: usize,
+    /// The width of the terminal when the line was last drawn.
+    columns: usize,
+}
+
+impl<'a> Session<'a> {
+    /// Edits until the line is entered, or `None` at the end of input.
+    fn run(&mut self) -> io::Result<Option<String>> {
+        self.refresh()?;
+        loop {
+            let key = match self.next_key() {
+                Ok(key) => key,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let key = match self.search {
+                Some(_) => match self.search_key(key) {
+                    Some(key) => key,
+                    None => {
+                        self.refresh()?;
+                        continue;
+                    }
+                },
+                None => key,
+            };
+            let last = std::mem::replace(&mut self.last, LastAction::Other);
+            match key {
+                Key::Enter => {
+                    self.line.move_end();
+                    self.refresh()?;
+                    self.write("\r\n")?;
+                    return Ok(Some(self.line.text().to_string()));
+                }
+                Key::Ctrl('d') if self.line.is_empty() => {
+                    self.write("\r\n")?;
+                    return Ok(None);
+                }
+                Key::Ctrl('c') => {
+                    self.line.move_end();
+                    self.refresh()?;
+                    self.write("^C")?;
+                    signals::raise(libc::SIGINT);
+                    return Err(io::Error::other(signals::Interrupted));
+                }
+                Key::Ctrl('x') if self.editor.mode == Some(Mode::Emacs) => {
+                    if self.next_key()? == Key::Ctrl('u') {
+                        self.undo();
+                    }
+                }
+                key if self.editor.mode == Some(Mode::Vi) => self.vi(key, last)?,
+                key => self.edit(key, last),
+            }
+            self.refresh()?;
+        }
+    }
+
+    fn edit(&mut self, key: Key, last: LastAction) {
+        let key = match key {
+            Key::Meta(char) => Key::Meta(char.to_ascii_lowercase()),
+            key => key,
+        };
+        match key {
+            Key::Char(char) => {
+                if last != LastAction::Insert {
+                    self.undo.push(self.line.clone());
+                }
+                self.line.insert(char.encode_utf8(&mut [0; 4]));
+                self.last = LastAction::Insert;
+            }
+            Key::Ctrl('a') | Key::Home => self.line.move_home(),
+            Key::Ctrl('e') | Key::End => self.line.move_end(),
+            Key::Ctrl('b') | Key::Left => self.line.move_left(),
+            Key::Ctrl('f') | Key::Right => self.line.move_right(),
+            Key::Meta('b') | Key::WordLeft => self.line.move_word_left(),
+            Key::Meta('f') | Key::WordRight => self.line.move_word_right(),
+            Key::Ctrl('p') | Key::Up => {
+                self.browse(true);
+            }
+            Key::Ctrl('n') | Key::Down => {
+                self.browse(false);
+            }
+            Key::Ctrl('r') => self.start_search(true),
+            Key::Ctrl('s') => self.start_search(false),
+            Key::Backspace => self.change(Line::delete_back),
+            Key::Ctrl('d') | Key::Delete => self.change(Line::delete_forward),
+            Key::Ctrl('t') => self.change(Line::transpose),
+            Key::Ctrl('k') => self.kill(last, false, Line::kill_to_end),
+            Key::Ctrl('u') => self.kill(last, true, Line::kill_to_start),
+            Key::Ctrl('w') => self.kill(last, true, Line::kill_blank_word_back),
+            Key::MetaBackspace => self.kill(last, true, Line::kill_word_back),
+            Key::Meta('d') => self.kill(last, false, Line::kill_word_forward),
+            Key::Ctrl('y') => self.yank(),
+            Key::Meta('y') => self.yank_pop(last),
+            Key::Ctrl('_') => self.undo(),
+            Key::Ctrl('l') => {
+                // Cleared, the prompt goes back to the top.
+                print!("\x1b[H\x1b[2J");
+                self.cursor_row = 0;
+            }
+            _ => (),
+        }
+    }
+
+    /**
+    Shows the history entry before the one shown, or after it, returning whether there was one.
+    Going past the newest entry comes back to the line being typed.
+    **/
+    fn browse(&mut self, back: bool) -> bool {
+        let index = match back {
+            true => self.history_index.checked_sub(1),
+            false => Some(self.history_index + 1).filter(|&index| index <= self.history.len()),
+        };
+        let Some(index) = index else {
+            return false;
+        };
+        if self.history_index == self.history.len() {
+            self.typed = self.line.clone();
+        }
+        self.history_index = index;
+        self.line = match self.history.get(index) {
+            Some(entry) => Line::new(entry),
+            None => std::mem::take(&mut self.typed),
+        };
+        self.undo.clear();
+        true
+    }
+
+    /// Makes a change that can be undone, if it changes anything.
+    fn change(&mut self, change: fn(&mut Line) -> bool) {
+        let before = self.line.clone();
+        if change(&mut self.line) {
+            self.undo.push(before);
+        }
+    }
+
+    fn kill(&mut self, last: LastAction, before: bool, kill: fn(&mut Line) -> String) {
+        let saved = self.line.clone();
+        let killed = kill(&mut self.line);
+        if killed.is_empty() {
+            return;
+        }
+        self.undo.push(saved);
+        let join = last == LastAction::Kill;
+        self.editor.kill_ring.add(killed, join, before);
+        self.last = LastAction::Kill;
+    }
+
+    fn yank(&mut self) {
+        let Some(text) = self.editor.kill_ring.yank() else {
+            return;
+        };
+        self.undo.push(self.line.clone());
+        let start = self.line.cursor();
+        self.line.insert(text);
+        let end = self.line.cursor();
+        self.last = LastAction::Yank { start, end };
+    }
+
+    /// Swaps the text just yanked for the kill before it.
+    fn yank_pop(&mut self, last: LastAction) {
+        let LastAction::Yank { start, end } = last else {
+            return;
+        };
+        let Some(text) = self.editor.kill_ring.rotate() else {
+            return;
+        };
+        self.line.remove(start..end);
+        self.line.insert(text);
+        let end = self.line.cursor();
+        self.last = LastAction::Yank { start, end };
+    }
+
+    fn undo(&mut self) {
+        if let Some(previous) = self.undo.pop() {
+            self.line = previous;
+        }
+    }
+
+    /// The next key, from those waiting to be handled or else from the terminal.
+    fn next_key(&mut self) -> io::Result<Key> {
+        let key = match self.pending.pop_front() {
+            Some(key) => key,
+            None => keys::read_key(self)?,
+        };
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+        Ok(key)
+    }
+
+    /// The next byte typed, redrawing the line if the terminal is resized in the meantime.
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if self.editor.stdin.buffer().is_empty() {
+            self.wait_for_input()?;
+        }
+        let byte = match self.editor.stdin.fill_buf()? {
+            [] => return Err(io::ErrorKind::UnexpectedEof.into()),
+            [byte, ..] => *byte,
+        };
+        self.editor.stdin.consume(1);
+        Ok(byte)
+    }
+
+    /// Unlike `read`, `poll` is never restarted after a signal, so resizing the window interrupts it.
+    fn wait_for_input(&mut self) -> io::Result<()> {
+        loop {
+            let mut input = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: poll only writes `revents` of the one pollfd given.
+            if unsafe { libc::poll(&mut input, 1, -1) } >= 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+            if signals::is_pending(libc::SIGINT) || signals::is_pending(libc::SIGHUP) {
+                return Err(io::Error::other(signals::Interrupted));
+            }
+            if terminal_columns() != self.columns {
+                self.refresh()?;
+            }
+        }
+    }
+
+    fn write(&self, text: &str) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+
+    /// The prompt, after which vi mode shows whether it is inserting or taking commands.
+    fn prompt(&self) -> Cow<'a, str> {
+        match (self.editor.mode, self.insert) {
+            (Some(Mode::Vi), true) => Cow::Owned(format!("(ins){}", self.prompt)),
+            (Some(Mode::Vi), false) => Cow::Owned(format!("(cmd){}", self.prompt)),
+            _ => Cow::Borrowed(self.prompt),
+        }
+    }
+
+    /// Draws the prompt and the line again, or the search going on, from the row the prompt starts on.
+    fn refresh(&mut self) -> io::Result<()> {
+        self.columns = terminal_columns();
+        let view = match &self.search {
+            Some(search) => search.view(self.history, self.columns),
+            None => View {
+                prompt: self.prompt(),
+                text: self.line.text(),
+                cursor: self.line.cursor(),
+                highlight: Vec::new(),
+                below: Vec::new(),
+            },
+        };
+        let (drawn, cursor_row) = view.render(self.columns);
+        let mut screen = String::new();
+        if self.cursor_row > 0 {
+            write!(screen, "\x1b[{}A", self.cursor_row).expect("writing to a string");
+        }
+        screen.push_str("\r\x1b[J");
+        screen.push_str(&drawn);
+        self.cursor_row = cursor_row;
+        self.write(&screen)
+    }
+}
+
+/// What is drawn for the line: the prompt and text, with the cursor at an offset in the text.
+struct View<'s> {
+    prompt: Cow<'s, str>,
+    text: &'s str,
+    cursor: usize,
+    /// Parts of the text shown in reverse video.
+    highlight: Vec<Range<usize>>,
+    /// Rows shown beneath the text, each narrower than the terminal.
+    below: Vec<String>,
+}
+
+impl View<'_> {
+    /// What to write from the start of the prompt on a terminal `columns` wide, and the row the cursor is left on.
+    fn render(&self, columns: usize) -> (String, usize) {
+        let (before, after) = self.text.split_at(self.cursor);
+        let start = advance(&self.prompt, columns, (0, 0));
+        let cursor = advance(before, columns, start);
+        let mut end = advance(after, columns, cursor);
+
+        let mut screen = self.prompt.to_string();
+        for (offset, grapheme) in self.text.grapheme_indices(true) {
+            match self.highlight.iter().any(|range| range.contains(&offset)) {
+                true => write!(screen, "\x1b[7m{}\x1b[27m", shown(grapheme)),
+                false => write!(screen, "{}", shown(grapheme)),
+            }
+            .expect("writing to a string");
+        }
+        // Filling up the last column leaves the terminal waiting to wrap, until something more is written.
+        if end.1 == 0 && end.0 > 0 {
+            screen.push_str("\r\n");
+        }
+        for row in &self.below {
+            screen.push_str("\r\n");
+            screen.push_str(row);
+            end.0 += 1;
+        }
+        if end.0 > cursor.0 {
+            write!(screen, "\x1b[{}A", end.0 - cursor.0).expect("writing to a string");
+        }
+        screen.push('\r');
+        if cursor.1 > 0 {
+            write!(screen, "\x1b[{}C", cursor.1).expect("writing to a string");
+        }
+        (screen, cursor.0)
+    }
+}
+
+impl keys::Input for Session<'_> {
+    fn next(&mut self) -> io::Result<u8> {
+        self.read_byte()
+    }
+
+    /// Emacs mode waits as long as it takes for the key an Escape goes with; vi mode takes a lone Escape to leave insert mode.
+    fn next_soon(&mut self) -> io::Result<Option<u8>> {
+        if self.editor.mode == Some(Mode::Vi)
+            && self.editor.stdin.buffer().is_empty()
+            && !input_within(ESCAPE_TIMEOUT)
+        {
+            return Ok(None);
+        }
+        self.read_byte().map(Some)
+    }
+}
+
+/// Whether there is input to read within `timeout` milliseconds.
+fn input_within(timeout: i32) -> bool {
+    let mut input = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll only writes `revents` of the one pollfd given.
+    unsafe { libc::poll(&mut input, 1, timeout) > 0 }
+}
+
+#[cfg(test)]
+mod rendering {
+    use super::*;
+
+    #[test]
+    fn wraps_at_the_edge() {
+        assert_eq!(advance("$ abc", 10, (0, 0)), (0, 5));
+        assert_eq!(advance("0123456789", 10, (0, 0)), (1, 0));
+        assert_eq!(advance("0123456789a", 10, (0, 0)), (1, 1));
+        // A wide character that does not fit in the last column goes on the next row.
+        assert_eq!(advance("012345678字", 10, (0, 0)), (1, 2));
+        assert_eq!(advance("e\u{301}字\x01", 10, (0, 0)), (0, 5));
+    }
+
+    #[test]
+    fn highlights_and_rows_below() {
+        let view = View {
+            prompt: Cow::Borrowed("$ "),
+            text: "abcdef",
+            cursor: 1,
+            highlight: vec![1..2, 2..3],
+            below: vec!["> x".to_string()],
+        };
+        assert_eq!(
+            view.render(80),
+            (
+                "$ a\x1b[7mb\x1b[27m\x1b[7mc\x1b[27mdef\r\n> x\x1b[1A\r\x1b[3C".to_string(),
+                0
+            )
+        );
+        let view = View {
+            cursor: 6,
+            highlight: Vec::new(),
+            ..view
+        };
+        // The row the line fills up is left for the cursor, before the rows beneath.
+        assert_eq!(
+            view.render(4),
+            ("$ abcdef\r\n\r\n> x\x1b[1A\r".to_string(), 2)
+        );
+    }
+}
+
+#[cfg(test)]
+mod kill_ring {
+    use super::*;
+
+    #[test]
+    fn kills_in_a_row_join_up() {
+        let mut ring = KillRing::default();
+        ring.add("world".to_string(), false, false);
+        ring.add("hello ".to_string(), true, true);
+        ring.add("x".to_string(), false, false);
+        assert_eq!(ring.yank(), Some("x"));
+        assert_eq!(ring.rotate(), Some("hello world"));
+        assert_eq!(ring.rotate(), Some("x"));
+    }
+}
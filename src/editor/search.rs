@@ -0,0 +1,414 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use super::keys::Key;
+use super::line::Line;
+use super::{shown, Session, View};
+
+/// How many candidates fuzzy search lists beneath the prompt.
+const CANDIDATES: usize = 5;
+
+/// A history entry that matches, with the parts of it that do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Found {
+    index: usize,
+    highlight: Vec<Range<usize>>,
+}
+
+/// An incremental search through the history, started with Ctrl-R or Ctrl-S.
+#[derive(Debug)]
+pub struct Search {
+    back: bool,
+    fuzzy: bool,
+    query: String,
+    found: Option<Found>,
+    /// Whether the query has no match, or none further on than the one found.
+    failed: bool,
+    /// The query and what it found before each character was typed, for Backspace to go back to.
+    typed: Vec<(String, Option<Found>, bool)>,
+    /// The best matches in fuzzy mode, and which of them is chosen.
+    candidates: Vec<Found>,
+    selected: usize,
+    /// The line and history entry shown before, for giving up on the search.
+    original: (Line, usize),
+}
+
+/// The next entry from `from` on, going back or forward, that has `query` in it. Entries the same as `skip` are passed over.
+fn find(
+    history: &[String],
+    query: &str,
+    from: usize,
+    back: bool,
+    skip: Option<&str>,
+) -> Option<Found> {
+    let mut indices: Box<dyn Iterator<Item = usize>> = match back {
+        true => Box::new((0..(from + 1).min(history.len())).rev()),
+        false => Box::new(from..history.len()),
+    };
+    indices.find_map(|index| {
+        let entry = &history[index];
+        if skip == Some(entry) {
+            return None;
+        }
+        let start = match back {
+            true => entry.rfind(query)?,
+            false => entry.find(query)?,
+        };
+        Some(Found {
+            index,
+            highlight: std::iter::once(start..start + query.len()).collect(),
+        })
+    })
+}
+
+/**
+How well `query` matches `entry` as a subsequence, with the offsets of the characters matched, or
+`None` when it does not. Matches score more for characters that follow one another or start words,
+and less for gaps. The query only cares about case when it has capitals in it.
+**/
+fn fuzzy_match(entry: &str, query: &str) -> Option<(i64, Vec<usize>)> {
+    let sensitive = query.chars().any(char::is_uppercase);
+    let same = |a: char, b: char| match sensitive {
+        true => a == b,
+        false => a.to_lowercase().eq(b.to_lowercase()),
+    };
+    let chars: Vec<(usize, char)> = entry.char_indices().collect();
+    let query: Vec<char> = query.chars().collect();
+    let first = *query.first()?;
+
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    'starts: for start in (0..chars.len()).filter(|&start| same(chars[start].1, first)) {
+        let mut positions = vec![start];
+        for &wanted in &query[1..] {
+            let from = positions[positions.len() - 1] + 1;
+            match (from..chars.len()).find(|&index| same(chars[index].1, wanted)) {
+                Some(index) => positions.push(index),
+                // Starting any later cannot match either.
+                None => break 'starts,
+            }
+        }
+        let mut score = 0;
+        for (n, &index) in positions.iter().enumerate() {
+            score += 16;
+            if index == 0 || !chars[index - 1].1.is_alphanumeric() {
+                score += 8;
+            }
+            if n > 0 {
+                match index - positions[n - 1] - 1 {
+                    0 => score += 12,
+                    gap => score -= gap.min(8) as i64,
+                }
+            }
+        }
+        if best.as_ref().map_or(true, |(best, _)| score > *best) {
+            best = Some((score, positions));
+        }
+    }
+    best.map(|(score, positions)| {
+        (
+            score,
+            positions.into_iter().map(|index| chars[index].0).collect(),
+        )
+    })
+}
+
+/**
+The entries `query` matches best as a subsequence, each only once, with the more recent ahead of
+those that match about as well. With no query, the most recent entries.
+**/
+fn fuzzy(history: &[String], query: &str) -> Vec<Found> {
+    let mut seen = HashSet::new();
+    let mut scored: Vec<(i64, Found)> = history
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, entry)| seen.insert(entry.as_str()))
+        .enumerate()
+        .filter_map(|(age, (index, entry))| {
+            let (score, positions) = match query.is_empty() {
+                true => (0, Vec::new()),
+                false => fuzzy_match(entry, query)?,
+            };
+            let highlight = positions
+                .into_iter()
+                .map(|offset| {
+                    let length = entry[offset..].chars().next().map_or(1, char::len_utf8);
+                    offset..offset + length
+                })
+                .collect();
+            // Older entries lose a little, up to what half a matching character is worth.
+            let recency = age.min(32) as i64 / 4;
+            Some((score - recency, Found { index, highlight }))
+        })
+        .collect();
+    // A stable sort, so the more recent of equal scores stay ahead.
+    scored.sort_by_key(|(score, _)| Reverse(*score));
+    scored
+        .into_iter()
+        .take(CANDIDATES)
+        .map(|(_, found)| found)
+        .collect()
+}
+
+/// An entry as it is listed beneath the prompt: on one row at most `columns` wide, with the matched characters highlighted.
+fn candidate(entry: &str, highlight: &[Range<usize>], selected: bool, columns: usize) -> String {
+    let mut row = String::from(if selected { "> " } else { "  " });
+    let mut width = 2;
+    for (offset, grapheme) in entry.grapheme_indices(true) {
+        let grapheme = shown(grapheme);
+        width += grapheme.width();
+        if width >= columns {
+            break;
+        }
+        match highlight.iter().any(|range| range.contains(&offset)) {
+            true => row.push_str(&format!("\x1b[7m{}\x1b[27m", grapheme)),
+            false => row.push_str(&grapheme),
+        }
+    }
+    row
+}
+
+impl Search {
+    /// What is drawn in place of the line while searching.
+    pub(super) fn view<'s>(&'s self, history: &'s [String], columns: usize) -> View<'s> {
+        let kind = match (self.fuzzy, self.back) {
+            (true, _) => "fuzzy-search",
+            (false, true) => "reverse-i-search",
+            (false, false) => "i-search",
+        };
+        let failed = match self.failed {
+            true => "failed ",
+            false => "",
+        };
+        let prompt = Cow::Owned(format!("({}{})`{}': ", failed, kind, self.query));
+        let below = match self.fuzzy {
+            true => self
+                .candidates
+                .iter()
+                .enumerate()
+                .map(|(n, found)| {
+                    let entry = &history[found.index];
+                    candidate(entry, &found.highlight, n == self.selected, columns)
+                })
+                .collect(),
+            false => Vec::new(),
+        };
+        match &self.found {
+            Some(found) => View {
+                prompt,
+                text: &history[found.index],
+                cursor: found.highlight.first().map_or(0, |range| range.start),
+                highlight: found.highlight.clone(),
+                below,
+            },
+            None => View {
+                prompt,
+                text: self.original.0.text(),
+                cursor: self.original.0.cursor(),
+                highlight: Vec::new(),
+                below,
+            },
+        }
+    }
+}
+
+impl Session<'_> {
+    pub(super) fn start_search(&mut self, back: bool) {
+        let mut search = Search {
+            back,
+            fuzzy: self.editor.fuzzy_search,
+            query: String::new(),
+            found: None,
+            failed: false,
+            typed: Vec::new(),
+            candidates: Vec::new(),
+            selected: 0,
+            original: (self.line.clone(), self.history_index),
+        };
+        if search.fuzzy {
+            search.candidates = fuzzy(self.history, "");
+            search.found = search.candidates.first().cloned();
+        }
+        self.search = Some(search);
+    }
+
+    /// Handles a key while searching, returning it when it ends the search and still has to be handled.
+    pub(super) fn search_key(&mut self, key: Key) -> Option<Key> {
+        let history = self.history;
+        let search = self.search.as_mut()?;
+        match key {
+            Key::Char(char) => {
+                let before = (search.query.clone(), search.found.clone(), search.failed);
+                search.typed.push(before);
+                search.query.push(char);
+                self.search_again(false);
+            }
+            Key::Backspace => {
+                if let Some((query, found, failed)) = search.typed.pop() {
+                    search.query = query;
+                    search.found = found;
+                    search.failed = failed;
+                    if search.fuzzy {
+                        search.candidates = fuzzy(history, &search.query);
+                        search.selected = 0;
+                        search.found = search.candidates.first().cloned();
+                    }
+                }
+            }
+            Key::Ctrl(direction @ ('r' | 's')) => {
+                search.back = direction == 'r';
+                // Searching again straight away looks for what was searched for last.
+                if search.query.is_empty() && !search.fuzzy {
+                    search.query = self.editor.last_search.clone();
+                }
+                self.search_again(true);
+            }
+            Key::Up | Key::Down if search.fuzzy => {
+                search.back = key == Key::Up;
+                self.search_again(true);
+            }
+            Key::Ctrl('g') => {
+                let (line, index) = self.search.take()?.original;
+                self.line = line;
+                self.history_index = index;
+            }
+            // Escape only ends the search, rather than leaving vi insert mode as well.
+            Key::Escape => self.finish_search(),
+            key => {
+                self.finish_search();
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    /// Looks for the query from the entry found, or with `further`, past it. Fuzzy search moves down the list or up it instead.
+    fn search_again(&mut self, further: bool) {
+        let history = self.history;
+        let Some(search) = self.search.as_mut() else {
+            return;
+        };
+        if search.fuzzy {
+            match further {
+                true if search.back => {
+                    search.selected =
+                        (search.selected + 1).min(search.candidates.len().saturating_sub(1))
+                }
+                true => search.selected = search.selected.saturating_sub(1),
+                false => {
+                    search.candidates = fuzzy(history, &search.query);
+                    search.selected = 0;
+                }
+            }
+            search.found = search.candidates.get(search.selected).cloned();
+            search.failed = search.found.is_none() && !search.query.is_empty();
+            return;
+        }
+        if search.query.is_empty() {
+            return;
+        }
+
+        let (from, skip) = match &search.found {
+            Some(found) if further => {
+                let from = match search.back {
+                    true => found.index.checked_sub(1),
+                    false => Some(found.index + 1),
+                };
+                (from, Some(history[found.index].as_str()))
+            }
+            Some(found) => (Some(found.index), None),
+            None if search.back => (search.original.1.checked_sub(1), None),
+            None => (Some(search.original.1), None),
+        };
+        // Without a match, what was found last stays shown, with the search marked as failing.
+        match from.and_then(|from| find(history, &search.query, from, search.back, skip)) {
+            Some(found) => {
+                search.found = Some(found);
+                search.failed = false;
+            }
+            None => search.failed = true,
+        }
+    }
+
+    /// Takes the entry found as the line to edit, or the line from before when nothing was.
+    fn finish_search(&mut self) {
+        let Some(search) = self.search.take() else {
+            return;
+        };
+        if !search.query.is_empty() {
+            self.editor.last_search = search.query.clone();
+        }
+        let Some(found) = search.found else {
+            (self.line, self.history_index) = search.original;
+            return;
+        };
+        if search.original.1 == self.history.len() {
+            self.typed = search.original.0;
+        }
+        self.history_index = found.index;
+        self.line = Line::new(&self.history[found.index]);
+        if let (Some(range), false) = (found.highlight.first(), search.fuzzy) {
+            self.line.move_to(range.start);
+        }
+        self.undo.clear();
+    }
+}
+
+#[cfg(test)]
+mod searching {
+    use super::*;
+
+    fn history(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn incremental() {
+        let history = history(&["make test", "git commit", "make", "ls"]);
+        let found = |from, back, skip| find(&history, "ma", from, back, skip).map(|f| f.index);
+        assert_eq!(found(3, true, None), Some(2));
+        assert_eq!(found(1, true, None), Some(0));
+        assert_eq!(found(2, true, Some("make")), Some(0));
+        assert_eq!(found(1, false, None), Some(2));
+        assert_eq!(found(3, false, None), None);
+        assert_eq!(found(9, true, None), Some(2));
+        assert_eq!(
+            find(&history, "t", 3, true, None).map(|f| (f.index, f.highlight[0].clone())),
+            Some((1, 9..10))
+        );
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        let (_, positions) = fuzzy_match("git commit -m", "gcm").unwrap();
+        assert_eq!(positions, [0, 4, 6]);
+        assert!(fuzzy_match("git status", "gcm").is_none());
+        assert!(fuzzy_match("Make", "mk").is_some());
+        assert!(fuzzy_match("make", "Mk").is_none());
+        // Characters together beat the same characters spread out.
+        let together = fuzzy_match("cargo build", "build").unwrap().0;
+        let apart = fuzzy_match("bb uu ii ll dd", "build").unwrap().0;
+        assert!(together > apart);
+    }
+
+    #[test]
+    fn ranked_by_match_then_recency() {
+        let history = history(&["cargo build", "cd build", "cargo build", "cat b"]);
+        let indices = |query| {
+            fuzzy(&history, query)
+                .into_iter()
+                .map(|found| found.index)
+                .collect::<Vec<_>>()
+        };
+        // The repeated entry shows up once, as its most recent, behind closer matches.
+        assert_eq!(indices("cb"), [1, 3, 2]);
+        // Of entries that match as well, the more recent comes first.
+        assert_eq!(indices("build"), [2, 1]);
+        assert_eq!(indices(""), [3, 2, 1]);
+    }
+}
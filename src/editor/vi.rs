@@ -0,0 +1,642 @@
+use std::io;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::keys::Key;
+use super::{LastAction, Session};
+
+/// What vi mode remembers from one line to the next.
+#[derive(Debug, Default)]
+pub struct Memory {
+    /// The last `f`, `F`, `t` or `T`, for `;` and `,` to search again.
+    last_find: Option<Motion>,
+    /// The keys of the last change and the count it was given, for `.` to repeat.
+    last_change: Option<(Option<usize>, Vec<Key>)>,
+}
+
+/// Where the cursor goes for a motion key, which operators take the text up to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    /// `w`, or `W` for words made of anything but blanks.
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+    Start,
+    FirstNonBlank,
+    End,
+    /// `f` and `F`, or `t` and `T` which stop just before the character.
+    Find {
+        target: char,
+        forward: bool,
+        till: bool,
+    },
+}
+
+/// Sorts graphemes into blanks, word characters and punctuation, the runs of which make up vi words.
+fn class(grapheme: &str, big: bool) -> u8 {
+    let first = grapheme.chars().next().unwrap_or(' ');
+    if first.is_whitespace() {
+        0
+    } else if big || first.is_alphanumeric() || first == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+impl Motion {
+    /// Motions that take in the grapheme they land on when an operator goes up to them.
+    fn inclusive(self) -> bool {
+        matches!(
+            self,
+            Motion::WordEnd(_) | Motion::End | Motion::Find { forward: true, .. }
+        )
+    }
+
+    /// The grapheme the motion goes to from `from`, `count` times over, or `None` when it cannot go.
+    fn target(self, graphemes: &[&str], from: usize, count: usize) -> Option<usize> {
+        let length = graphemes.len();
+        let class = |index: usize, big| class(graphemes[index], big);
+        let mut index = from;
+        match self {
+            Motion::Left => return (from > 0).then(|| from.saturating_sub(count)),
+            Motion::Right => {
+                return (from < length).then(|| from.saturating_add(count).min(length))
+            }
+            Motion::WordForward(big) => {
+                for _ in 0..count {
+                    if index < length && class(index, big) != 0 {
+                        let word = class(index, big);
+                        while index < length && class(index, big) == word {
+                            index += 1;
+                        }
+                    }
+                    while index < length && class(index, big) == 0 {
+                        index += 1;
+                    }
+                }
+            }
+            Motion::WordBackward(big) => {
+                for _ in 0..count {
+                    while index > 0 && class(index - 1, big) == 0 {
+                        index -= 1;
+                    }
+                    if index > 0 {
+                        let word = class(index - 1, big);
+                        while index > 0 && class(index - 1, big) == word {
+                            index -= 1;
+                        }
+                    }
+                }
+            }
+            Motion::WordEnd(big) => {
+                for _ in 0..count {
+                    let mut end = index + 1;
+                    while end < length && class(end, big) == 0 {
+                        end += 1;
+                    }
+                    if end >= length {
+                        break;
+                    }
+                    let word = class(end, big);
+                    while end + 1 < length && class(end + 1, big) == word {
+                        end += 1;
+                    }
+                    index = end;
+                }
+            }
+            Motion::Start => index = 0,
+            Motion::FirstNonBlank => {
+                index = (0..length)
+                    .find(|&index| class(index, true) != 0)
+                    .unwrap_or(length.saturating_sub(1))
+            }
+            Motion::End => index = length.saturating_sub(1),
+            Motion::Find {
+                target,
+                forward,
+                till,
+            } => {
+                let target = target.encode_utf8(&mut [0; 4]).to_string();
+                let matches = |index: &usize| graphemes[*index] == target;
+                let found = match forward {
+                    true => (from + 1..length).filter(matches).nth(count - 1)?,
+                    false => (0..from).rev().filter(matches).nth(count - 1)?,
+                };
+                index = match (till, forward) {
+                    (false, _) => found,
+                    (true, true) => found - 1,
+                    (true, false) => found + 1,
+                };
+            }
+        }
+        Some(index)
+    }
+}
+
+/**
+The graphemes a text object takes in around `at`: `w` and `W` for words, quotes, and brackets by
+either of the pair. With `around`, a word takes the blanks after it too, or else those before it,
+and quotes and brackets take themselves in.
+**/
+fn text_object(graphemes: &[&str], at: usize, object: char, around: bool) -> Option<Range<usize>> {
+    let length = graphemes.len();
+    if at >= length {
+        return None;
+    }
+    let (open, close) = match object {
+        'w' | 'W' => {
+            let big = object == 'W';
+            let class = |index: usize| class(graphemes[index], big);
+            let word = class(at);
+            let (mut start, mut end) = (at, at + 1);
+            while start > 0 && class(start - 1) == word {
+                start -= 1;
+            }
+            while end < length && class(end) == word {
+                end += 1;
+            }
+            if around {
+                if word == 0 && end < length {
+                    let next = class(end);
+                    while end < length && class(end) == next {
+                        end += 1;
+                    }
+                } else if end < length && class(end) == 0 {
+                    while end < length && class(end) == 0 {
+                        end += 1;
+                    }
+                } else {
+                    while start > 0 && class(start - 1) == 0 {
+                        start -= 1;
+                    }
+                }
+            }
+            return Some(start..end);
+        }
+        '"' | '\'' | '`' => {
+            // Quotes pair up from the start of the line, the pair around the cursor or else the next one.
+            let quote = object.to_string();
+            let quotes: Vec<usize> = (0..length).filter(|&i| graphemes[i] == quote).collect();
+            let pair = quotes
+                .chunks_exact(2)
+                .find(|pair| pair[1] >= at)
+                .map(|pair| (pair[0], pair[1]))?;
+            return Some(match around {
+                true => pair.0..pair.1 + 1,
+                false => pair.0 + 1..pair.1,
+            });
+        }
+        '(' | ')' | 'b' => ("(", ")"),
+        '[' | ']' => ("[", "]"),
+        '{' | '}' | 'B' => ("{", "}"),
+        '<' | '>' => ("<", ">"),
+        _ => return None,
+    };
+
+    // Back to the bracket still open at the cursor, then on to the one that closes it.
+    let mut depth = 0;
+    let mut start = None;
+    for index in (0..=at).rev() {
+        if graphemes[index] == close && index != at {
+            depth += 1;
+        } else if graphemes[index] == open {
+            if depth == 0 {
+                start = Some(index);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let start = start?;
+    let mut depth = 0;
+    let mut end = None;
+    for (index, &grapheme) in graphemes.iter().enumerate().skip(start + 1) {
+        if grapheme == open {
+            depth += 1;
+        } else if grapheme == close {
+            if depth == 0 {
+                end = Some(index);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let end = end?;
+    Some(match around {
+        true => start..end + 1,
+        false => start + 1..end,
+    })
+}
+
+/// The graphemes of `text`, with the offset each starts at and the length of the text after them.
+fn split(text: &str) -> (Vec<&str>, Vec<usize>) {
+    let (mut offsets, graphemes): (Vec<usize>, Vec<&str>) = text.grapheme_indices(true).unzip();
+    offsets.push(text.len());
+    (graphemes, offsets)
+}
+
+impl Session<'_> {
+    /// Handles a key in vi mode: typed text in insert mode, or else a normal-mode command.
+    pub(super) fn vi(&mut self, key: Key, last: LastAction) -> io::Result<()> {
+        if self.insert {
+            match key {
+                Key::Escape => self.leave_insert(),
+                // Escape and the key after it came too close together to tell from Meta.
+                Key::Meta(char) => {
+                    if let Some((_, keys)) = &mut self.recording {
+                        keys.pop();
+                        keys.push(Key::Escape);
+                    }
+                    self.leave_insert();
+                    self.pending.push_front(Key::Char(char));
+                }
+                key => self.edit(key, last),
+            }
+            return Ok(());
+        }
+
+        let (count, key) = self.count(key)?;
+        self.recording = Some((count, vec![key]));
+        let before = self.line.clone();
+        let change = self.command(key, count.unwrap_or(1))?;
+        if self.insert {
+            // What is typed is undone along with the command that started it.
+            self.undo.push(before);
+            self.last = LastAction::Insert;
+            return Ok(());
+        }
+        let recording = self.recording.take();
+        if change {
+            if self.line != before {
+                self.undo.push(before);
+            }
+            self.editor.vi.last_change = recording;
+        }
+        // Outside insert mode the cursor is on a grapheme, never past the last one.
+        if self.line.cursor() == self.line.text().len() {
+            self.line.move_left();
+        }
+        Ok(())
+    }
+
+    fn leave_insert(&mut self) {
+        self.insert = false;
+        if self.undo.last() == Some(&self.line) {
+            self.undo.pop();
+        }
+        if let Some(recording) = self.recording.take() {
+            self.editor.vi.last_change = Some(recording);
+        }
+        self.line.move_left();
+    }
+
+    /// Reads the digits of a count, if there are any before `key`, and the key after them.
+    fn count(&mut self, mut key: Key) -> io::Result<(Option<usize>, Key)> {
+        let mut count: Option<usize> = None;
+        while let Key::Char(digit @ '0'..='9') = key {
+            // A `0` of its own goes to the start of the line.
+            if digit == '0' && count.is_none() {
+                break;
+            }
+            let digit = digit.to_digit(10).unwrap_or_default() as usize;
+            count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+            key = self.next_key()?;
+        }
+        Ok((count, key))
+    }
+
+    /// Runs a normal-mode command, returning whether it is a change for `.` to repeat.
+    fn command(&mut self, key: Key, count: usize) -> io::Result<bool> {
+        let cursor = self.line.cursor();
+        let end = self.line.text().len();
+        match key {
+            Key::Char(operator @ ('d' | 'c' | 'y')) => {
+                let Some(range) = self.operator_range(operator, count)? else {
+                    return Ok(false);
+                };
+                self.operate(operator, range);
+                return Ok(operator != 'y');
+            }
+            Key::Char('D') => self.operate('d', cursor..end),
+            Key::Char('C') => self.operate('c', cursor..end),
+            Key::Char('Y') => self.operate('y', cursor..end),
+            Key::Char('S') => self.operate('c', 0..end),
+            Key::Char(char @ ('x' | 'X' | 's')) => {
+                let motion = match char {
+                    'X' => Motion::Left,
+                    _ => Motion::Right,
+                };
+                let operator = if char == 's' { 'c' } else { 'd' };
+                match self.motion_range(motion, count) {
+                    Some(range) => self.operate(operator, range),
+                    None if char == 's' => self.insert = true,
+                    None => return Ok(false),
+                }
+            }
+            Key::Char('i') => self.insert = true,
+            Key::Char('a') => {
+                self.line.move_right();
+                self.insert = true;
+            }
+            Key::Char('I') => {
+                self.motion(Motion::FirstNonBlank, 1);
+                self.insert = true;
+            }
+            Key::Char('A') => {
+                self.line.move_end();
+                self.insert = true;
+            }
+            Key::Char(paste @ ('p' | 'P')) => return Ok(self.paste(paste == 'p', count)),
+            Key::Char('r') => {
+                let Key::Char(char) = self.next_key()? else {
+                    return Ok(false);
+                };
+                let (_, offsets) = split(self.line.text());
+                let index = offsets.partition_point(|&offset| offset < cursor);
+                let Some(&end) = offsets.get(index + count) else {
+                    return Ok(false);
+                };
+                self.line.remove(cursor..end);
+                self.line.insert(&char.to_string().repeat(count));
+                self.line.move_left();
+            }
+            Key::Char('~') => {
+                let range = self
+                    .motion_range(Motion::Right, count)
+                    .unwrap_or(cursor..cursor);
+                let toggled: String = self.line.text()[range.clone()]
+                    .chars()
+                    .map(|char| match char.is_uppercase() {
+                        true => char.to_lowercase().to_string(),
+                        false => char.to_uppercase().to_string(),
+                    })
+                    .collect();
+                self.line.remove(range);
+                self.line.insert(&toggled);
+            }
+            Key::Char('k' | '-' | 'j' | '+') | Key::Up | Key::Down => {
+                let back = matches!(key, Key::Char('k' | '-') | Key::Up);
+                for _ in 0..count {
+                    if !self.browse(back) {
+                        break;
+                    }
+                }
+                self.line.move_home();
+                return Ok(false);
+            }
+            Key::Char('u') => {
+                for _ in 0..count {
+                    self.undo();
+                }
+                return Ok(false);
+            }
+            Key::Char('.') => {
+                if let Some((stored, keys)) = self.editor.vi.last_change.clone() {
+                    let digits = count_keys(self.recording_count().or(stored));
+                    for key in digits.into_iter().chain(keys).rev() {
+                        self.pending.push_front(key);
+                    }
+                }
+                return Ok(false);
+            }
+            Key::Ctrl('l' | 'r' | 's') => {
+                self.edit(key, LastAction::Other);
+                return Ok(false);
+            }
+            key => {
+                if let Some(motion) = self.read_motion(key)? {
+                    self.motion(motion, count);
+                }
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The count the command being run was given.
+    fn recording_count(&self) -> Option<usize> {
+        self.recording.as_ref().and_then(|(count, _)| *count)
+    }
+
+    /// The motion a key stands for, reading the character to find for `f`, `F`, `t` and `T`.
+    fn read_motion(&mut self, key: Key) -> io::Result<Option<Motion>> {
+        Ok(Some(match key {
+            Key::Char('h') | Key::Left | Key::Backspace => Motion::Left,
+            Key::Char('l' | ' ') | Key::Right => Motion::Right,
+            Key::Char('w') | Key::WordRight => Motion::WordForward(false),
+            Key::Char('W') => Motion::WordForward(true),
+            Key::Char('b') | Key::WordLeft => Motion::WordBackward(false),
+            Key::Char('B') => Motion::WordBackward(true),
+            Key::Char('e') => Motion::WordEnd(false),
+            Key::Char('E') => Motion::WordEnd(true),
+            Key::Char('0') | Key::Home => Motion::Start,
+            Key::Char('^') => Motion::FirstNonBlank,
+            Key::Char('$') | Key::End => Motion::End,
+            Key::Char(find @ ('f' | 'F' | 't' | 'T')) => {
+                let Key::Char(target) = self.next_key()? else {
+                    return Ok(None);
+                };
+                let motion = Motion::Find {
+                    target,
+                    forward: find.is_lowercase(),
+                    till: find.eq_ignore_ascii_case(&'t'),
+                };
+                self.editor.vi.last_find = Some(motion);
+                motion
+            }
+            Key::Char(repeat @ (';' | ',')) => match self.editor.vi.last_find {
+                Some(Motion::Find {
+                    target,
+                    forward,
+                    till,
+                }) => Motion::Find {
+                    target,
+                    forward: forward == (repeat == ';'),
+                    till,
+                },
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Moves the cursor with a motion, if it can go.
+    fn motion(&mut self, motion: Motion, count: usize) {
+        let (graphemes, offsets) = split(self.line.text());
+        let from = offsets.partition_point(|&offset| offset < self.line.cursor());
+        if let Some(target) = motion.target(&graphemes, from, count) {
+            self.line.move_to(offsets[target]);
+        }
+    }
+
+    /// The text between the cursor and where a motion goes, in bytes.
+    fn motion_range(&self, motion: Motion, count: usize) -> Option<Range<usize>> {
+        let (graphemes, offsets) = split(self.line.text());
+        let from = offsets.partition_point(|&offset| offset < self.line.cursor());
+        let target = motion.target(&graphemes, from, count)?;
+        let end = (from.max(target) + usize::from(motion.inclusive())).min(graphemes.len());
+        let start = from.min(target);
+        (start < end).then(|| offsets[start]..offsets[end])
+    }
+
+    /// Reads what an operator works on: a motion, a text object, or the operator again for the whole line.
+    fn operator_range(&mut self, operator: char, count: usize) -> io::Result<Option<Range<usize>>> {
+        let key = self.next_key()?;
+        let (inner, key) = self.count(key)?;
+        let count = count.saturating_mul(inner.unwrap_or(1));
+        if key == Key::Char(operator) {
+            return Ok(Some(0..self.line.text().len()));
+        }
+        if let Key::Char(kind @ ('i' | 'a')) = key {
+            let Key::Char(object) = self.next_key()? else {
+                return Ok(None);
+            };
+            let (graphemes, offsets) = split(self.line.text());
+            let at = offsets.partition_point(|&offset| offset < self.line.cursor());
+            return Ok(text_object(&graphemes, at, object, kind == 'a')
+                .map(|range| offsets[range.start]..offsets[range.end]));
+        }
+        let Some(motion) = self.read_motion(key)? else {
+            return Ok(None);
+        };
+        if let (Motion::WordForward(big), 'c') = (motion, operator) {
+            // `cw` changes the word the cursor is on without the blanks after it, like `ce` does.
+            let (graphemes, offsets) = split(self.line.text());
+            let from = offsets.partition_point(|&offset| offset < self.line.cursor());
+            if from < graphemes.len() && class(graphemes[from], big) != 0 {
+                let mut end = from;
+                while end + 1 < graphemes.len()
+                    && class(graphemes[end + 1], big) == class(graphemes[from], big)
+                {
+                    end += 1;
+                }
+                if count > 1 {
+                    end = Motion::WordEnd(big)
+                        .target(&graphemes, end, count - 1)
+                        .unwrap_or(end);
+                }
+                return Ok(Some(offsets[from]..offsets[end + 1]));
+            }
+        }
+        Ok(self.motion_range(motion, count))
+    }
+
+    /// Deletes, changes or yanks a range, with what is taken out going to the kill ring.
+    fn operate(&mut self, operator: char, range: Range<usize>) {
+        let text = self.line.text()[range.clone()].to_string();
+        if !text.is_empty() {
+            self.editor.kill_ring.add(text, false, false);
+        }
+        if operator != 'y' {
+            self.line.remove(range.clone());
+        }
+        self.line.move_to(range.start);
+        self.insert = operator == 'c';
+    }
+
+    /// Puts the newest kill after the cursor, or before it, leaving the cursor on its last grapheme.
+    fn paste(&mut self, after: bool, count: usize) -> bool {
+        let Some(text) = self.editor.kill_ring.yank() else {
+            return false;
+        };
+        let text = text.repeat(count);
+        if after {
+            self.line.move_right();
+        }
+        self.line.insert(&text);
+        self.line.move_left();
+        true
+    }
+}
+
+/// The keys typed for a count.
+fn count_keys(count: Option<usize>) -> Vec<Key> {
+    count.map_or_else(Vec::new, |count| {
+        count.to_string().chars().map(Key::Char).collect()
+    })
+}
+
+#[cfg(test)]
+mod motions {
+    use super::*;
+
+    /// The graphemes of `text`, and where the `|` in it is.
+    fn split_at_cursor(text: &str) -> (Vec<&str>, usize) {
+        let at = text.find('|').expect("a cursor");
+        let graphemes: Vec<&str> = text.graphemes(true).filter(|&g| g != "|").collect();
+        (graphemes, at)
+    }
+
+    fn target(text: &str, motion: Motion, count: usize) -> Option<usize> {
+        let (graphemes, at) = split_at_cursor(text);
+        motion.target(&graphemes, at, count)
+    }
+
+    fn object(text: &str, object: char, around: bool) -> Option<String> {
+        let (graphemes, at) = split_at_cursor(text);
+        text_object(&graphemes, at, object, around).map(|range| graphemes[range].concat())
+    }
+
+    #[test]
+    fn words() {
+        let line = "|echo foo.bar  baz";
+        assert_eq!(target(line, Motion::WordForward(false), 1), Some(5));
+        assert_eq!(target(line, Motion::WordForward(false), 3), Some(9));
+        assert_eq!(target(line, Motion::WordForward(true), 2), Some(14));
+        assert_eq!(target(line, Motion::WordForward(true), 9), Some(17));
+        assert_eq!(target(line, Motion::WordEnd(false), 1), Some(3));
+        assert_eq!(target(line, Motion::WordEnd(true), 2), Some(11));
+        assert_eq!(
+            target("echo foo.bar  |baz", Motion::WordBackward(false), 1),
+            Some(9)
+        );
+        assert_eq!(
+            target("echo foo.bar  |baz", Motion::WordBackward(true), 1),
+            Some(5)
+        );
+        assert_eq!(target("|", Motion::WordEnd(false), 1), Some(0));
+    }
+
+    #[test]
+    fn line_and_finds() {
+        assert_eq!(target("  ab|c", Motion::FirstNonBlank, 1), Some(2));
+        assert_eq!(target("  ab|c", Motion::End, 1), Some(4));
+        let find = |target, forward, till| Motion::Find {
+            target,
+            forward,
+            till,
+        };
+        assert_eq!(target("|a,b,c", find(',', true, false), 2), Some(3));
+        assert_eq!(target("|a,b,c", find(',', true, true), 1), Some(0));
+        assert_eq!(target("a,b,|c", find(',', false, true), 1), Some(4));
+        assert_eq!(target("a,b,|c", find('x', false, false), 1), None);
+        assert!(find(',', true, true).inclusive());
+        assert!(!find(',', false, false).inclusive());
+    }
+
+    #[test]
+    fn objects() {
+        assert_eq!(object("echo fo|o bar", 'w', false).as_deref(), Some("foo"));
+        assert_eq!(object("echo fo|o bar", 'w', true).as_deref(), Some("foo "));
+        assert_eq!(object("echo fo|o", 'w', true).as_deref(), Some(" foo"));
+        assert_eq!(object("a \"b |c\" d", '"', false).as_deref(), Some("b c"));
+        assert_eq!(
+            object("a \"b |c\" d", '"', true).as_deref(),
+            Some("\"b c\"")
+        );
+        assert_eq!(object("f(a, (|b), c)", 'b', false).as_deref(), Some("b"));
+        assert_eq!(
+            object("f(a, (b)|, c)", '(', true).as_deref(),
+            Some("(a, (b), c)")
+        );
+        assert_eq!(
+            object("f(a, (b), c|)", ')', false).as_deref(),
+            Some("a, (b), c")
+        );
+        assert_eq!(object("no |brackets", '[', false), None);
+    }
+}
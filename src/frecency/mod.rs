@@ -0,0 +1,263 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use crate::data::{self, now};
+
+/// Once the ranks of all entries add up to more than this, every rank is decayed so the database stays bounded.
+const MAX_TOTAL_RANK: f64 = 9000.0;
+const AGING_FACTOR: f64 = 0.99;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub rank: f64,
+    pub time: u64,
+}
+
+impl Entry {
+    /// Frequency weighted by how recently the directory was visited.
+    pub fn frecency(&self, now: u64) -> f64 {
+        match now.saturating_sub(self.time) {
+            age if age < HOUR => self.rank * 4.0,
+            age if age < DAY => self.rank * 2.0,
+            age if age < WEEK => self.rank / 2.0,
+            _ => self.rank / 4.0,
+        }
+    }
+}
+
+/// The directories visited with `cd`, stored one per line as `path|rank|time`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Database {
+    entries: Vec<Entry>,
+}
+
+impl Database {
+    /// `z` in the data directory, like `~/.local/share/shelly/z`.
+    pub fn location() -> Option<PathBuf> {
+        Some(data::dir()?.join("z"))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /**
+    Lines that cannot be understood are dropped rather than failing the whole database.
+    Paths are kept as raw bytes, since directory names need not be UTF-8.
+    **/
+    pub fn parse(contents: impl AsRef<[u8]>) -> Self {
+        let entries = contents
+            .as_ref()
+            .split(|&b| b == b'\n')
+            .filter_map(|line| {
+                // Split from the right, since the path itself may contain '|'.
+                let mut fields = line.rsplitn(3, |&b| b == b'|');
+                let time = str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+                let rank = str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+                let path = fields.next().filter(|path| !path.is_empty())?;
+                Some(Entry {
+                    path: PathBuf::from(OsStr::from_bytes(path)),
+                    rank,
+                    time,
+                })
+            })
+            .collect();
+        Database { entries }
+    }
+
+    /// Writes to a temporary file first and renames it into place, so a concurrent shell never reads half a database.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let mut file = fs::File::create(&tmp)?;
+        for entry in &self.entries {
+            file.write_all(entry.path.as_os_str().as_bytes())?;
+            writeln!(file, "|{}|{}", entry.rank, entry.time)?;
+        }
+        drop(file);
+        fs::rename(&tmp, path)
+    }
+
+    pub fn add(&mut self, dir: &Path, now: u64) {
+        match self.entries.iter_mut().find(|entry| entry.path == dir) {
+            Some(entry) => {
+                entry.rank += 1.0;
+                entry.time = now;
+            }
+            None => self.entries.push(Entry {
+                path: dir.to_path_buf(),
+                rank: 1.0,
+                time: now,
+            }),
+        }
+        self.age();
+    }
+
+    /// Returns whether the directory was in the database.
+    pub fn remove(&mut self, dir: &Path) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.path != dir);
+        self.entries.len() != before
+    }
+
+    fn age(&mut self) {
+        let total: f64 = self.entries.iter().map(|entry| entry.rank).sum();
+        if total > MAX_TOTAL_RANK {
+            for entry in self.entries.iter_mut() {
+                entry.rank *= AGING_FACTOR;
+            }
+            self.entries.retain(|entry| entry.rank >= 1.0);
+        }
+    }
+
+    /**
+    All entries whose path contains every keyword, in order, sorted from best to worst.
+    Matching is case-sensitive, unless that finds nothing, in which case it is retried case-insensitively.
+    **/
+    pub fn matches(&self, keywords: &[String], now: u64) -> Vec<&Entry> {
+        let mut found = self.matching(keywords, false);
+        if found.is_empty() {
+            found = self.matching(keywords, true);
+        }
+        found.sort_by(|a, b| b.frecency(now).total_cmp(&a.frecency(now)));
+        found
+    }
+
+    fn matching(&self, keywords: &[String], ignore_case: bool) -> Vec<&Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                let path = entry.path.to_string_lossy();
+                if ignore_case {
+                    let keywords: Vec<String> = keywords.iter().map(|k| k.to_lowercase()).collect();
+                    contains_in_order(&path.to_lowercase(), &keywords)
+                } else {
+                    contains_in_order(&path, keywords)
+                }
+            })
+            .collect()
+    }
+}
+
+fn contains_in_order(haystack: &str, keywords: &[String]) -> bool {
+    let mut rest = haystack;
+    for keyword in keywords {
+        match rest.find(keyword.as_str()) {
+            Some(index) => rest = &rest[index + keyword.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Records a visit to `dir`. Failing to update the database should never make a `cd` fail, so errors are ignored.
+pub fn record(dir: &Path) {
+    if let Some(location) = Database::location() {
+        if let Ok(mut database) = Database::load(&location) {
+            database.add(dir, now());
+            let _ = database.save(&location);
+        }
+    }
+}
+
+#[cfg(test)]
+mod database {
+    use super::*;
+
+    #[test]
+    fn parse_skips_broken_lines() {
+        let database = Database::parse("/tmp|2|100\ngarbage\n/a|b|c|3.5|200\n");
+        let expected = Database {
+            entries: vec![
+                Entry {
+                    path: PathBuf::from("/tmp"),
+                    rank: 2.0,
+                    time: 100,
+                },
+                Entry {
+                    path: PathBuf::from("/a|b|c"),
+                    rank: 3.5,
+                    time: 200,
+                },
+            ],
+        };
+        assert_eq!(database, expected);
+    }
+
+    #[test]
+    fn non_utf8_paths_round_trip() {
+        let database = Database::parse(b"/tmp/caf\xe9|1|100\n");
+        let expected = PathBuf::from(OsStr::from_bytes(b"/tmp/caf\xe9"));
+        assert_eq!(database.entries[0].path, expected);
+    }
+
+    #[test]
+    fn add_existing_bumps_rank_and_time() {
+        let mut database = Database::parse("/tmp|2|100\n");
+        database.add(Path::new("/tmp"), 500);
+        assert_eq!(database.entries[0].rank, 3.0);
+        assert_eq!(database.entries[0].time, 500);
+    }
+
+    #[test]
+    fn aging_drops_rarely_used_entries() {
+        let mut database = Database::parse("/big|9000|100\n/small|1|100\n");
+        database.add(Path::new("/big"), 100);
+        let paths: Vec<&Path> = database.entries.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("/big")]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut database = Database::parse("/tmp|2|100\n/home|2|100\n");
+        assert!(database.remove(Path::new("/tmp")));
+        assert!(!database.remove(Path::new("/tmp")));
+        assert_eq!(database.entries.len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod matching {
+    use super::*;
+
+    fn keywords(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn all_keywords_in_order() {
+        let database = Database::parse("/home/foo/bar|1|0\n/home/bar/foo|1|0\n");
+        let best = database.matches(&keywords(&["foo", "bar"]), 0)[0];
+        assert_eq!(best.path, PathBuf::from("/home/foo/bar"));
+    }
+
+    #[test]
+    fn recent_beats_frequent() {
+        let now = 10 * WEEK;
+        let database = Database::parse(format!("/src/old|10|0\n/src/new|3|{}\n", now - 10));
+        let best = database.matches(&keywords(&["src"]), now)[0];
+        assert_eq!(best.path, PathBuf::from("/src/new"));
+    }
+
+    #[test]
+    fn falls_back_to_case_insensitive() {
+        let database = Database::parse("/home/Projects|1|0\n");
+        assert_eq!(database.matches(&keywords(&["proj"]), 0).len(), 1);
+        assert!(database.matches(&keywords(&["nope"]), 0).is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashEntry {
+    pub path: PathBuf,
+    /// How many times the command has been executed through this entry.
+    pub hits: usize,
+    /// Set with `hash -p`; such entries survive invalidation since they do not come from PATH.
+    pinned: bool,
+}
+
+/**
+Remembers where commands were found in PATH, so each is only searched for once.
+Commands are looked up lazily, and the table forgets everything it learned from PATH
+when PATH itself changes or when a directory in it is modified.
+**/
+#[derive(Debug, Default)]
+pub struct CommandTable {
+    entries: BTreeMap<OsString, HashEntry>,
+    /// The PATH the entries were found with.
+    path: OsString,
+    /// Modification times of the PATH directories when the entries were found.
+    mtimes: Vec<Option<SystemTime>>,
+    /// PATH directories we could not search, so each is only complained about once.
+    unreadable: HashSet<PathBuf>,
+}
+
+impl CommandTable {
+    /// Where `name` is found in `path`, consulting the table first.
+    pub fn lookup(&mut self, name: &OsStr, path: &OsStr) -> Option<PathBuf> {
+        self.invalidate_if_stale(path);
+        match self.entries.get(name) {
+            Some(entry) if entry.pinned || is_command(&entry.path) => Some(entry.path.clone()),
+            _ => {
+                let found = self.search(name, path)?;
+                // Found through an empty or relative PATH entry, so it depends on the working directory.
+                if found.is_relative() {
+                    return Some(found);
+                }
+                self.entries.insert(
+                    name.to_os_string(),
+                    HashEntry {
+                        path: found.clone(),
+                        hits: 0,
+                        pinned: false,
+                    },
+                );
+                Some(found)
+            }
+        }
+    }
+
+    pub fn record_hit(&mut self, name: &OsStr) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.hits += 1;
+        }
+    }
+
+    pub fn get(&self, name: &OsStr) -> Option<&HashEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&OsString, &HashEntry)> {
+        self.entries.iter()
+    }
+
+    pub fn pin(&mut self, name: &OsStr, path: PathBuf) {
+        self.entries.insert(
+            name.to_os_string(),
+            HashEntry {
+                path,
+                hits: 0,
+                pinned: true,
+            },
+        );
+    }
+
+    /// Returns whether the command was in the table.
+    pub fn remove(&mut self, name: &OsStr) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn invalidate_if_stale(&mut self, path: &OsStr) {
+        let mtimes = directory_mtimes(path);
+        if self.path != path || self.mtimes != mtimes {
+            self.entries.retain(|_, entry| entry.pinned);
+            self.path = path.to_os_string();
+            self.mtimes = mtimes;
+            self.unreadable.clear();
+        }
+    }
+
+    /// The first executable file called `name` in the directories of `path`, in order, without remembering it.
+    pub fn search(&mut self, name: &OsStr, path: &OsStr) -> Option<PathBuf> {
+        for dir in search_dirs(path) {
+            let candidate = dir.join(name);
+            match fs::metadata(&candidate) {
+                Ok(metadata) if metadata.is_file() && is_executable(&metadata) => {
+                    return Some(candidate)
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::PermissionDenied
+                        && self.unreadable.insert(dir.clone()) =>
+                {
+                    eprintln!("shelly: {}: Permission denied", dir.display());
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+/// The directories of `path`, where an empty entry means the current directory.
+fn search_dirs(path: &OsStr) -> impl Iterator<Item = PathBuf> + '_ {
+    env::split_paths(path).map(|dir| {
+        if dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir
+        }
+    })
+}
+
+fn directory_mtimes(path: &OsStr) -> Vec<Option<SystemTime>> {
+    search_dirs(path)
+        .map(|dir| fs::metadata(dir).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Whether `path` is, after following symlinks, a file we may execute.
+pub fn is_command(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && is_executable(&metadata))
+}
+
+/**
+Whether the execute bit that applies to us is set: the owner's if we own the file,
+the group's if we are in its group, and otherwise everyone else's. Root may execute anything
+with at least one execute bit.
+**/
+pub fn is_executable(metadata: &Metadata) -> bool {
+    let mode = metadata.mode();
+    // SAFETY: these calls cannot fail and only read the credentials of this process.
+    let euid = unsafe { libc::geteuid() };
+    if euid == 0 {
+        mode & 0o111 != 0
+    } else if metadata.uid() == euid {
+        mode & 0o100 != 0
+    } else if in_group(metadata.gid()) {
+        mode & 0o010 != 0
+    } else {
+        mode & 0o001 != 0
+    }
+}
+
+fn in_group(gid: libc::gid_t) -> bool {
+    // SAFETY: getgroups is first asked how many groups there are, and then given a buffer of that size.
+    unsafe {
+        if libc::getegid() == gid {
+            return true;
+        }
+        let count = libc::getgroups(0, std::ptr::null_mut());
+        if count <= 0 {
+            return false;
+        }
+        let mut groups = vec![0; count as usize];
+        let count = libc::getgroups(count, groups.as_mut_ptr());
+        groups.truncate(count.max(0) as usize);
+        groups.contains(&gid)
+    }
+}
+
+#[cfg(test)]
+mod command_table {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A fresh PATH directory for a single test.
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shelly-hash-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_executable(path: &Path) {
+        fs::write(path, "").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn finds_and_remembers() {
+        let dir = test_dir("remembers");
+        write_executable(&dir.join("tool"));
+        let mut table = CommandTable::default();
+
+        let found = table.lookup(OsStr::new("tool"), dir.as_os_str());
+        assert_eq!(found, Some(dir.join("tool")));
+        assert!(table.get(OsStr::new("tool")).is_some());
+        assert_eq!(table.lookup(OsStr::new("missing"), dir.as_os_str()), None);
+    }
+
+    #[test]
+    fn forgets_when_path_changes() {
+        let first = test_dir("path-first");
+        let second = test_dir("path-second");
+        write_executable(&first.join("tool"));
+        let mut table = CommandTable::default();
+        table.lookup(OsStr::new("tool"), first.as_os_str());
+        table.record_hit(OsStr::new("tool"));
+
+        assert_eq!(table.lookup(OsStr::new("tool"), second.as_os_str()), None);
+        assert!(table.get(OsStr::new("tool")).is_none());
+    }
+
+    #[test]
+    fn earlier_directories_win() {
+        let first = test_dir("order-first");
+        let second = test_dir("order-second");
+        for dir in [&first, &second] {
+            write_executable(&dir.join("tool"));
+        }
+        let path = env::join_paths([&second, &first]).unwrap();
+        let mut table = CommandTable::default();
+
+        assert_eq!(
+            table.lookup(OsStr::new("tool"), &path),
+            Some(second.join("tool"))
+        );
+    }
+
+    #[test]
+    fn skips_files_without_execute_bit() {
+        let first = test_dir("noexec-first");
+        let second = test_dir("noexec-second");
+        fs::write(first.join("tool"), "").unwrap();
+        fs::set_permissions(first.join("tool"), fs::Permissions::from_mode(0o644)).unwrap();
+        write_executable(&second.join("tool"));
+        let path = env::join_paths([&first, &second]).unwrap();
+        let mut table = CommandTable::default();
+
+        assert_eq!(
+            table.lookup(OsStr::new("tool"), &path),
+            Some(second.join("tool"))
+        );
+    }
+
+    #[test]
+    fn follows_symlinks() {
+        let dir = test_dir("symlink");
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+        let mut table = CommandTable::default();
+        assert_eq!(table.lookup(OsStr::new("link"), dir.as_os_str()), None);
+
+        write_executable(&dir.join("real"));
+        assert_eq!(
+            table.lookup(OsStr::new("link"), dir.as_os_str()),
+            Some(dir.join("link"))
+        );
+    }
+
+    #[test]
+    fn pinned_entries_survive_invalidation() {
+        let dir = test_dir("pinned");
+        let mut table = CommandTable::default();
+        table.pin(OsStr::new("tool"), PathBuf::from("/somewhere/tool"));
+
+        let found = table.lookup(OsStr::new("tool"), dir.as_os_str());
+        assert_eq!(found, Some(PathBuf::from("/somewhere/tool")));
+    }
+}
@@ -0,0 +1,293 @@
+pub mod record;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use crate::data;
+
+/// How many entries are kept when `HISTSIZE` is not set.
+pub const DEFAULT_SIZE: usize = 500;
+
+/**
+The command lines entered, oldest first. They are numbered for `history` from `base`, which goes
+up as the oldest are dropped. Entries from `saved` on are not in the history file yet.
+**/
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Vec<String>,
+    base: usize,
+    saved: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: Vec::new(),
+            base: 1,
+            saved: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// The number of the oldest entry.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn add(&mut self, line: &str) {
+        self.entries.push(line.to_string());
+    }
+
+    /// Drops the oldest entries beyond `limit`, where `None` is no limit.
+    pub fn truncate(&mut self, limit: Option<usize>) {
+        let Some(limit) = limit else {
+            return;
+        };
+        let excess = self.entries.len().saturating_sub(limit);
+        self.entries.drain(..excess);
+        self.base += excess;
+        self.saved = self.saved.saturating_sub(excess);
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+
+    /// Removes the entry with this number, returning whether there was one.
+    pub fn remove(&mut self, number: usize) -> bool {
+        let Some(index) = number
+            .checked_sub(self.base)
+            .filter(|&index| index < self.entries.len())
+        else {
+            return false;
+        };
+        self.entries.remove(index);
+        if index < self.saved {
+            self.saved -= 1;
+        }
+        true
+    }
+
+    /// Adds entries read from the history file, ahead of those that are not in it yet.
+    pub fn extend_saved(&mut self, entries: Vec<String>) {
+        let count = entries.len();
+        self.entries.splice(self.saved..self.saved, entries);
+        self.saved += count;
+    }
+
+    /// The entries not in the history file yet.
+    pub fn unsaved(&self) -> &[String] {
+        &self.entries[self.saved..]
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = self.entries.len();
+    }
+}
+
+/// Whether a line of a history file is the `#time` that starts an entry.
+fn is_marker(line: &str) -> bool {
+    let digits = line
+        .trim_end_matches('\n')
+        .strip_prefix('#')
+        .unwrap_or_default();
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/**
+Splits a history file into the text of its entries, markers included. An entry starts at a `#time`
+marker and takes in the lines up to the next one, so commands that go over several lines stay whole.
+Lines outside of marked entries, as in a file written by something else, are an entry each.
+**/
+fn records(contents: &str) -> Vec<&str> {
+    let mut records = Vec::new();
+    let mut start = 0;
+    let mut marked = false;
+    let mut offset = 0;
+    for line in contents.split_inclusive('\n') {
+        if offset > start && (is_marker(line) || !marked) {
+            records.push(&contents[start..offset]);
+            start = offset;
+        }
+        if offset == start {
+            marked = is_marker(line);
+        }
+        offset += line.len();
+    }
+    if offset > start {
+        records.push(&contents[start..offset]);
+    }
+    records
+}
+
+/// The text of an entry in a history file, without its marker and last newline.
+fn text(record: &str) -> &str {
+    let text = match record.split_once('\n') {
+        Some((first, rest)) if is_marker(first) => rest,
+        None if is_marker(record) => "",
+        _ => record,
+    };
+    text.strip_suffix('\n').unwrap_or(text)
+}
+
+/// The entries in the contents of a history file.
+pub fn parse(contents: &str) -> Vec<String> {
+    records(contents)
+        .into_iter()
+        .map(text)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<String>> {
+    let contents = std::fs::read(path)?;
+    Ok(parse(&String::from_utf8_lossy(&contents)))
+}
+
+/// Waits until no other shell has the file locked, then holds the lock until the file is closed.
+fn lock(file: &File) -> io::Result<()> {
+    loop {
+        // SAFETY: flock only takes the descriptor, which stays open for as long as `file` does.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// The entries as they go in the file, each after a marker with the time it was written.
+fn format(entries: &[String]) -> String {
+    let now = data::now();
+    entries
+        .iter()
+        .map(|entry| format!("#{}\n{}\n", now, entry))
+        .collect()
+}
+
+/// Keeps the newest `limit` entries of a locked file.
+fn truncate_file(file: &mut File, limit: Option<usize>) -> io::Result<()> {
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let records = records(&contents);
+    if records.len() <= limit {
+        return Ok(());
+    }
+    let kept = records[records.len() - limit..].concat();
+    file.set_len(0)?;
+    file.write_all(kept.as_bytes())
+}
+
+/**
+Adds entries to the end of a history file, then drops its oldest entries beyond `limit`. The file
+is locked meanwhile, so that shells saving their history at the same time take turns.
+**/
+pub fn append(path: &Path, entries: &[String], limit: Option<usize>) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    lock(&file)?;
+    file.write_all(format(entries).as_bytes())?;
+    truncate_file(&mut file, limit)
+}
+
+/// Replaces the contents of a history file with the newest `limit` entries.
+pub fn write(path: &Path, entries: &[String], limit: Option<usize>) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    lock(&file)?;
+    file.set_len(0)?;
+    let skipped = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    file.write_all(format(&entries[skipped..]).as_bytes())
+}
+
+#[cfg(test)]
+mod entries {
+    use super::*;
+
+    fn history(entries: &[&str]) -> History {
+        let mut history = History::default();
+        for entry in entries {
+            history.add(entry);
+        }
+        history
+    }
+
+    #[test]
+    fn numbered_from_the_oldest_kept() {
+        let mut history = history(&["a", "b", "c", "d"]);
+        history.truncate(Some(3));
+        assert_eq!(history.entries(), ["b", "c", "d"]);
+        assert_eq!(history.base(), 2);
+        assert!(history.remove(3));
+        assert!(!history.remove(1));
+        assert!(!history.remove(4));
+        assert_eq!(history.entries(), ["b", "d"]);
+        history.truncate(None);
+        assert_eq!(history.entries().len(), 2);
+    }
+
+    #[test]
+    fn saved_and_unsaved() {
+        let mut history = History::default();
+        history.extend_saved(vec!["old".to_string()]);
+        history.add("new");
+        assert_eq!(history.unsaved(), ["new"]);
+        history.extend_saved(vec!["read".to_string()]);
+        assert_eq!(history.entries(), ["old", "read", "new"]);
+        assert_eq!(history.unsaved(), ["new"]);
+        history.mark_saved();
+        assert!(history.unsaved().is_empty());
+        history.add("newer");
+        history.truncate(Some(1));
+        assert_eq!(history.unsaved(), ["newer"]);
+    }
+
+    #[test]
+    fn parses_plain_and_marked_files() {
+        assert_eq!(parse("ls\ncd /tmp\n"), ["ls", "cd /tmp"]);
+        assert_eq!(
+            parse("#1\nif true\nthen echo\nfi\n#2\nls\n#3\n"),
+            ["if true\nthen echo\nfi", "ls"]
+        );
+        assert_eq!(parse("plain\n#12\nmarked\n#x"), ["plain", "marked\n#x"]);
+    }
+
+    #[test]
+    fn appends_and_limits_the_file() {
+        let path = std::env::temp_dir().join(format!("shelly-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let lines = |lines: &[&str]| {
+            lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+        };
+        append(&path, &lines(&["a", "b\nc"]), None).unwrap();
+        append(&path, &lines(&["d"]), Some(2)).unwrap();
+        assert_eq!(read(&path).unwrap(), ["b\nc", "d"]);
+        write(&path, &lines(&["x", "y", "z"]), Some(1)).unwrap();
+        assert_eq!(read(&path).unwrap(), ["z"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt::{self, Write as _};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::data;
+
+/// A command line run at the prompt, with when, where and how it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// When it started, in seconds since the epoch.
+    pub time: u64,
+    /// The shell it ran in, which tells apart the sessions going on at once.
+    pub session: String,
+    pub host: String,
+    pub cwd: String,
+    pub status: i32,
+    /// How long it took, in seconds.
+    pub duration: f64,
+    pub command: String,
+}
+
+/// A command line that has started running, to be recorded once it finishes.
+#[derive(Debug)]
+pub struct Started {
+    command: String,
+    time: u64,
+    cwd: String,
+    at: Instant,
+}
+
+impl Started {
+    pub fn new(command: &str) -> Self {
+        Started {
+            command: command.to_string(),
+            time: data::now(),
+            cwd: std::env::current_dir()
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            at: Instant::now(),
+        }
+    }
+
+    pub fn finish(self, status: i32, session: &str) -> Record {
+        Record {
+            time: self.time,
+            session: session.to_string(),
+            host: hostname(),
+            cwd: self.cwd,
+            status,
+            duration: self.at.elapsed().as_secs_f64(),
+            command: self.command,
+        }
+    }
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: gethostname writes no more than the length it is given.
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return String::new();
+    }
+    let length = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..length]).into_owned()
+}
+
+/// A time as `2024-05-01 14:03:12` in the local time zone.
+fn local_time(time: u64) -> String {
+    let seconds = time as libc::time_t;
+    // SAFETY: localtime_r only fills in the tm it is given.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut tm) }.is_null() {
+        return time.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:>3}  {:>8}  {}  {}",
+            local_time(self.time),
+            self.status,
+            format!("{:.2}s", self.duration),
+            self.cwd,
+            self.command
+        )
+    }
+}
+
+/// A JSON string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for char in text.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0'..='\u{1f}' | '\u{7f}' => {
+                write!(quoted, "\\u{:04x}", u32::from(char)).expect("writing to a string")
+            }
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Record {
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"time":{},"session":{},"host":{},"cwd":{},"status":{},"duration":{:.3},"command":{}}}"#,
+            self.time,
+            quote(&self.session),
+            quote(&self.host),
+            quote(&self.cwd),
+            self.status,
+            self.duration,
+            quote(&self.command)
+        )
+    }
+
+    /// The line the record is kept as in the log: its fields split by tabs, in the order they are declared.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{:.3}\t{}",
+            self.time,
+            escape(&self.session),
+            escape(&self.host),
+            escape(&self.cwd),
+            self.status,
+            self.duration,
+            escape(&self.command)
+        )
+    }
+
+    /// Reads a record back from its line in the log, or `None` when the line is not one.
+    pub fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let record = Record {
+            time: fields.next()?.parse().ok()?,
+            session: unescape(fields.next()?)?,
+            host: unescape(fields.next()?)?,
+            cwd: unescape(fields.next()?)?,
+            status: fields.next()?.parse().ok()?,
+            duration: fields.next()?.parse().ok()?,
+            command: unescape(fields.next()?)?,
+        };
+        fields.next().is_none().then_some(record)
+    }
+}
+
+/// A field with the backslashes, tabs and line breaks in it escaped, so it fits on its line.
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::new();
+    let mut chars = field.chars();
+    while let Some(char) = chars.next() {
+        unescaped.push(match char {
+            '\\' => match chars.next()? {
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                '\\' => '\\',
+                _ => return None,
+            },
+            char => char,
+        });
+    }
+    Some(unescaped)
+}
+
+/// `history.log` in the data directory, next to the database `z` keeps.
+pub fn location() -> Option<PathBuf> {
+    Some(data::dir()?.join("history.log"))
+}
+
+/// Adds a record to the end of the log, which is only ever appended to, locked like the history file.
+pub fn append(path: &Path, record: &Record) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    super::lock(&file)?;
+    file.write_all(format!("{}\n", record.to_line()).as_bytes())
+}
+
+/// The records in the log, oldest first, skipping lines that are not records.
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(String::from_utf8_lossy(&contents)
+        .lines()
+        .filter_map(Record::from_line)
+        .collect())
+}
+
+/// An age like `90s`, `30m`, `2h`, `1d` or `1w`, in seconds. Without a unit it is in seconds.
+pub fn parse_age(age: &str) -> Option<u64> {
+    let split = age
+        .find(|char: char| !char.is_ascii_digit())
+        .unwrap_or(age.len());
+    let (number, unit) = age.split_at(split);
+    let unit = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+/// Which records `history` shows.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    /// Run in this directory, or one below it.
+    pub cwd: Option<PathBuf>,
+    /// With a status other than 0.
+    pub failed: bool,
+    /// Started at this time or later.
+    pub since: Option<u64>,
+    /// Started before this time.
+    pub until: Option<u64>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.cwd
+            .as_ref()
+            .map_or(true, |cwd| Path::new(&record.cwd).starts_with(cwd))
+            && (!self.failed || record.status != 0)
+            && self.since.map_or(true, |since| record.time >= since)
+            && self.until.map_or(true, |until| record.time < until)
+    }
+}
+
+#[cfg(test)]
+mod log {
+    use super::*;
+
+    fn record(command: &str, cwd: &str, status: i32, time: u64) -> Record {
+        Record {
+            time,
+            session: "1-2".to_string(),
+            host: "box".to_string(),
+            cwd: cwd.to_string(),
+            status,
+            duration: 0.25,
+            command: command.to_string(),
+        }
+    }
+
+    #[test]
+    fn line_round_trip() {
+        let tricky = record("echo \"a\\b\"\n\ttab \u{1} é 😀", "/tmp", 1, 1700000000);
+        let line = tricky.to_line();
+        assert_eq!(
+            line,
+            "1700000000\t1-2\tbox\t/tmp\t1\t0.250\techo \"a\\\\b\"\\n\\ttab \u{1} é 😀"
+        );
+        assert_eq!(Record::from_line(&line), Some(tricky));
+        assert_eq!(Record::from_line("1\t1-2\tbox\t/\t0\t0.1"), None);
+        assert_eq!(Record::from_line("1\t1-2\tbox\t/\t0\t0.1\tls\textra"), None);
+        assert_eq!(Record::from_line("1\t1-2\tbox\t/\t0\t0.1\tbad \\q"), None);
+        assert_eq!(Record::from_line("ls -l"), None);
+    }
+
+    #[test]
+    fn json_output() {
+        let tricky = record("echo \"a\\b\"\n\ttab \u{1} é 😀", "/tmp", 1, 1700000000);
+        assert_eq!(
+            tricky.to_json(),
+            r#"{"time":1700000000,"session":"1-2","host":"box","cwd":"/tmp","status":1,"duration":0.250,"command":"echo \"a\\b\"\n\ttab \u0001 é 😀"}"#
+        );
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(parse_age("90"), Some(90));
+        assert_eq!(parse_age("30m"), Some(1800));
+        assert_eq!(parse_age("1h"), Some(3600));
+        assert_eq!(parse_age("2d"), Some(172800));
+        assert_eq!(parse_age("h"), None);
+        assert_eq!(parse_age("1y"), None);
+    }
+
+    #[test]
+    fn filters() {
+        let filter = Filter {
+            cwd: Some(PathBuf::from("/repo")),
+            failed: true,
+            since: Some(100),
+            until: Some(200),
+        };
+        assert!(filter.matches(&record("make", "/repo/src", 2, 150)));
+        assert!(!filter.matches(&record("make", "/repository", 2, 150)));
+        assert!(!filter.matches(&record("make", "/repo", 0, 150)));
+        assert!(!filter.matches(&record("make", "/repo", 2, 200)));
+        assert!(!filter.matches(&record("make", "/repo", 2, 99)));
+        assert!(Filter::default().matches(&record("ls", "/", 0, 0)));
+    }
+}
@@ -0,0 +1,603 @@
+use std::ffi::CStr;
+use std::io::{self, Write};
+
+use crate::signals;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// Stopped by the given signal, usually `SIGTSTP` from Ctrl-Z.
+    Stopped(i32),
+    Exited(i32),
+    Signaled(i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: libc::pid_t,
+    pub state: ProcessState,
+}
+
+/// The processes started for one command line, which share a process group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// The number `[n]` the job is reported with, or 0 while it runs in the foreground.
+    pub id: usize,
+    pub processes: Vec<Process>,
+    /// The command line as it was typed.
+    pub command: String,
+    /// Whether a change of state still has to be reported to the user.
+    notify: bool,
+}
+
+impl Job {
+    pub fn new(pids: Vec<libc::pid_t>, command: String) -> Self {
+        Job {
+            id: 0,
+            processes: pids
+                .into_iter()
+                .map(|pid| Process {
+                    pid,
+                    state: ProcessState::Running,
+                })
+                .collect(),
+            command,
+            notify: false,
+        }
+    }
+
+    /// The process group of the job, which is the pid of its first process.
+    pub fn pgid(&self) -> libc::pid_t {
+        self.processes.first().map_or(0, |process| process.pid)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.processes.iter().all(|process| {
+            matches!(
+                process.state,
+                ProcessState::Exited(_) | ProcessState::Signaled(_)
+            )
+        })
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.processes
+            .iter()
+            .any(|process| matches!(process.state, ProcessState::Stopped(_)))
+    }
+
+    /// The exit status of the job, like `$?` would have it.
+    pub fn status(&self) -> i32 {
+        match self.processes.last().map(|process| process.state) {
+            None | Some(ProcessState::Running) => 0,
+            Some(ProcessState::Exited(code)) => code,
+            Some(ProcessState::Stopped(signal) | ProcessState::Signaled(signal)) => 128 + signal,
+        }
+    }
+
+    /// How the job is described in reports: `Running`, `Stopped`, `Done`, `Exit 2`, `Terminated`...
+    pub fn state(&self) -> String {
+        let stopped = self
+            .processes
+            .iter()
+            .find_map(|process| match process.state {
+                ProcessState::Stopped(signal) => Some(signal),
+                _ => None,
+            });
+        match stopped {
+            Some(libc::SIGTTIN) => return "Stopped (tty input)".to_string(),
+            Some(libc::SIGTTOU) => return "Stopped (tty output)".to_string(),
+            Some(_) => return "Stopped".to_string(),
+            None => (),
+        }
+        match self.processes.last().map(|process| process.state) {
+            Some(ProcessState::Exited(0)) => "Done".to_string(),
+            Some(ProcessState::Exited(code)) => format!("Exit {}", code),
+            Some(ProcessState::Signaled(signal)) => signal_description(signal),
+            _ => "Running".to_string(),
+        }
+    }
+
+    /// Marks every stopped process as running again, after it was sent `SIGCONT`.
+    pub fn set_running(&mut self) {
+        for process in &mut self.processes {
+            if let ProcessState::Stopped(_) = process.state {
+                process.state = ProcessState::Running;
+            }
+        }
+    }
+
+    /**
+    Sends a signal to the job: to its process group if it has one of its own, and otherwise,
+    when it shares the shell's, to each of its processes.
+    **/
+    pub fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        // SAFETY: getpgid has no effect on memory.
+        if unsafe { libc::getpgid(self.pgid()) } == self.pgid() {
+            return signal_group(self.pgid(), signal);
+        }
+        for process in &self.processes {
+            // SAFETY: kill has no effect on memory.
+            if unsafe { libc::kill(process.pid, signal) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Blocks until none of the processes is running any more, because they finished or were stopped.
+    Fails if one of them can no longer be waited for, with its state left as it was last seen.
+    **/
+    pub fn wait(&mut self) -> io::Result<()> {
+        while let Some(process) = self
+            .processes
+            .iter_mut()
+            .find(|process| process.state == ProcessState::Running)
+        {
+            let Some(status) = waitpid(process.pid, libc::WUNTRACED) else {
+                return Err(io::Error::last_os_error());
+            };
+            if let Some(state) = process_state(status) {
+                process.state = state;
+            }
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, pid: libc::pid_t, state: ProcessState) -> bool {
+        let Some(process) = self.processes.iter_mut().find(|process| process.pid == pid) else {
+            return false;
+        };
+        process.state = state;
+        self.notify = self.is_done() || self.is_stopped();
+        true
+    }
+}
+
+/// What went wrong when looking up a job spec like `%2` or `%vim`.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum JobSpecError {
+    #[error("no such job")]
+    NoSuchJob,
+    #[error("ambiguous job spec")]
+    Ambiguous,
+}
+
+/**
+The jobs of the shell, ordered by number. Finished and stopped jobs stay in the table,
+marked as such, until they have been reported to the user.
+**/
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Job numbers from the least to the most recently started or stopped; the last is the current job.
+    recent: Vec<usize>,
+    /// The pids and exit statuses of processes in finished jobs that left the table, oldest first,
+    /// for `wait pid` to collect.
+    finished: Vec<(libc::pid_t, i32)>,
+}
+
+/// How many statuses of finished processes are kept for `wait` at most.
+const FINISHED_KEPT: usize = 64;
+
+impl JobTable {
+    /// Adds a background job for the given processes, returning its number.
+    pub fn add(&mut self, pids: Vec<libc::pid_t>, command: String) -> usize {
+        self.insert(Job::new(pids, command))
+    }
+
+    /**
+    Puts a job in the table and makes it the current job. A job without a number gets one more
+    than the highest in use, while one coming back from the foreground keeps its own.
+    **/
+    pub fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.last().map_or(1, |last| last.id + 1);
+        }
+        let id = job.id;
+        let index = self.jobs.partition_point(|other| other.id < id);
+        self.jobs.insert(index, job);
+        self.make_current(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        self.recent.retain(|&recent| recent != id);
+        Some(self.jobs.remove(index))
+    }
+
+    /// Takes a finished job out of the table once it has been reported, keeping its statuses for `wait`.
+    pub fn retire(&mut self, id: usize) {
+        let Some(job) = self.remove(id) else {
+            return;
+        };
+        for process in &job.processes {
+            let status = match process.state {
+                ProcessState::Exited(code) => code,
+                ProcessState::Signaled(signal) => 128 + signal,
+                ProcessState::Running | ProcessState::Stopped(_) => continue,
+            };
+            self.finished.push((process.pid, status));
+        }
+        let excess = self.finished.len().saturating_sub(FINISHED_KEPT);
+        self.finished.drain(..excess);
+    }
+
+    /// Retires every finished job that has been reported.
+    pub fn retire_reported(&mut self) {
+        let reported: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|job| job.is_done() && !job.notify)
+            .map(|job| job.id)
+            .collect();
+        for id in reported {
+            self.retire(id);
+        }
+    }
+
+    /// The exit status of a process whose job was retired, which can only be collected once.
+    pub fn take_finished(&mut self, pid: libc::pid_t) -> Option<i32> {
+        let index = self.finished.iter().position(|&(other, _)| other == pid)?;
+        Some(self.finished.remove(index).1)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn make_current(&mut self, id: usize) {
+        self.recent.retain(|&recent| recent != id);
+        self.recent.push(id);
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.recent.last().copied()
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        self.recent.iter().rev().nth(1).copied()
+    }
+
+    /// `+` for the current job, `-` for the previous one, and a space for the others.
+    pub fn marker(&self, id: usize) -> char {
+        if self.current() == Some(id) {
+            '+'
+        } else if self.previous() == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// The job with the given pid among its processes.
+    pub fn find_pid(&self, pid: libc::pid_t) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.processes.iter().any(|process| process.pid == pid))
+            .map(|job| job.id)
+    }
+
+    /**
+    The job a job spec refers to: `%n` by number, `%+`, `%%` or `%` for the current job,
+    `%-` for the previous one, `%string` for the job whose command starts with string and
+    `%?string` for the one whose command contains it.
+    **/
+    pub fn resolve(&self, spec: &str) -> Result<usize, JobSpecError> {
+        let spec = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match spec {
+            "" | "+" | "%" => self.current(),
+            "-" => self.previous(),
+            _ if spec.bytes().all(|b| b.is_ascii_digit()) => {
+                spec.parse().ok().filter(|&id| self.get(id).is_some())
+            }
+            _ => {
+                let mut matching = self.jobs.iter().filter(|job| match spec.strip_prefix('?') {
+                    Some(text) => job.command.contains(text),
+                    None => job.command.starts_with(spec),
+                });
+                let found = matching.next().map(|job| job.id);
+                if matching.next().is_some() {
+                    return Err(JobSpecError::Ambiguous);
+                }
+                found
+            }
+        };
+        found.ok_or(JobSpecError::NoSuchJob)
+    }
+
+    /// Collects every child that has finished or stopped, so none of them is left a zombie.
+    pub fn reap(&mut self) {
+        while let Some((pid, status)) =
+            waitpid_any(libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED)
+        {
+            self.update(pid, status);
+        }
+    }
+
+    /// Records the wait status of one of our children, returning the number of its job.
+    pub fn update(&mut self, pid: libc::pid_t, status: libc::c_int) -> Option<usize> {
+        let state = process_state(status)?;
+        let job = self
+            .jobs
+            .iter_mut()
+            .find_map(|job| job.update(pid, state).then_some(job))?;
+        let (id, stopped) = (job.id, job.is_stopped());
+        if stopped {
+            self.make_current(id);
+        }
+        Some(id)
+    }
+
+    /**
+    The lines reporting jobs that finished or stopped since they were last reported,
+    like `[1]+  Done                    sleep 1`. Finished jobs stay in the table until retired.
+    **/
+    pub fn take_notifications(&mut self) -> Vec<String> {
+        let lines = self
+            .jobs
+            .iter()
+            .filter(|job| job.notify)
+            .map(|job| format_job(job, self.marker(job.id), false))
+            .collect();
+        for job in &mut self.jobs {
+            job.notify = false;
+        }
+        lines
+    }
+}
+
+/// The line a job is reported with, like `[1]+  Running                 sleep 10 &`, optionally with its pid.
+pub fn format_job(job: &Job, marker: char, with_pid: bool) -> String {
+    let pid = if with_pid {
+        format!(" {} ", job.pgid())
+    } else {
+        "  ".to_string()
+    };
+    let state = job.state();
+    let background = if state == "Running" { " &" } else { "" };
+    format!(
+        "[{}]{}{}{:<24}{}{}",
+        job.id, marker, pid, state, job.command, background
+    )
+}
+
+fn process_state(status: libc::c_int) -> Option<ProcessState> {
+    if libc::WIFSTOPPED(status) {
+        Some(ProcessState::Stopped(libc::WSTOPSIG(status)))
+    } else if libc::WIFCONTINUED(status) {
+        Some(ProcessState::Running)
+    } else if libc::WIFSIGNALED(status) {
+        Some(ProcessState::Signaled(libc::WTERMSIG(status)))
+    } else if libc::WIFEXITED(status) {
+        Some(ProcessState::Exited(libc::WEXITSTATUS(status)))
+    } else {
+        None
+    }
+}
+
+/// Waits for a particular child, retrying when interrupted. Returns `None` if there is no such child.
+pub fn waitpid(pid: libc::pid_t, options: libc::c_int) -> Option<libc::c_int> {
+    loop {
+        let mut status = 0;
+        // SAFETY: waitpid only writes to `status`, which lives for the duration of the call.
+        match unsafe { libc::waitpid(pid, &mut status, options) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            found if found == pid => return Some(status),
+            _ => return None,
+        }
+    }
+}
+
+/// Waits for any child, returning its pid and wait status, or `None` if there is none to collect.
+pub fn waitpid_any(options: libc::c_int) -> Option<(libc::pid_t, libc::c_int)> {
+    loop {
+        let mut status = 0;
+        // SAFETY: waitpid only writes to `status`, which lives for the duration of the call.
+        match unsafe { libc::waitpid(-1, &mut status, options) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            pid if pid > 0 => return Some((pid, status)),
+            _ => return None,
+        }
+    }
+}
+
+/// The name the system gives a signal, like `Terminated` or `Killed`.
+pub fn signal_description(signal: i32) -> String {
+    // SAFETY: strsignal returns a pointer to a nul-terminated string, which we copy before anything else can change it.
+    unsafe {
+        let description = libc::strsignal(signal);
+        if description.is_null() {
+            format!("Signal {}", signal)
+        } else {
+            CStr::from_ptr(description).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// The signals an interactive shell ignores for itself, so that only its jobs are stopped.
+const JOB_CONTROL_SIGNALS: [libc::c_int; 3] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// Which process group a forked child goes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    /// The shell's own, when there is no job control.
+    Shell,
+    /// A new group, in the background.
+    Background,
+    /// A new group, which is given the terminal.
+    Foreground,
+    /// The group of an earlier process of the same job, like the first command of a pipeline.
+    Join(libc::pid_t),
+}
+
+impl Group {
+    /// The group id to give a new child, where 0 makes a group named after the child itself.
+    fn pgid(self) -> Option<libc::pid_t> {
+        match self {
+            Group::Shell => None,
+            Group::Background | Group::Foreground => Some(0),
+            Group::Join(pgid) => Some(pgid),
+        }
+    }
+}
+
+/**
+Runs `child` in a forked copy of the shell, which exits with the status it returns.
+The parent gets the pid of the child straight away.
+**/
+pub fn fork(group: Group, child: impl FnOnce() -> i32) -> io::Result<libc::pid_t> {
+    // SAFETY: the shell is single threaded, so the child gets a consistent copy of everything.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            // SAFETY: these only change the process group of the new child.
+            unsafe {
+                if let Some(pgid) = group.pgid() {
+                    libc::setpgid(0, pgid);
+                }
+                if group == Group::Foreground {
+                    libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                }
+            }
+            signals::reset();
+            let status = child();
+            let _ = io::stdout().flush();
+            // SAFETY: _exit ends the child without running the parent's exit handlers a second time.
+            unsafe { libc::_exit(status) }
+        }
+        pid => {
+            // Also done here, so the group exists whichever of parent and child runs first.
+            if let Some(pgid) = group.pgid() {
+                // SAFETY: only changes the process group of our own child.
+                unsafe { libc::setpgid(pid, if pgid == 0 { pid } else { pgid }) };
+            }
+            Ok(pid)
+        }
+    }
+}
+
+/**
+Sets the shell up to control jobs, if its input is a terminal: it gets a process group of its own,
+which is put in the foreground, and ignores Ctrl-Z and background terminal access for itself.
+Returns whether job control is possible.
+**/
+pub fn enable_job_control() -> bool {
+    // SAFETY: these only change the process group and signal dispositions of the shell itself.
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return false;
+        }
+        for signal in JOB_CONTROL_SIGNALS {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+        libc::setpgid(0, 0);
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+    }
+    true
+}
+
+/// Hands the terminal to the given process group.
+pub fn give_terminal(pgid: libc::pid_t) {
+    // SAFETY: tcsetpgrp has no effect on memory; failure only means the terminal stays where it is.
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid) };
+}
+
+/// Sends a signal to every process in a group.
+pub fn signal_group(pgid: libc::pid_t, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: kill has no effect on memory.
+    match unsafe { libc::kill(-pgid, signal) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod job_table {
+    use super::*;
+
+    fn exited(code: i32) -> libc::c_int {
+        code << 8
+    }
+
+    #[test]
+    fn numbers_follow_the_highest_job() {
+        let mut table = JobTable::default();
+        assert_eq!(table.add(vec![10], "a".to_string()), 1);
+        assert_eq!(table.add(vec![11], "b".to_string()), 2);
+        assert_eq!(table.marker(2), '+');
+        assert_eq!(table.marker(1), '-');
+    }
+
+    #[test]
+    fn finished_jobs_are_reported_once() {
+        let mut table = JobTable::default();
+        table.add(vec![10], "sleep 1".to_string());
+        table.add(vec![11], "false".to_string());
+        table.update(11, exited(1));
+
+        let lines = table.take_notifications();
+        assert_eq!(lines, vec![format!("[2]+  {:<24}false", "Exit 1")]);
+        assert!(table.take_notifications().is_empty());
+        assert_eq!(table.iter().count(), 2);
+
+        // Once reported, the job leaves the table, though `wait` can still get its status.
+        table.retire_reported();
+        assert_eq!(table.iter().count(), 1);
+        assert_eq!(table.take_finished(11), Some(1));
+        assert_eq!(table.take_finished(11), None);
+    }
+
+    #[test]
+    fn stopped_jobs_become_current() {
+        let mut table = JobTable::default();
+        table.add(vec![10], "vim".to_string());
+        table.add(vec![11], "sleep 9".to_string());
+        table.update(10, 0x7f | (libc::SIGTSTP << 8));
+
+        assert_eq!(table.current(), Some(1));
+        assert_eq!(table.get(1).unwrap().state(), "Stopped");
+        assert_eq!(
+            table.take_notifications(),
+            vec![format!("[1]+  {:<24}vim", "Stopped")]
+        );
+        assert_eq!(table.iter().count(), 2);
+    }
+
+    #[test]
+    fn job_specs() {
+        let mut table = JobTable::default();
+        table.add(vec![10], "vim notes".to_string());
+        table.add(vec![11], "sleep 9".to_string());
+        table.add(vec![12], "sleep 10".to_string());
+
+        assert_eq!(table.resolve("%1"), Ok(1));
+        assert_eq!(table.resolve("%+"), Ok(3));
+        assert_eq!(table.resolve("%%"), Ok(3));
+        assert_eq!(table.resolve("%-"), Ok(2));
+        assert_eq!(table.resolve("%vim"), Ok(1));
+        assert_eq!(table.resolve("%?notes"), Ok(1));
+        assert_eq!(table.resolve("%sleep"), Err(JobSpecError::Ambiguous));
+        assert_eq!(table.resolve("%4"), Err(JobSpecError::NoSuchJob));
+        assert_eq!(table.resolve("%emacs"), Err(JobSpecError::NoSuchJob));
+    }
+
+    #[test]
+    fn waiting_for_a_stranger_fails() {
+        // Process 1 is never a child of ours.
+        let mut job = Job::new(vec![1], "init".to_string());
+        let err = job.wait().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ECHILD));
+        assert_eq!(job.processes[0].state, ProcessState::Running);
+    }
+}
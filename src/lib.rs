@@ -1,98 +1,1334 @@
+mod aliases;
 mod commands;
+mod conditional;
+mod data;
+pub mod editor;
+mod frecency;
+mod hash;
+mod history;
+mod jobs;
 mod parse;
+mod pattern;
+pub mod signals;
+mod spell;
+pub mod startup;
+#[cfg(test)]
+mod testing;
+mod traps;
+mod vars;
+use crate::aliases::Aliases;
 use crate::commands::*;
+use crate::hash::CommandTable;
+use crate::history::record::{self, Started};
+use crate::history::History;
+use crate::jobs::{Group, Job, JobTable, ProcessState};
+use crate::parse::syntax::{
+    self, AndOr, CaseTerminator, Connector, Item, List, Pipeline, SyntaxError,
+};
 use crate::parse::*;
+use crate::traps::{Condition, Traps};
+use crate::vars::Variables;
 use std::collections::HashMap;
-use std::env;
 use std::error;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, BufRead, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct CommandInfo {
-    pub bin: String,
+    pub bin: OsString,
     pub path: OsString,
 }
 
 #[derive(Debug)]
 pub struct Shell {
-    valid_commands: HashMap<String, OsString>,
+    commands: CommandTable,
+    vars: Variables,
+    options: ShellOptions,
+    /// The exit status of the most recently executed command.
+    last_status: i32,
+    jobs: JobTable,
+    /// The pid of the most recent background command, for `$!`.
+    last_background: Option<libc::pid_t>,
+    /// Whether there is a user at a prompt, to tell about jobs starting and finishing.
+    interactive: bool,
+    /// Whether jobs get process groups of their own, and can be moved between foreground and background.
+    job_control: bool,
+    /// The command line being executed, which names the job if it gets stopped.
+    command_line: String,
+    traps: Traps,
+    /// Set while a trap runs, since `DEBUG` and `ERR` traps do not fire for the commands of traps.
+    in_trap: bool,
+    /// `$0`, the name of the shell or of the script it runs.
+    arg0: OsString,
+    /// `$1`, `$2`, ...
+    positional: Vec<OsString>,
+    /// How many loops are running, which `break` and `continue` may leave.
+    loop_depth: usize,
+    /// A pending `break` or `continue`, which skips the rest of what runs until loops take it.
+    control: Option<Control>,
+    /// How many conditions of `if`, `while` and `until` are being tested. A failing condition does not fire the `ERR` trap.
+    in_condition: usize,
+    /// The functions defined so far, by name, with the compound command that makes up their body.
+    functions: HashMap<String, Rc<syntax::Command>>,
+    /// How many function calls are running, limited by `FUNCNEST`.
+    function_depth: usize,
+    /// `BASH_REMATCH`: what the last `=~` inside `[[ ]]` matched, followed by what its groups matched.
+    rematch: Vec<OsString>,
+    aliases: Aliases,
+    /// How many files are being sourced, which `return` may leave like a function.
+    source_depth: usize,
+    /// The file and line the commands being run were read from, for error messages.
+    location: Option<(PathBuf, usize)>,
+    /// The keybindings of the line editor, set with `set -o`, or `None` when both are turned off.
+    editing_mode: Option<editor::Mode>,
+    /// The command lines entered at the prompt, which `history` lists and the line editor goes back through.
+    history: History,
+    /// Tells the records this shell adds to the log of commands from those of others.
+    session: String,
+    /// The command line entered at the prompt that is running, for the log of commands.
+    started: Option<Started>,
+}
+
+/// Leaving loops early. The count is how many loops are left, the innermost first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Break(usize),
+    /// Leaves `n - 1` loops, then goes on with the next iteration of the one around them.
+    Continue(usize),
+    /// Leaves the function being run, along with any loops in it.
+    Return,
+}
+
+/// Why a command cannot be run.
+#[derive(Debug, thiserror::Error)]
+pub enum ExecError {
+    #[error("No such file or directory")]
+    NotFound,
+    #[error("Permission denied")]
+    PermissionDenied,
+    #[error("Is a directory")]
+    IsADirectory,
+    #[error("Argument list too long")]
+    ArgumentListTooLong,
+    #[error("Text file busy")]
+    TextFileBusy,
+    #[error("{interpreter}: bad interpreter: {reason}")]
+    BadInterpreter { interpreter: String, reason: String },
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ExecError {
+    pub fn status(&self) -> i32 {
+        match self {
+            ExecError::NotFound => NOT_FOUND,
+            _ => NOT_EXECUTABLE,
+        }
+    }
+
+    /**
+    Explains why spawning the program at `path` failed.
+    execve reports a missing `#!` interpreter as ENOENT on the script itself, so that case is told apart by looking at the file.
+    **/
+    pub fn from_spawn(path: &Path, err: &io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(libc::E2BIG) => ExecError::ArgumentListTooLong,
+            Some(libc::ETXTBSY) => ExecError::TextFileBusy,
+            Some(libc::EISDIR) => ExecError::IsADirectory,
+            Some(libc::ENOENT | libc::EACCES) if path.is_file() => match read_interpreter(path) {
+                Some(interpreter) => ExecError::BadInterpreter {
+                    interpreter,
+                    reason: strerror(err),
+                },
+                None if err.raw_os_error() == Some(libc::EACCES) => ExecError::PermissionDenied,
+                None => ExecError::Other(strerror(err)),
+            },
+            Some(libc::ENOENT) => ExecError::NotFound,
+            Some(libc::EACCES) => ExecError::PermissionDenied,
+            _ => ExecError::Other(strerror(err)),
+        }
+    }
+}
+
+/// The interpreter named on the `#!` line of a script, if it has one.
+fn read_interpreter(path: &Path) -> Option<String> {
+    let mut first_line = String::new();
+    io::BufReader::new(fs::File::open(path).ok()?)
+        .read_line(&mut first_line)
+        .ok()?;
+    let interpreter = first_line.strip_prefix("#!")?.split_whitespace().next()?;
+    Some(interpreter.to_string())
+}
+
+/// A pipe as its read and write ends, which are not passed on to programs the shell runs.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 writes two new descriptors into `fds`, which are then owned here.
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])))
+    }
+}
+
+/// The description of an OS error without Rust's " (os error N)" suffix, like `strerror` gives.
+fn strerror(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+/// The behaviours that can be toggled with `shopt`.
+#[derive(Debug, Default)]
+pub struct ShellOptions {
+    /// A command word naming a directory is treated as `cd` to that directory.
+    pub autocd: bool,
+    /// Small typos in the argument to `cd` are corrected.
+    pub cdspell: bool,
+    /// History search with Ctrl-R matches the characters typed in order and lists the best matches.
+    pub fuzzysearch: bool,
+}
+
+impl ShellOptions {
+    pub const NAMES: [&'static str; 3] = ["autocd", "cdspell", "fuzzysearch"];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "autocd" => Some(&mut self.autocd),
+            "cdspell" => Some(&mut self.cdspell),
+            "fuzzysearch" => Some(&mut self.fuzzysearch),
+            _ => None,
+        }
+    }
 }
 
 impl Shell {
     pub fn init() -> Self {
+        signals::init(false);
         Shell {
-            valid_commands: get_binaries_from_paths(get_path_variable()).unwrap_or_default(),
+            commands: CommandTable::default(),
+            vars: Variables::from_env(),
+            options: ShellOptions::default(),
+            last_status: exitcode::OK,
+            jobs: JobTable::default(),
+            last_background: None,
+            interactive: false,
+            job_control: false,
+            command_line: String::new(),
+            traps: Traps::default(),
+            in_trap: false,
+            arg0: std::env::args_os()
+                .next()
+                .unwrap_or_else(|| "shelly".into()),
+            positional: Vec::new(),
+            loop_depth: 0,
+            control: None,
+            in_condition: 0,
+            functions: HashMap::new(),
+            function_depth: 0,
+            rematch: Vec::new(),
+            aliases: Aliases::default(),
+            source_depth: 0,
+            location: None,
+            editing_mode: Some(editor::Mode::default()),
+            history: History::default(),
+            session: format!("{}-{}", data::now(), std::process::id()),
+            started: None,
+        }
+    }
+
+    /// Interactive shells also control jobs, when their input is a terminal.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+        signals::init(interactive);
+        self.job_control = interactive && jobs::enable_job_control();
+        self.update_window_size();
+    }
+
+    /**
+    Parses and executes input, which may hold several commands separated by `;`, `&` or newlines.
+    Each command is parsed with the aliases there are once those before it have run, so that an
+    alias can be used after the `alias` command defining it.
+    **/
+    pub fn run(&mut self, input: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        self.handle_signals();
+        // Checked whole first, so that a syntax error anywhere keeps all of it from running.
+        let items = syntax::parse_with_aliases(input, &self.aliases)
+            .and_then(|_| syntax::Items::new(input));
+        let mut items = match items {
+            Ok(items) => items,
+            Err(err) => {
+                self.syntax_error(err);
+                return Ok(());
+            }
+        };
+        while self.control.is_none() && !signals::is_pending(libc::SIGINT) {
+            match items.next(&self.aliases) {
+                Ok(Some(item)) => self.execute_item(&item)?,
+                Ok(None) => break,
+                Err(err) => {
+                    self.syntax_error(err);
+                    break;
+                }
+            }
         }
+        Ok(())
+    }
+
+    fn syntax_error(&mut self, err: SyntaxError) {
+        eprintln!("shelly: {}{}", self.location_prefix(), err);
+        self.last_status = SYNTAX_ERROR;
     }
 
-    pub fn parse(&self, input: String) -> Result<Box<dyn Execute>, Box<dyn error::Error>> {
-        let mut parser = Parser::default();
-        let options = parser.parse_input(&input);
-        // We are now fetching these every input
-        let valid_commands = get_binaries_from_paths(get_path_variable()).unwrap_or_default();
-        match options.cmd.clone().unwrap_or_default().as_str() {
-            cmd if !BUILTINS.contains(&cmd) && !self.valid_commands.contains_key(cmd) => {
-                Ok(Box::new(InvalidCommand::new(options)))
+    /// `file:line: ` for errors in commands read from a file, and nothing for those typed in.
+    pub fn location_prefix(&self) -> String {
+        match &self.location {
+            Some((path, line)) => format!("{}:{}: ", path.display(), line),
+            None => String::new(),
+        }
+    }
+
+    /// Executes commands one after the other, until the end or a `break` or `continue`.
+    fn execute_list(&mut self, list: &List) -> Result<(), Box<dyn error::Error>> {
+        for item in &list.items {
+            if self.control.is_some() || signals::is_pending(libc::SIGINT) {
+                break;
             }
-            "exit" => Ok(Box::new(ExitCommand::new(options))),
-            "echo" => Ok(Box::new(EchoCommand::new(options))),
-            "type" => Ok(Box::new(TypeCommand::new(options, valid_commands))),
-            "pwd" => Ok(Box::new(PwdCommand::new(options))),
-            "cd" => Ok(Box::new(CdCommand::new(options))),
+            self.execute_item(item)?;
+        }
+        Ok(())
+    }
 
-            cmd if self.valid_commands.contains_key(cmd) => Ok(Box::new(RunCommand::new(
-                options,
-                get_command_info(&valid_commands, cmd),
-            ))),
+    fn execute_item(&mut self, item: &Item) -> Result<(), Box<dyn error::Error>> {
+        match item.background {
+            true => self.run_background(&item.and_or),
+            false => self.execute_and_or(&item.and_or),
+        }
+    }
 
-            _ => Err("Try again.".into()),
+    /// Executes the pipelines of an and-or list for as long as `&&` and `||` let it go on.
+    fn execute_and_or(&mut self, and_or: &AndOr) -> Result<(), Box<dyn error::Error>> {
+        let mut pipelines = std::iter::once((None, &and_or.first))
+            .chain(
+                and_or
+                    .rest
+                    .iter()
+                    .map(|(connector, pipeline)| (Some(*connector), pipeline)),
+            )
+            .peekable();
+        while let Some((connector, pipeline)) = pipelines.next() {
+            if self.control.is_some() || signals::is_pending(libc::SIGINT) {
+                break;
+            }
+            let run = match connector {
+                None => true,
+                Some(Connector::And) => self.last_status == exitcode::OK,
+                Some(Connector::Or) => self.last_status != exitcode::OK,
+            };
+            if !run {
+                continue;
+            }
+            // Only the last pipeline fires the `ERR` trap, as the others are tested like conditions.
+            let last = pipelines.peek().is_none();
+            if last {
+                self.execute_pipeline(pipeline)?;
+            } else {
+                self.execute_condition_with(|shell| shell.execute_pipeline(pipeline))?;
+            }
         }
+        Ok(())
+    }
+
+    /// Executes a pipeline in the foreground. A single command runs in the shell itself, and more as one job of forked copies.
+    fn execute_pipeline(&mut self, pipeline: &Pipeline) -> Result<(), Box<dyn error::Error>> {
+        let run = |shell: &mut Shell| match pipeline.commands.as_slice() {
+            [command] => shell.execute_command(command),
+            commands => {
+                let pids = shell.spawn_pipeline(commands, true)?;
+                shell.last_status = shell.foreground(Job::new(pids, pipeline.to_string()));
+                Ok(())
+            }
+        };
+        if !pipeline.negated {
+            return run(self);
+        }
+        // A negated pipeline is expected to fail as often as not.
+        self.execute_condition_with(run)?;
+        self.last_status = (self.last_status == exitcode::OK).into();
+        Ok(())
+    }
+
+    /**
+    Starts each command of a pipeline in a forked copy of the shell, with its standard output going
+    into a pipe to the standard input of the next. With job control they share the process group of
+    the first. Returns their pids in order.
+    **/
+    fn spawn_pipeline(
+        &mut self,
+        commands: &[syntax::Command],
+        foreground: bool,
+    ) -> Result<Vec<libc::pid_t>, Box<dyn error::Error>> {
+        let mut pids = Vec::new();
+        let mut input: Option<OwnedFd> = None;
+        for (index, command) in commands.iter().enumerate() {
+            let pipe = if index + 1 < commands.len() {
+                Some(pipe()?)
+            } else {
+                None
+            };
+            let pid = self.fork_in(foreground, pids.first().copied(), |shell| {
+                // SAFETY: these only rearrange descriptors of the child, which are not used through anything else.
+                unsafe {
+                    if let Some(input) = &input {
+                        libc::dup2(input.as_raw_fd(), libc::STDIN_FILENO);
+                        libc::close(input.as_raw_fd());
+                    }
+                    if let Some((read, write)) = &pipe {
+                        libc::dup2(write.as_raw_fd(), libc::STDOUT_FILENO);
+                        libc::close(write.as_raw_fd());
+                        libc::close(read.as_raw_fd());
+                    }
+                }
+                shell.run_in_child(command)
+            })?;
+            pids.push(pid);
+            // The write end is the child's alone now, so the reader sees the end of input when it exits.
+            input = pipe.map(|(read, _)| read);
+        }
+        Ok(pids)
+    }
+
+    /// Runs a command in a forked copy of the shell, returning the status for the copy to exit with.
+    fn run_in_child(&mut self, command: &syntax::Command) -> i32 {
+        let result = match command {
+            syntax::Command::Simple(words) => {
+                let line = words.join(b" ".as_slice());
+                self.command_line = String::from_utf8_lossy(&line).into_owned();
+                self.parse(&line).map(|command| command.run_forked(self))
+            }
+            _ => self.execute_command(command).map(|()| self.last_status),
+        };
+        result.unwrap_or_else(|err| {
+            eprintln!("shelly: {}{}", self.location_prefix(), err);
+            1
+        })
     }
-}
 
-pub fn get_binaries_from_paths(paths: Vec<PathBuf>) -> io::Result<HashMap<String, OsString>> {
-    let mut binaries: HashMap<String, OsString> = HashMap::new();
-    for dir in paths {
-        if dir.is_dir() {
-            for entry in fs::read_dir(&dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_file() {
-                    let binary_name = path
-                        .file_name()
-                        .unwrap()
-                        .to_os_string()
-                        .into_string()
-                        .unwrap();
-
-                    binaries
-                        .entry(binary_name)
-                        .or_insert(path.clone().into_os_string());
+    /// Executes a command in the foreground, leaving its exit status in `$?`.
+    fn execute_command(&mut self, command: &syntax::Command) -> Result<(), Box<dyn error::Error>> {
+        match command {
+            syntax::Command::Simple(words) => self.run_command(&words.join(b" ".as_slice())),
+            syntax::Command::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    self.execute_condition(condition)?;
+                    if self.control.is_some() {
+                        return Ok(());
+                    }
+                    if self.last_status == exitcode::OK {
+                        return self.execute_list(body);
+                    }
+                }
+                match otherwise {
+                    Some(otherwise) => self.execute_list(otherwise),
+                    None => {
+                        self.last_status = exitcode::OK;
+                        Ok(())
+                    }
+                }
+            }
+            syntax::Command::While {
+                condition,
+                body,
+                until,
+            } => {
+                let mut status = exitcode::OK;
+                self.loop_depth += 1;
+                let result = loop {
+                    if let Err(err) = self.execute_condition(condition) {
+                        break Err(err);
+                    }
+                    if self.leave_loop() || (self.last_status == exitcode::OK) == *until {
+                        break Ok(());
+                    }
+                    if let Err(err) = self.execute_list(body) {
+                        break Err(err);
+                    }
+                    status = self.last_status;
+                    if self.leave_loop() {
+                        break Ok(());
+                    }
+                };
+                self.loop_depth -= 1;
+                self.last_status = status;
+                result
+            }
+            syntax::Command::For { name, words, body } => {
+                let values = match words {
+                    Some(words) => words.iter().flat_map(|word| self.expand(word)).collect(),
+                    None => self.positional.clone(),
+                };
+                self.last_status = exitcode::OK;
+                self.loop_depth += 1;
+                let mut result = Ok(());
+                for value in values {
+                    self.vars.set(name, value);
+                    result = self.execute_list(body);
+                    if result.is_err() || self.leave_loop() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                result
+            }
+            syntax::Command::Case { word, items } => {
+                let word = self.expand(word).join(OsStr::new(" "));
+                self.last_status = exitcode::OK;
+                let mut falling_through = false;
+                for item in items {
+                    let matched = falling_through
+                        || item.patterns.iter().any(|pattern| {
+                            let pattern = self
+                                .expand(&pattern::quote_specials(pattern, pattern::GLOB_SPECIALS))
+                                .join(OsStr::new(" "));
+                            pattern::matches(pattern.as_bytes(), word.as_bytes())
+                        });
+                    if !matched {
+                        continue;
+                    }
+                    self.execute_list(&item.body)?;
+                    match item.terminator {
+                        CaseTerminator::Break => break,
+                        CaseTerminator::FallThrough => falling_through = true,
+                        CaseTerminator::Continue => falling_through = false,
+                    }
                 }
+                Ok(())
+            }
+            syntax::Command::Group(list) => self.execute_list(list),
+            syntax::Command::Subshell(list) => {
+                let pid = self.fork(true, |shell| match shell.execute_list(list) {
+                    Ok(()) => shell.last_status,
+                    Err(err) => {
+                        eprintln!("shelly: {}", err);
+                        1
+                    }
+                })?;
+                self.last_status = self.foreground(Job::new(vec![pid], command.to_string()));
+                Ok(())
+            }
+            syntax::Command::Redirected { command, redirects } => {
+                let lookup = |name: &str| self.parameter(name);
+                let redirection =
+                    Parser::with_lookup(&lookup).parse_input(redirects.join(b" ".as_slice()));
+                match commands::with_redirects(&redirection, || self.execute_command(command)) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        eprintln!("shelly: {}{}", self.location_prefix(), err);
+                        self.last_status = 1;
+                        Ok(())
+                    }
+                }
+            }
+            syntax::Command::Function { name, body } => {
+                self.functions
+                    .insert(name.clone(), Rc::new((**body).clone()));
+                self.last_status = exitcode::OK;
+                Ok(())
+            }
+            syntax::Command::Conditional(expression) => {
+                self.last_status = match conditional::evaluate(self, expression) {
+                    Ok(true) => exitcode::OK,
+                    Ok(false) => 1,
+                    Err(err) => {
+                        eprintln!("shelly: {}[[: {}", self.location_prefix(), err);
+                        SYNTAX_ERROR
+                    }
+                };
+                if self.last_status != exitcode::OK && self.in_condition == 0 {
+                    self.run_trap(Condition::Err);
+                }
+                Ok(())
             }
         }
     }
-    Ok(binaries)
-}
 
-pub fn get_path_variable() -> Vec<PathBuf> {
-    match env::var_os("PATH") {
-        Some(v) => env::split_paths(&v).collect(),
+    /// Tests the condition of `if`, `while` or `until`, whose failure is not an error.
+    fn execute_condition(&mut self, condition: &List) -> Result<(), Box<dyn error::Error>> {
+        self.execute_condition_with(|shell| shell.execute_list(condition))
+    }
+
+    /// Runs `run` as if it were a condition, so that what fails in it does not fire the `ERR` trap.
+    fn execute_condition_with(
+        &mut self,
+        run: impl FnOnce(&mut Shell) -> Result<(), Box<dyn error::Error>>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.in_condition += 1;
+        let result = run(self);
+        self.in_condition -= 1;
+        result
+    }
+
+    /// Takes a pending `break` or `continue` on behalf of the innermost loop, and tells whether it has to stop.
+    fn leave_loop(&mut self) -> bool {
+        if signals::is_pending(libc::SIGINT) {
+            return true;
+        }
+        match self.control.take() {
+            None => false,
+            Some(Control::Break(count)) => {
+                self.control = (count > 1).then(|| Control::Break(count - 1));
+                true
+            }
+            Some(Control::Continue(count)) => {
+                self.control = (count > 1).then(|| Control::Continue(count - 1));
+                count > 1
+            }
+            Some(Control::Return) => {
+                self.control = Some(Control::Return);
+                true
+            }
+        }
+    }
+
+    /**
+    Runs a function with `arguments` as its positional parameters. The variables assigned before its
+    name are local to the call, and exported to the commands it runs.
+    **/
+    pub fn call_function(
+        &mut self,
+        name: &str,
+        body: &syntax::Command,
+        arguments: Vec<OsString>,
+        assignments: Vec<(String, OsString)>,
+    ) -> Result<i32, Box<dyn error::Error>> {
+        let limit = self
+            .vars
+            .get("FUNCNEST")
+            .and_then(OsStr::to_str)
+            .and_then(|limit| limit.parse::<usize>().ok())
+            .filter(|&limit| limit > 0);
+        if let Some(limit) = limit.filter(|&limit| self.function_depth >= limit) {
+            eprintln!(
+                "shelly: {}: maximum function nesting level exceeded ({})",
+                name, limit
+            );
+            return Ok(1);
+        }
+
+        let positional = std::mem::replace(&mut self.positional, arguments);
+        // The loops of the caller cannot be left with `break` from inside the function.
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+        self.vars.push_scope();
+        for (name, value) in assignments {
+            self.vars.make_local(&name);
+            self.vars.set(&name, value);
+            self.vars.export(&name);
+        }
+
+        let result = self.execute_command(body);
+        if self.control == Some(Control::Return) {
+            self.control = None;
+        }
+        self.run_trap(Condition::Return);
+
+        self.vars.pop_scope();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        result.map(|()| self.last_status)
+    }
+
+    /// The definition of a function, like `name () { ... }` over several lines.
+    pub fn function_text(&self, name: &str) -> Option<String> {
+        let body = self.functions.get(name)?;
+        Some(format!("{} () {}", name, body.pretty()))
+    }
+
+    /// The words a raw word expands to, with its quotes removed.
+    fn expand(&self, word: &[u8]) -> Vec<OsString> {
+        let lookup = |name: &str| self.parameter(name);
+        let parsed = Parser::with_lookup(&lookup).parse_input(word);
+        parsed
+            .cmd
+            .into_iter()
+            .chain(parsed.args.into_iter().flatten())
+            .collect()
+    }
+
+    /// What a raw word expands to as a single word, without field splitting, as inside `[[ ]]`.
+    fn expand_word(&self, word: &[u8]) -> OsString {
+        let lookup = |name: &str| self.parameter(name);
+        let parsed = Parser::with_lookup(&lookup)
+            .without_field_splitting()
+            .parse_input(word);
+        let words: Vec<OsString> = parsed
+            .cmd
+            .into_iter()
+            .chain(parsed.args.into_iter().flatten())
+            .collect();
+        words.join(OsStr::new(" "))
+    }
+
+    /// Executes a simple command, remembering its exit status.
+    fn run_command(&mut self, line: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        self.run_trap(Condition::Debug);
+        self.command_line = String::from_utf8_lossy(line).into_owned();
+        let command = self.parse(line)?;
+        self.last_status = command.execute(self)?;
+        if self.last_status != exitcode::OK && self.in_condition == 0 {
+            self.run_trap(Condition::Err);
+        }
+        self.run_signal_traps();
+        Ok(())
+    }
+
+    /**
+    Starts an and-or list as a background job. A pipeline is started as it would be in the foreground,
+    while a list with `&&` or `||` runs in a forked copy of the shell.
+    **/
+    fn run_background(&mut self, and_or: &AndOr) -> Result<(), Box<dyn error::Error>> {
+        let pipeline = and_or.as_single().filter(|pipeline| !pipeline.negated);
+        let pids = match pipeline.map(|pipeline| (pipeline, pipeline.as_single())) {
+            Some((_, Some(syntax::Command::Simple(words)))) => {
+                let line = words.join(b" ".as_slice());
+                self.run_trap(Condition::Debug);
+                self.command_line = String::from_utf8_lossy(&line).into_owned();
+                vec![self.parse(&line)?.spawn(self)?]
+            }
+            Some((pipeline, _)) => self.spawn_pipeline(&pipeline.commands, false)?,
+            None => vec![
+                self.fork(false, |shell| match shell.execute_and_or(and_or) {
+                    Ok(()) => shell.last_status,
+                    Err(err) => {
+                        eprintln!("shelly: {}", err);
+                        1
+                    }
+                })?,
+            ],
+        };
+        let pid = *pids.last().expect("a job has at least one process");
+        let id = self.jobs.add(pids, and_or.to_string());
+        if self.interactive {
+            eprintln!("[{}] {}", id, pid);
+        }
+        self.last_background = Some(pid);
+        self.last_status = exitcode::OK;
+        Ok(())
+    }
+
+    /**
+    Deals with the signals that arrived since the last prompt: hangs up on `SIGHUP`, picks up
+    the new window size after `SIGWINCH`, and reaps children after `SIGCHLD`.
+    A `SIGINT` has already cancelled the line being typed, and only leaves its status behind.
+    **/
+    pub fn handle_signals(&mut self) {
+        // Set first, so that a trap on INT sees the status too and leaves it behind.
+        if signals::is_pending(libc::SIGINT) {
+            self.last_status = 128 + libc::SIGINT;
+        }
+        self.run_signal_traps();
+        if signals::take(libc::SIGHUP) {
+            self.hang_up();
+        }
+        signals::take(libc::SIGINT);
+        if signals::take(libc::SIGWINCH) {
+            self.update_window_size();
+        }
+        self.reap_jobs();
+    }
+
+    /// Runs the traps of the signals that arrived. `SIGCHLD` is left for `reap_jobs`, which also needs it.
+    fn run_signal_traps(&mut self) {
+        for &(_, signal) in signals::SIGNALS {
+            let condition = Condition::Signal(signal);
+            if signal != libc::SIGCHLD
+                && self
+                    .traps
+                    .get(condition)
+                    .is_some_and(|action| !action.is_empty())
+                && signals::take(signal)
+            {
+                self.run_trap(condition);
+            }
+        }
+    }
+
+    /**
+    Runs the command set with `trap` for `condition`, leaving `$?` as it was.
+    `DEBUG` and `ERR` traps do not fire while another trap runs.
+    **/
+    pub fn run_trap(&mut self, condition: Condition) {
+        if self.in_trap && matches!(condition, Condition::Debug | Condition::Err) {
+            return;
+        }
+        let Some(action) = self
+            .traps
+            .get(condition)
+            .filter(|action| !action.is_empty())
+            .map(<[u8]>::to_vec)
+        else {
+            return;
+        };
+        let (status, in_trap) = (self.last_status, self.in_trap);
+        self.in_trap = true;
+        if let Err(err) = self.run(&action) {
+            eprintln!("shelly: {}", err);
+        }
+        self.in_trap = in_trap;
+        self.last_status = status;
+    }
+
+    /// Exits the shell with `status`, after running the `EXIT` trap. An interactive shell takes its jobs with it.
+    pub fn exit(&mut self, status: i32) -> ! {
+        self.last_status = status;
+        let action = self.traps.get(Condition::Exit).map(<[u8]>::to_vec);
+        // An `exit` in the trap itself must not run it again.
+        self.traps.reset(Condition::Exit, self.interactive);
+        if let Some(action) = action {
+            if let Err(err) = self.run(&action) {
+                eprintln!("shelly: {}", err);
+            }
+        }
+        self.log_command();
+        if self.interactive {
+            self.hang_up_jobs();
+            self.save_history();
+        }
+        let _ = io::stdout().flush();
+        std::process::exit(status);
+    }
+
+    /// Passes a hangup on to every job and exits.
+    fn hang_up(&mut self) -> ! {
+        // An interactive shell does so on any exit.
+        if !self.interactive {
+            self.hang_up_jobs();
+        }
+        self.exit(128 + libc::SIGHUP);
+    }
+
+    /// Sends every job a hangup, waking the stopped ones so they get it.
+    fn hang_up_jobs(&self) {
+        for job in self.jobs.iter() {
+            let _ = job.signal(libc::SIGHUP);
+            if job.is_stopped() {
+                let _ = job.signal(libc::SIGCONT);
+            }
+        }
+    }
+
+    /// Sets `COLUMNS` and `LINES` to the size of the terminal.
+    fn update_window_size(&mut self) {
+        if let Some((columns, lines)) = signals::window_size() {
+            self.vars.set("COLUMNS", columns.to_string().into());
+            self.vars.set("LINES", lines.to_string().into());
+        }
+    }
+
+    /**
+    Collects finished and stopped jobs. At a prompt they are reported once, as `[1]+  Done ...`, and
+    finished ones then leave the table. Otherwise they stay there for `wait` to collect.
+    **/
+    pub fn reap_jobs(&mut self) {
+        if signals::take(libc::SIGCHLD) {
+            self.jobs.reap();
+            self.run_trap(Condition::Signal(libc::SIGCHLD));
+        }
+        let lines = self.jobs.take_notifications();
+        if self.interactive {
+            for line in lines {
+                eprintln!("{}", line);
+            }
+            self.jobs.retire_reported();
+        }
+    }
+
+    /**
+    Runs `child` in a forked copy of the shell. With job control it gets a process group of its own,
+    which is given the terminal if it runs in the foreground. The copy never controls jobs itself.
+    **/
+    pub fn fork(
+        &mut self,
+        foreground: bool,
+        child: impl FnOnce(&mut Shell) -> i32,
+    ) -> io::Result<libc::pid_t> {
+        self.fork_in(foreground, None, child)
+    }
+
+    /// Like `fork`, but with job control a child given `pgid` joins that group, as the later commands of a pipeline do.
+    fn fork_in(
+        &mut self,
+        foreground: bool,
+        pgid: Option<libc::pid_t>,
+        child: impl FnOnce(&mut Shell) -> i32,
+    ) -> io::Result<libc::pid_t> {
+        let group = match (self.job_control, pgid, foreground) {
+            (false, _, _) => Group::Shell,
+            (true, Some(pgid), _) => Group::Join(pgid),
+            (true, None, false) => Group::Background,
+            (true, None, true) => Group::Foreground,
+        };
+        jobs::fork(group, || {
+            self.job_control = false;
+            // Saving the history is left to the shell the copy was forked from.
+            self.history.mark_saved();
+            self.started = None;
+            self.traps.reset_for_subshell();
+            child(self)
+        })
+    }
+
+    /**
+    Waits for a job in the foreground, returning its exit status. If it is stopped instead,
+    it is put in the job table and reported, like `[1]+  Stopped  vim`.
+    **/
+    pub fn foreground(&mut self, mut job: Job) -> i32 {
+        if self.job_control {
+            jobs::give_terminal(job.pgid());
+        }
+        let waited = job.wait();
+        if self.job_control {
+            // SAFETY: getpgrp cannot fail.
+            jobs::give_terminal(unsafe { libc::getpgrp() });
+            // Only the foreground job hears about the window being resized while it runs.
+            self.update_window_size();
+        }
+        if let Err(err) = waited {
+            eprintln!("shelly: wait: {}: {}", job.pgid(), strerror(&err));
+            return NOT_FOUND;
+        }
+        let status = job.status();
+        // The job took the Ctrl-C meant for everything running, so the shell acts as if it got it too.
+        if job
+            .processes
+            .iter()
+            .any(|process| process.state == ProcessState::Signaled(libc::SIGINT))
+        {
+            signals::raise(libc::SIGINT);
+        }
+        if self.interactive {
+            match job.processes.last().map(|process| process.state) {
+                // Ctrl-C leaves the cursor after the `^C` the terminal echoed.
+                Some(ProcessState::Signaled(libc::SIGINT)) => eprintln!(),
+                Some(ProcessState::Signaled(signal)) if signal != libc::SIGPIPE => {
+                    eprintln!("{}", jobs::signal_description(signal))
+                }
+                _ => (),
+            }
+        }
+        if job.is_stopped() {
+            let id = self.jobs.insert(job);
+            let job = self.jobs.get(id).expect("the job was just inserted");
+            eprintln!();
+            eprintln!("{}", jobs::format_job(job, self.jobs.marker(id), false));
+        }
+        status
+    }
+
+    /// Executes the file at `path` a command at a time, reading on while a command spans several lines.
+    pub fn run_file(&mut self, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        let input = fs::read(path)?;
+        self.run_lines(path, &input)
+    }
+
+    /**
+    Runs the lines read from `path`, a command at a time, stopping early on `return`. Errors
+    give the line they were found on.
+    **/
+    fn run_lines(&mut self, path: &Path, input: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        let location = self.location.take();
+        let mut pending = Vec::new();
+        let mut result = Ok(());
+        for (index, line) in input.split_inclusive(|&b| b == b'\n').enumerate() {
+            pending.extend_from_slice(line);
+            if is_incomplete(&pending) {
+                continue;
+            }
+            self.location = Some((path.to_path_buf(), index + 1));
+            result = self.run(&std::mem::take(&mut pending));
+            if result.is_err() || self.control.is_some() {
+                pending.clear();
+                break;
+            }
+        }
+        if !pending.is_empty() {
+            let lines = input.split_inclusive(|&b| b == b'\n').count();
+            self.location = Some((path.to_path_buf(), lines));
+            result = self.run(&pending);
+        }
+        self.location = location;
+        result
+    }
+
+    /**
+    Runs the contents of a file in the current shell, the way `source` does. Given arguments stand
+    in for the positional parameters until it is done. Returns the status of its last command.
+    **/
+    pub fn source(
+        &mut self,
+        path: &Path,
+        input: &[u8],
+        arguments: Option<Vec<OsString>>,
+    ) -> Result<i32, Box<dyn error::Error>> {
+        let positional =
+            arguments.map(|arguments| std::mem::replace(&mut self.positional, arguments));
+        // Like a function, a sourced file cannot leave the loops around it with `break`.
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.source_depth += 1;
+        self.last_status = exitcode::OK;
+
+        let result = self.run_lines(path, input);
+        if self.control == Some(Control::Return) {
+            self.control = None;
+        }
+        self.run_trap(Condition::Return);
+
+        self.source_depth -= 1;
+        self.loop_depth = loop_depth;
+        if let Some(positional) = positional {
+            self.positional = positional;
+        }
+        result.map(|()| self.last_status)
+    }
+
+    /// Sources the startup files that apply to how the shell was started. Files that do not exist are skipped.
+    pub fn run_startup_files(&mut self, startup: &startup::Startup) {
+        let lookup = |name: &str| match name {
+            "ENV" => self
+                .parameter(name)
+                .map(|env| self.expand_word(env.as_bytes())),
+            _ => self.parameter(name),
+        };
+        for path in startup.files(self.interactive, lookup) {
+            if !path.exists() {
+                continue;
+            }
+            if let Err(err) = self.run_file(&path) {
+                eprintln!("shelly: {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// `$HISTFILE`, or `~/.shelly_history` when it is unset. Set to nothing, there is no history file.
+    pub fn history_file(&self) -> Option<PathBuf> {
+        match self.vars.get("HISTFILE") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(PathBuf::from(self.vars.get("HOME")?).join(".shelly_history")),
+        }
+    }
+
+    /**
+    How many entries `HISTSIZE` keeps in memory, or `HISTFILESIZE` in the history file, which
+    follows `HISTSIZE` when it is unset. A negative value means no limit.
+    **/
+    pub fn history_limit(&self, name: &str) -> Option<usize> {
+        let value = self.vars.get(name).and_then(OsStr::to_str).map(str::trim);
+        match value.map(str::parse::<i64>) {
+            Some(Ok(limit)) => usize::try_from(limit).ok(),
+            _ if name == "HISTFILESIZE" => self.history_limit("HISTSIZE"),
+            _ => Some(history::DEFAULT_SIZE),
+        }
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /**
+    Runs a command line entered at the prompt. Unless it is blank it goes in the history, and
+    once it finishes, in the log of commands along with how it went.
+    **/
+    pub fn run_entered(&mut self, line: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        let text = String::from_utf8_lossy(line);
+        let command = text.trim_end_matches('\n');
+        if !command.trim().is_empty() {
+            self.history.add(command);
+            self.history.truncate(self.history_limit("HISTSIZE"));
+            self.started = Some(Started::new(command));
+        }
+        let result = self.run(line);
+        self.log_command();
+        result
+    }
+
+    /// Adds the command line that was running to the log. Failing to should not get in the way of the next command, so errors are ignored.
+    fn log_command(&mut self) {
+        let Some(started) = self.started.take() else {
+            return;
+        };
+        let record = started.finish(self.last_status, &self.session);
+        if let Some(path) = record::location() {
+            let _ = record::append(&path, &record);
+        }
+    }
+
+    /// Reads the history file, which an interactive shell does once its startup files have had a chance to set `HISTFILE`.
+    pub fn load_history(&mut self) {
+        let Some(path) = self.history_file() else {
+            return;
+        };
+        match history::read(&path) {
+            Ok(entries) => self.history.extend_saved(entries),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => eprintln!("shelly: {}: {}", path.display(), err),
+        }
+        self.history.truncate(self.history_limit("HISTSIZE"));
+    }
+
+    /// Appends the lines entered since the history file was last written to it.
+    fn save_history(&mut self) {
+        let Some(path) = self.history_file() else {
+            return;
+        };
+        if self.history.unsaved().is_empty() {
+            return;
+        }
+        let limit = self.history_limit("HISTFILESIZE");
+        match history::append(&path, self.history.unsaved(), limit) {
+            Ok(()) => self.history.mark_saved(),
+            Err(err) => eprintln!("shelly: {}: {}", path.display(), err),
+        }
+    }
+
+    /// Sets `$0` and the positional parameters, for a script run with arguments.
+    pub fn set_arguments(&mut self, arg0: OsString, arguments: Vec<OsString>) {
+        self.arg0 = arg0;
+        self.positional = arguments;
+    }
+
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    pub fn editing_mode(&self) -> Option<editor::Mode> {
+        self.editing_mode
+    }
+
+    pub fn options(&self) -> &ShellOptions {
+        &self.options
+    }
+
+    pub fn parse(&mut self, input: &[u8]) -> Result<Box<dyn Execute>, Box<dyn error::Error>> {
+        let lookup = |name: &str| self.parameter(name);
+        let mut options = Parser::with_lookup(&lookup).parse_input(input);
+        let redirects = options.take_redirects();
+        let command = self.dispatch(options);
+        Ok(match redirects.has_redirects() {
+            true => Box::new(RedirectedCommand::new(redirects, command)),
+            false => command,
+        })
+    }
+
+    /// Picks what runs a parsed command, based on its name.
+    pub fn dispatch(&mut self, options: ParsedCommand) -> Box<dyn Execute> {
+        self.find_command(options, true)
+    }
+
+    /// Like `dispatch`, but passes over functions, for `command name`.
+    pub fn dispatch_command(&mut self, options: ParsedCommand) -> Box<dyn Execute> {
+        self.find_command(options, false)
+    }
+
+    fn find_command(&mut self, options: ParsedCommand, functions: bool) -> Box<dyn Execute> {
+        let (assignments, options) = split_assignments(options);
+        let Some(cmd) = options.cmd.clone() else {
+            return Box::new(AssignCommand::new(assignments));
+        };
+        if let Some(body) = cmd
+            .to_str()
+            .and_then(|name| self.functions.get(name))
+            .filter(|_| functions)
+        {
+            return Box::new(FunctionCommand::new(options, body.clone(), assignments));
+        }
+        // Assignments before a special builtin stay in effect afterwards, and other builtins only see them.
+        if is_special_builtin(&cmd) {
+            self.assign(assignments.clone());
+        } else if is_builtin(&cmd) && !assignments.is_empty() {
+            let command = self.find_command(options, functions);
+            return Box::new(AssignedCommand::new(assignments, command));
+        }
 
-        None => todo!(),
+        // Builtins all have ASCII names, so a command that is not UTF-8 can only be an executable.
+        match cmd.to_str() {
+            _ if self.options.autocd
+                && !is_builtin(&cmd)
+                && Path::new(&cmd).is_dir()
+                && self.lookup_command(&cmd).is_none() =>
+            {
+                Box::new(CdCommand::new(ParsedCommand {
+                    cmd: Some("cd".into()),
+                    args: Some(vec![cmd]),
+                    ..options
+                }))
+            }
+            _ if cmd.as_bytes().contains(&b'/') => match lookup_path_command(&cmd) {
+                Ok(command) => Box::new(RunCommand::new(options, command, assignments)),
+                Err(error) => Box::new(UnrunnableCommand::new(options, error)),
+            },
+            Some("exit") => Box::new(ExitCommand::new(options)),
+            Some("echo") => Box::new(EchoCommand::new(options)),
+            Some("type") => Box::new(TypeCommand::new(options)),
+            Some("pwd") => Box::new(PwdCommand::new(options)),
+            Some("cd") => Box::new(CdCommand::new(options)),
+            Some("z") => Box::new(ZCommand::new(options)),
+            Some("shopt") => Box::new(ShoptCommand::new(options)),
+            Some("hash") => Box::new(HashCommand::new(options)),
+            Some("export") => Box::new(ExportCommand::new(options)),
+            Some("unset") => Box::new(UnsetCommand::new(options)),
+            Some("command") => Box::new(CommandCommand::new(options)),
+            Some("jobs") => Box::new(JobsCommand::new(options)),
+            Some("fg") => Box::new(FgCommand::new(options)),
+            Some("bg") => Box::new(BgCommand::new(options)),
+            Some("disown") => Box::new(DisownCommand::new(options)),
+            Some("wait") => Box::new(WaitCommand::new(options)),
+            Some("trap") => Box::new(TrapCommand::new(options)),
+            Some("kill") => Box::new(KillCommand::new(options)),
+            Some("break") => Box::new(LoopCommand::new_break(options)),
+            Some("continue") => Box::new(LoopCommand::new_continue(options)),
+            Some("return") => Box::new(ReturnCommand::new(options)),
+            Some("local") => Box::new(LocalCommand::new(options)),
+            Some("set") => Box::new(SetCommand::new(options)),
+            Some("history") => Box::new(HistoryCommand::new(options)),
+            Some("alias") => Box::new(AliasCommand::new(options)),
+            Some("unalias") => Box::new(UnaliasCommand::new(options)),
+            Some("source") => Box::new(SourceCommand::new_source(options)),
+            Some(".") => Box::new(SourceCommand::new_dot(options)),
+            Some("test") => Box::new(TestCommand::new_test(options)),
+            Some("[") => Box::new(TestCommand::new_bracket(options)),
+
+            _ => match self.lookup_command(&cmd) {
+                Some(path) => {
+                    self.commands.record_hit(&cmd);
+                    Box::new(RunCommand::new(
+                        options,
+                        CommandInfo {
+                            bin: cmd,
+                            path: path.into_os_string(),
+                        },
+                        assignments,
+                    ))
+                }
+                None => Box::new(InvalidCommand::new(options)),
+            },
+        }
+    }
+
+    /// The value of a variable or special parameter.
+    pub fn parameter(&self, name: &str) -> Option<OsString> {
+        match name {
+            "?" => Some(self.last_status.to_string().into()),
+            "$" => Some(std::process::id().to_string().into()),
+            "!" => self.last_background.map(|pid| pid.to_string().into()),
+            "0" => Some(self.arg0.clone()),
+            "#" => Some(self.positional.len().to_string().into()),
+            "@" | "*" => Some(self.positional.join(OsStr::new(" "))),
+            "BASH_REMATCH" => self.rematch.first().cloned(),
+            "BASH_REMATCH[@]" | "BASH_REMATCH[*]" => Some(self.rematch.join(OsStr::new(" "))),
+            _ if name.starts_with("BASH_REMATCH[") && name.ends_with(']') => {
+                let index = &name["BASH_REMATCH[".len()..name.len() - 1];
+                self.rematch.get(index.parse::<usize>().ok()?).cloned()
+            }
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => name
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| self.positional.get(index.checked_sub(1)?).cloned()),
+            _ => self.vars.get(name).map(OsStr::to_os_string),
+        }
+    }
+
+    pub fn assign(&mut self, assignments: Vec<(String, OsString)>) {
+        for (name, value) in assignments {
+            self.vars.set(&name, value);
+        }
+    }
+
+    /// Runs `run` with the variables assigned and exported, putting back what they were afterwards.
+    pub fn with_assignments<T>(
+        &mut self,
+        assignments: Vec<(String, OsString)>,
+        run: impl FnOnce(&mut Shell) -> T,
+    ) -> T {
+        self.vars.push_scope();
+        for (name, value) in assignments {
+            self.vars.make_local(&name);
+            self.vars.set(&name, value);
+            self.vars.export(&name);
+        }
+        let result = run(self);
+        self.vars.pop_scope();
+        result
+    }
+
+    /// PATH, or the system default search path when PATH is unset.
+    pub fn search_path(&self) -> OsString {
+        self.vars
+            .get("PATH")
+            .map(OsStr::to_os_string)
+            .unwrap_or_else(vars::default_path)
+    }
+
+    /// Finds a command in PATH through the command hash table.
+    pub fn lookup_command(&mut self, name: &OsStr) -> Option<PathBuf> {
+        let path = self.search_path();
+        self.commands.lookup(name, &path)
     }
 }
+
+/// Whether `input` stops in the middle of a command, which more lines could finish.
+pub fn is_incomplete(input: &[u8]) -> bool {
+    syntax::parse(input) == Err(SyntaxError::Incomplete)
+}
+
+/// Takes the leading `NAME=value` words off a command.
+fn split_assignments(options: ParsedCommand) -> (Vec<(String, OsString)>, ParsedCommand) {
+    let mut words = options.cmd.iter().chain(options.args.iter().flatten());
+    let assignments = words
+        .by_ref()
+        .take(options.assignments)
+        .filter_map(|word| vars::parse_assignment(word))
+        .collect();
+
+    let cmd = words.next().cloned();
+    let args: Vec<OsString> = words.cloned().collect();
+    (
+        assignments,
+        ParsedCommand {
+            cmd,
+            args: if args.is_empty() { None } else { Some(args) },
+            assignments: 0,
+            ..options
+        },
+    )
+}
+
 /**
-This function assumes that command is in valid_commands, and constructs a struct with the relevant information.
+Commands containing a slash are not looked up in PATH, but run from the path as given.
 **/
-pub fn get_command_info(valid_commands: &HashMap<String, OsString>, command: &str) -> CommandInfo {
-    let command_borrowed = valid_commands.get_key_value(command).unwrap();
-
-    CommandInfo {
-        bin: command_borrowed.0.to_string(),
-        path: command_borrowed.1.to_owned(),
+pub fn lookup_path_command(command: &OsStr) -> Result<CommandInfo, ExecError> {
+    let path = Path::new(command);
+    let metadata = fs::metadata(path).map_err(|_| ExecError::NotFound)?;
+    if metadata.is_dir() {
+        Err(ExecError::IsADirectory)
+    } else if !hash::is_executable(&metadata) {
+        Err(ExecError::PermissionDenied)
+    } else {
+        Ok(CommandInfo {
+            bin: command.to_owned(),
+            path: command.to_owned(),
+        })
     }
 }
@@ -1,17 +1,63 @@
+use codecrafters_shell::editor::Editor;
+use codecrafters_shell::startup::Startup;
 use codecrafters_shell::*;
-use std::io::{self, Write};
+use std::env;
+use std::mem;
+use std::path::Path;
 extern crate exitcode;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let shell = Shell::init();
+    let mut shell = Shell::init();
+
+    let mut args = env::args_os();
+    let arg0 = args.next().unwrap_or_default();
+    let (startup, mut args) = match Startup::parse(&arg0, args.collect()) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("shelly: {}", err);
+            std::process::exit(exitcode::USAGE);
+        }
+    };
+
+    // `shelly script [args]` runs the script instead of prompting.
+    if !args.is_empty() {
+        let script = args.remove(0);
+        shell.set_arguments(script.clone(), args);
+        shell.run_startup_files(&startup);
+        shell.run_file(Path::new(&script))?;
+        let status = shell.last_status();
+        shell.exit(status);
+    }
+
+    shell.set_interactive(true);
+    shell.run_startup_files(&startup);
+    shell.load_history();
+    let mut editor = Editor::default();
+    // What has been typed of a command that goes on over several lines.
+    let mut pending = Vec::new();
     loop {
-        print!("$ ");
-        io::stdout().flush().unwrap();
+        shell.handle_signals();
+        let prompt = if pending.is_empty() { "$ " } else { "> " };
+        editor.set_mode(shell.editing_mode());
+        editor.set_fuzzy_search(shell.options().fuzzysearch);
 
         // Wait for user input
-        let stdin = io::stdin();
-        let mut input = String::new();
-        stdin.read_line(&mut input).unwrap();
-        shell.parse(input.trim().to_string())?.execute()?;
+        match editor.read_line(prompt, shell.history().entries(), &mut pending) {
+            Ok(0) => {
+                if !pending.is_empty() {
+                    shell.run_entered(&mem::take(&mut pending))?;
+                }
+                let status = shell.last_status();
+                shell.exit(status)
+            }
+            Ok(_) if is_incomplete(&pending) => (),
+            Ok(_) => shell.run_entered(&mem::take(&mut pending))?,
+            // Ctrl-C throws away the command being typed.
+            Err(err) if signals::Interrupted::is(&err) => {
+                pending.clear();
+                println!()
+            }
+            Err(err) => return Err(err.into()),
+        }
     }
 }
@@ -1,38 +1,109 @@
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::{self, Error, Write};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::PathBuf;
 
+use crate::vars;
+
+pub mod syntax;
+pub mod tokens;
+
+/// Words are kept as raw bytes, since Unix file names and arguments need not be UTF-8.
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
 pub struct ParsedCommand {
-    pub cmd: Option<String>,
-    pub args: Option<Vec<String>>,
-    pub output: Option<PathBuf>,
-    pub errorout: Option<PathBuf>,
+    pub cmd: Option<OsString>,
+    pub args: Option<Vec<OsString>>,
+    /// Redirections of the output and errors, in the order they are to be made.
+    pub redirects: Vec<Redirect>,
+    /// How many words, from the command name on, are unquoted assignments like `FOO=1`.
+    pub assignments: usize,
+}
+
+/// Where a command sends one of its descriptors.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Redirect {
+    /// `n> path`, or `n>> path` to add to the end of the file.
+    File {
+        descriptor: u8,
+        path: PathBuf,
+        append: bool,
+    },
+    /// `2>&1`: errors go wherever the output goes at that point.
+    ErrorsToOutput,
 }
 
 impl ParsedCommand {
+    /// Whether the command redirects its output or errors anywhere.
+    pub fn has_redirects(&self) -> bool {
+        !self.redirects.is_empty()
+    }
+
+    /// Takes the redirections off the command, leaving it to write where the shell does.
+    pub fn take_redirects(&mut self) -> ParsedCommand {
+        ParsedCommand {
+            redirects: mem::take(&mut self.redirects),
+            ..Default::default()
+        }
+    }
+
+    /// The file the output was last redirected to.
+    pub fn output(&self) -> Option<&PathBuf> {
+        self.redirects
+            .iter()
+            .rev()
+            .find_map(|redirect| match redirect {
+                Redirect::File {
+                    descriptor: 1,
+                    path,
+                    ..
+                } => Some(path),
+                _ => None,
+            })
+    }
+
     pub fn get_output(&self) -> Result<Box<dyn Write>, Error> {
-        match self.output {
-            Some(ref path) => File::create(path).map(|f| Box::new(f) as Box<dyn Write>),
+        match self.output() {
+            Some(path) => File::create(path).map(|f| Box::new(f) as Box<dyn Write>),
             None => Ok(Box::new(io::stdout())),
         }
     }
 }
 
+/// Resolves a parameter name (`HOME`, `?`, `1`, ...) to its value, if it is set.
+pub type Lookup<'a> = &'a dyn Fn(&str) -> Option<OsString>;
+
 // TODO: impl parse_input to this one
 #[derive(Default)]
-pub struct Parser {
+pub struct Parser<'a> {
     parsing_data: ParseInfo,
+    /// Without a lookup, `$` is an ordinary character.
+    lookup: Option<Lookup<'a>>,
+    /// Keeps unquoted expansions in one word, as inside `[[ ]]`.
+    no_field_splitting: bool,
 }
 
 #[derive(Default)]
 struct ParseInfo {
     parse_state: ParseState,
     parsed_buffer: Vec<u8>,
-    parsed_redirect: Vec<String>,
-    final_parsed_input: Vec<String>,
+    /// The targets of redirections, with the descriptor each is for and whether it appends.
+    parsed_redirect: Vec<(u8, bool, OsString)>,
+    redirect_descriptor: u8,
+    redirect_append: bool,
+    final_parsed_input: Vec<OsString>,
     escaped: bool,
     is_word_done: bool,
+    /// Whether the current word had quotes, which makes it a word even if it is empty, like `''`.
+    word_quoted: bool,
+    /// Whether the current word had a backslash or an expansion, so it cannot start with a variable name.
+    word_escaped: bool,
+    /// Whether the current word is a `NAME=value` assignment, with `NAME=` written out plainly.
+    word_assignment: bool,
+    assignments: usize,
+    parameter_name: Vec<u8>,
+    parameter_braced: bool,
 }
 
 #[derive(Default)]
@@ -43,9 +114,13 @@ enum ParseState {
     DoubleQuote,
     PotentialRedirect,
     Redirect,
+    /// After a `$`; `quoted` tells whether we came from inside double quotes, and are going back there.
+    Parameter {
+        quoted: bool,
+    },
 }
 
-impl Parser {
+impl<'a> Parser<'a> {
     const SINGLE_QUOTE: u8 = b'\'';
     const DOUBLE_QUOTE: u8 = b'\"';
     const WHITESPACE: u8 = b' ';
@@ -53,6 +128,12 @@ impl Parser {
     const GRAVE: u8 = b'`';
     const DOLLAR_SIGN: u8 = b'$';
     const REDIRECT: u8 = b'>';
+    const OPEN_BRACE: u8 = b'{';
+    const CLOSE_BRACE: u8 = b'}';
+    /// Parameters whose name is a single character that cannot start a variable name.
+    const SPECIAL_PARAMETERS: [u8; 7] = [b'?', b'$', b'!', b'#', b'@', b'*', b'-'];
+    /// Unquoted expansions are split into several words on these.
+    const FIELD_SEPARATORS: [u8; 3] = [b' ', b'\t', b'\n'];
 
     const SPECIAL_CHARS: [u8; 4] = [
         Self::GRAVE,
@@ -61,76 +142,146 @@ impl Parser {
         Self::DOLLAR_SIGN,
     ];
 
+    pub fn with_lookup(lookup: Lookup<'a>) -> Self {
+        Parser {
+            lookup: Some(lookup),
+            ..Default::default()
+        }
+    }
+
+    pub fn without_field_splitting(mut self) -> Self {
+        self.no_field_splitting = true;
+        self
+    }
+
     // This is absolutely atrocious.
-    pub fn parse_input(&mut self, input: &str) -> ParsedCommand {
-        if input.bytes().all(|b| b == Parser::WHITESPACE) {
+    pub fn parse_input(&mut self, input: impl AsRef<[u8]>) -> ParsedCommand {
+        let input = input.as_ref();
+        if input.iter().all(|&b| b == Parser::WHITESPACE) {
             return ParsedCommand::default();
         }
 
         self.parsing_data = ParseInfo::default();
 
-        for (index, char) in input.bytes().enumerate() {
+        for (index, &char) in input.iter().enumerate() {
             if self.parsing_data.is_word_done {
                 self.handle_word_done();
             }
 
-            match self.parsing_data.parse_state {
-                ParseState::PotentialRedirect => self.handle_potential_redirect(char),
-                ParseState::Redirect => self.handle_redirect(char),
-                ParseState::DoubleQuote => self.handle_double_quote(char),
-                ParseState::SingleQuote => self.handle_single_quote(char),
-                ParseState::Normal => self.handle_normal_parse_state(char, input, index),
-            }
+            self.handle_char(char, input, index);
+        }
+
+        if let ParseState::Parameter { quoted } = self.parsing_data.parse_state {
+            self.expand_parameter(quoted);
         }
 
-        if !self.parsing_data.parsed_buffer.is_empty() {
+        if !self.parsing_data.parsed_buffer.is_empty() || self.parsing_data.word_quoted {
             self.handle_non_empty_parsed_buffer();
         }
 
         self.get_parse_result()
     }
 
+    fn handle_char(&mut self, char: u8, input: &[u8], index: usize) {
+        match self.parsing_data.parse_state {
+            ParseState::PotentialRedirect => self.handle_potential_redirect(char, input, index),
+            ParseState::Redirect => self.handle_redirect(char),
+            ParseState::DoubleQuote => self.handle_double_quote(char),
+            ParseState::SingleQuote => self.handle_single_quote(char),
+            ParseState::Normal => self.handle_normal_parse_state(char, input, index),
+            ParseState::Parameter { quoted } => self.handle_parameter(char, quoted, input, index),
+        }
+    }
+
     fn get_parse_result(&self) -> ParsedCommand {
-        ParsedCommand {
-            cmd: Some(self.parsing_data.final_parsed_input[0].clone()),
+        let mut result = ParsedCommand {
+            // Empty if the whole input expanded to nothing.
+            cmd: self.parsing_data.final_parsed_input.first().cloned(),
             args: if self.parsing_data.final_parsed_input.len() > 1 {
                 Some(self.parsing_data.final_parsed_input[1..].to_vec())
             } else {
                 None
             },
-            output: if self.parsing_data.parsed_redirect.is_empty() {
-                None
-            } else {
-                Some(PathBuf::from(
-                    self.parsing_data.parsed_redirect[0].clone().trim(),
-                ))
-            },
-            errorout: None,
+            assignments: self.parsing_data.assignments,
+            ..Default::default()
+        };
+        for &(descriptor, append, ref target) in &self.parsing_data.parsed_redirect {
+            let target = target.as_bytes().trim_ascii();
+            result.redirects.push(match descriptor {
+                2 if target == b"&1" => Redirect::ErrorsToOutput,
+                _ => Redirect::File {
+                    descriptor,
+                    path: PathBuf::from(OsStr::from_bytes(target)),
+                    append,
+                },
+            });
         }
+        result
+    }
+
+    fn start_redirect(&mut self, descriptor: u8) {
+        self.parsing_data.redirect_descriptor = descriptor;
+        self.parsing_data.redirect_append = false;
+        self.parsing_data.parse_state = ParseState::Redirect;
+    }
+
+    fn push_redirect(&mut self, target: OsString) {
+        let descriptor = self.parsing_data.redirect_descriptor;
+        let append = self.parsing_data.redirect_append;
+        self.parsing_data
+            .parsed_redirect
+            .push((descriptor, append, target));
     }
 
     fn handle_non_empty_parsed_buffer(&mut self) {
         match self.parsing_data.parse_state {
-            ParseState::Redirect => self.parsing_data.parsed_redirect.push(
-                String::from_utf8(self.parsing_data.parsed_buffer.clone())
-                    .expect("Non-UTF8 encountered."),
-            ),
-            _ => self.parsing_data.final_parsed_input.push(
-                String::from_utf8(self.parsing_data.parsed_buffer.clone())
-                    .expect("Non-UTF8 encountered."),
-            ),
+            ParseState::Redirect => {
+                let word = self.take_word();
+                self.push_redirect(word);
+            }
+            _ => self.push_word(),
+        }
+    }
+
+    fn take_word(&mut self) -> OsString {
+        self.parsing_data.word_quoted = false;
+        self.parsing_data.word_escaped = false;
+        self.parsing_data.word_assignment = false;
+        OsString::from_vec(mem::take(&mut self.parsing_data.parsed_buffer))
+    }
+
+    /// Ends the current word as one of the command's, counting it if it is a leading assignment.
+    fn push_word(&mut self) {
+        let assignment = self.parsing_data.word_assignment;
+        let word = self.take_word();
+        let data = &mut self.parsing_data;
+        if assignment && data.assignments == data.final_parsed_input.len() {
+            data.assignments += 1;
         }
-        self.parsing_data.parsed_buffer.clear();
+        data.final_parsed_input.push(word);
+    }
+
+    /// Whether an `=` read now makes the current word an assignment.
+    fn starts_assignment(&self) -> bool {
+        let data = &self.parsing_data;
+        !data.word_quoted
+            && !data.word_escaped
+            && !data.word_assignment
+            && std::str::from_utf8(&data.parsed_buffer).is_ok_and(vars::is_valid_name)
     }
 
-    fn handle_potential_redirect(&mut self, char: u8) {
+    fn handle_potential_redirect(&mut self, char: u8, input: &[u8], index: usize) {
         match char {
             Parser::REDIRECT => {
                 // Since this was truly a redirect, we pop the number from the parsed buffer
-                self.parsing_data.parsed_buffer.pop();
-                self.parsing_data.parse_state = ParseState::Redirect
+                let descriptor = self.parsing_data.parsed_buffer.pop();
+                self.start_redirect(if descriptor == Some(b'2') { 2 } else { 1 });
+            }
+            _ => {
+                // Just a digit, so this char is parsed like any other.
+                self.parsing_data.parse_state = ParseState::Normal;
+                self.handle_normal_parse_state(char, input, index);
             }
-            _ => self.parsing_data.parse_state = ParseState::Normal,
         }
     }
 
@@ -156,6 +307,9 @@ impl Parser {
                 // We exit DoubleQuote state if new char is double quote; enter escaped state if current char is backslash.
                 Parser::DOUBLE_QUOTE => self.parsing_data.parse_state = ParseState::Normal,
                 Parser::BACKSLASH => self.parsing_data.escaped = true,
+                Parser::DOLLAR_SIGN if self.lookup.is_some() => {
+                    self.parsing_data.parse_state = ParseState::Parameter { quoted: true }
+                }
                 _ => self.parsing_data.parsed_buffer.push(char),
             }
         }
@@ -163,18 +317,12 @@ impl Parser {
 
     fn handle_word_done(&mut self) {
         if let ParseState::Redirect = self.parsing_data.parse_state {
-            self.parsing_data.parsed_redirect.push(
-                String::from_utf8(self.parsing_data.parsed_buffer.clone())
-                    .expect("Non-UTF8 encountered."),
-            );
+            let word = self.take_word();
+            self.push_redirect(word);
             self.parsing_data.parse_state = ParseState::Normal;
         } else {
-            self.parsing_data.final_parsed_input.push(
-                String::from_utf8(self.parsing_data.parsed_buffer.clone())
-                    .expect("Non-UTF8 encountered."),
-            );
+            self.push_word();
         }
-        self.parsing_data.parsed_buffer.clear();
         self.parsing_data.is_word_done = false;
     }
 
@@ -185,44 +333,154 @@ impl Parser {
             }
 
             Parser::WHITESPACE => (),
+            // `>>` right after `>`.
+            Parser::REDIRECT
+                if self.parsing_data.parsed_buffer.is_empty()
+                    && !self.parsing_data.redirect_append =>
+            {
+                self.parsing_data.redirect_append = true;
+            }
             _ => {
                 self.parsing_data.parsed_buffer.push(char);
             }
         }
     }
 
-    fn handle_normal_parse_state(&mut self, char: u8, input: &str, index: usize) {
+    fn handle_normal_parse_state(&mut self, char: u8, input: &[u8], index: usize) {
         match char {
             _ if self.parsing_data.escaped => {
                 self.parsing_data.parsed_buffer.push(char);
                 self.parsing_data.escaped = false;
             }
-            b'1' | b'2' => {
+            // Only a digit that starts a word can be a file descriptor.
+            b'1' | b'2' if self.parsing_data.parsed_buffer.is_empty() => {
                 // We will later pop the char if this is truly a redirect (will be known at next iteration)
                 self.parsing_data.parsed_buffer.push(char);
                 self.parsing_data.parse_state = ParseState::PotentialRedirect;
             }
-            Parser::REDIRECT => {
-                self.parsing_data.parse_state = ParseState::Redirect;
-            }
-            Parser::WHITESPACE if !self.parsing_data.parsed_buffer.is_empty() => {
+            Parser::REDIRECT => self.start_redirect(1),
+            Parser::WHITESPACE
+                if !self.parsing_data.parsed_buffer.is_empty() || self.parsing_data.word_quoted =>
+            {
                 self.parsing_data.is_word_done = true;
             }
             Parser::WHITESPACE if !self.parsing_data.final_parsed_input.is_empty() => (),
             Parser::BACKSLASH => {
                 self.parsing_data.escaped = true;
+                self.parsing_data.word_escaped = true;
             }
-            Parser::SINGLE_QUOTE if input[index + 1..].contains("\'") => {
+            Parser::SINGLE_QUOTE if input[index + 1..].contains(&Parser::SINGLE_QUOTE) => {
+                self.parsing_data.word_quoted = true;
                 self.parsing_data.parse_state = ParseState::SingleQuote;
             }
             Parser::SINGLE_QUOTE => (),
-            Parser::DOUBLE_QUOTE if input[index + 1..].contains("\"") => {
+            Parser::DOUBLE_QUOTE if input[index + 1..].contains(&Parser::DOUBLE_QUOTE) => {
+                self.parsing_data.word_quoted = true;
                 self.parsing_data.parse_state = ParseState::DoubleQuote;
             }
             Parser::DOUBLE_QUOTE => (),
+            Parser::DOLLAR_SIGN if self.lookup.is_some() => {
+                self.parsing_data.word_escaped = true;
+                self.parsing_data.parse_state = ParseState::Parameter { quoted: false };
+            }
+            b'=' if self.starts_assignment() => {
+                self.parsing_data.word_assignment = true;
+                self.parsing_data.parsed_buffer.push(char);
+            }
+            _ => {
+                self.parsing_data.parsed_buffer.push(char);
+            }
+        }
+    }
+
+    fn handle_parameter(&mut self, char: u8, quoted: bool, input: &[u8], index: usize) {
+        let name = &mut self.parsing_data.parameter_name;
+        match char {
+            Parser::OPEN_BRACE if name.is_empty() && !self.parsing_data.parameter_braced => {
+                self.parsing_data.parameter_braced = true;
+            }
+            Parser::CLOSE_BRACE if self.parsing_data.parameter_braced => {
+                self.expand_parameter(quoted);
+            }
+            _ if self.parsing_data.parameter_braced => name.push(char),
+            // `$?`, `$$`, `$1`, ...: the name is this single character.
+            _ if name.is_empty()
+                && (Parser::SPECIAL_PARAMETERS.contains(&char) || char.is_ascii_digit()) =>
+            {
+                name.push(char);
+                self.expand_parameter(quoted);
+            }
+            _ if char.is_ascii_alphabetic() || char == b'_' => name.push(char),
+            _ if char.is_ascii_digit() && !name.is_empty() => name.push(char),
             _ => {
+                // The name ended right before this char, which belongs to the state we came from.
+                self.expand_parameter(quoted);
+                self.handle_char(char, input, index);
+            }
+        }
+    }
+
+    /// Replaces the parameter that was just read with its value.
+    fn expand_parameter(&mut self, quoted: bool) {
+        self.parsing_data.parse_state = if quoted {
+            ParseState::DoubleQuote
+        } else {
+            ParseState::Normal
+        };
+        self.parsing_data.parameter_braced = false;
+        let name = mem::take(&mut self.parsing_data.parameter_name);
+        if name.is_empty() {
+            // A lone `$` is just a dollar sign.
+            self.parsing_data.parsed_buffer.push(Parser::DOLLAR_SIGN);
+            return;
+        }
+        if quoted && !self.no_field_splitting && name == b"@" {
+            self.expand_positional();
+            return;
+        }
+
+        let value = String::from_utf8(name)
+            .ok()
+            .and_then(|name| self.lookup.and_then(|lookup| lookup(&name)))
+            .unwrap_or_default();
+        // The value of an assignment is not split, even unquoted.
+        if quoted || self.no_field_splitting || self.parsing_data.word_assignment {
+            self.parsing_data
+                .parsed_buffer
+                .extend_from_slice(value.as_bytes());
+            return;
+        }
+        for &char in value.as_bytes() {
+            if !Parser::FIELD_SEPARATORS.contains(&char) {
                 self.parsing_data.parsed_buffer.push(char);
+            } else if !self.parsing_data.parsed_buffer.is_empty() {
+                self.push_word();
+            }
+        }
+    }
+
+    /// Expands `"$@"` to a word for each positional parameter, looked up one at a time to keep them apart.
+    fn expand_positional(&mut self) {
+        let Some(lookup) = self.lookup else {
+            return;
+        };
+        let count = lookup("#")
+            .and_then(|count| count.to_str()?.parse::<usize>().ok())
+            .unwrap_or(0);
+        if count == 0 && self.parsing_data.parsed_buffer.is_empty() {
+            // With no parameters, `"$@"` is no word at all rather than an empty one.
+            self.parsing_data.word_quoted = false;
+            return;
+        }
+        for index in 1..=count {
+            if index > 1 {
+                self.push_word();
             }
+            let value = lookup(&index.to_string()).unwrap_or_default();
+            self.parsing_data
+                .parsed_buffer
+                .extend_from_slice(value.as_bytes());
+            self.parsing_data.word_quoted = true;
         }
     }
 }
@@ -236,7 +494,7 @@ mod without_quotes {
         let mut parser = Parser::default();
         let input = r#"test\ \ \ "#;
         let result = parser.parse_input(input);
-        let expected = "test   ".to_string();
+        let expected = OsString::from("test   ");
 
         assert_eq!(
             result.cmd.expect("Cmd not found: {input}, {result.cmd}"),
@@ -249,7 +507,7 @@ mod without_quotes {
         let mut parser = Parser::default();
         let input = r#"test\'\'\'"#;
         let result = parser.parse_input(input);
-        let expected = "test'''".to_string();
+        let expected = OsString::from("test'''");
         assert_eq!(
             result.cmd.expect("Cmd not found: {input}, {result.cmd}"),
             expected
@@ -257,6 +515,42 @@ mod without_quotes {
     }
 }
 
+#[cfg(test)]
+mod digits {
+    use super::*;
+
+    #[test]
+    fn are_kept_when_not_redirecting() {
+        let mut parser = Parser::default();
+        let input = "echo 123 a2b 1";
+        let result = parser.parse_input(input);
+        let expected = ParsedCommand {
+            cmd: Some("echo".into()),
+            args: Some(vec!["123".into(), "a2b".into(), "1".into()]),
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn redirect_after_descriptor() {
+        let mut parser = Parser::default();
+        let input = "echo 1> out.txt";
+        let result = parser.parse_input(input);
+        let expected = ParsedCommand {
+            cmd: Some("echo".into()),
+            args: None,
+            redirects: vec![Redirect::File {
+                descriptor: 1,
+                path: PathBuf::from("out.txt"),
+                append: false,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+}
+
 #[cfg(test)]
 mod single_quotes {
 
@@ -268,10 +562,21 @@ mod single_quotes {
         let input = "'hellooooo    '      test";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hellooooo    ".to_string()),
-            args: Some(vec!["test".to_string()]),
-            output: None,
-            errorout: None,
+            cmd: Some("hellooooo    ".into()),
+            args: Some(vec!["test".into()]),
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn empty_quotes_are_a_word() {
+        let mut parser = Parser::default();
+        let result = parser.parse_input("trap '' \"\" TERM");
+        let expected = ParsedCommand {
+            cmd: Some("trap".into()),
+            args: Some(vec!["".into(), "".into(), "TERM".into()]),
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -282,10 +587,9 @@ mod single_quotes {
         let input = "'hello''test'";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hellotest".to_string()),
+            cmd: Some("hellotest".into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -296,10 +600,9 @@ mod single_quotes {
         let input = "'hello'";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hello".to_string()),
+            cmd: Some("hello".into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -310,10 +613,9 @@ mod single_quotes {
         let input = "'hello world'";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hello world".to_string()),
+            cmd: Some("hello world".into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -324,10 +626,9 @@ mod single_quotes {
         let input = "'hello   '";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hello   ".to_string()),
+            cmd: Some("hello   ".into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -338,10 +639,9 @@ mod single_quotes {
         let input = "'hello's";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hellos".to_string()),
+            cmd: Some("hellos".into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -352,10 +652,9 @@ mod single_quotes {
         let input = "'hello  's'test  t'";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hello  stest  t".to_string()),
+            cmd: Some("hello  stest  t".into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -366,10 +665,9 @@ mod single_quotes {
         let input = "'hellooo' s 'again   t'";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hellooo".to_string()),
-            args: Some(vec!["s".to_string(), "again   t".to_string()]),
-            output: None,
-            errorout: None,
+            cmd: Some("hellooo".into()),
+            args: Some(vec!["s".into(), "again   t".into()]),
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -381,10 +679,9 @@ mod single_quotes {
         let input = "'hellooo' s 'again   t";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("hellooo".to_string()),
-            args: Some(vec!["s".to_string(), "again".to_string(), "t".to_string()]),
-            output: None,
-            errorout: None,
+            cmd: Some("hellooo".into()),
+            args: Some(vec!["s".into(), "again".into(), "t".into()]),
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -400,10 +697,9 @@ mod double_quotes {
         let input = r#""\"hellooo\"""#;
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some(r#""hellooo""#.to_string()),
+            cmd: Some(r#""hellooo""#.into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -414,10 +710,9 @@ mod double_quotes {
         let input = r#""\"hellooo test\"""#;
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some(r#""hellooo test""#.to_string()),
+            cmd: Some(r#""hellooo test""#.into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -428,10 +723,9 @@ mod double_quotes {
         let input = r#""\"hellooo' test\"""#;
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some(r#""hellooo' test""#.to_string()),
+            cmd: Some(r#""hellooo' test""#.into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -442,10 +736,9 @@ mod double_quotes {
         let input = r#""\"hellooo \\ test\"""#;
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some(r#""hellooo \ test""#.to_string()),
+            cmd: Some(r#""hellooo \ test""#.into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -456,10 +749,9 @@ mod double_quotes {
         let input = r#""\"hellooo \' test\"""#;
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some(r#""hellooo \' test""#.to_string()),
+            cmd: Some(r#""hellooo \' test""#.into()),
             args: None,
-            output: None,
-            errorout: None,
+            ..Default::default()
         };
         assert_eq!(result, expected);
     }
@@ -475,11 +767,223 @@ mod redirect {
         let input = "echo > test.txt";
         let result = parser.parse_input(input);
         let expected = ParsedCommand {
-            cmd: Some("echo".to_string()),
+            cmd: Some("echo".into()),
             args: None,
-            output: Some(PathBuf::from("test.txt")),
-            errorout: None,
+            redirects: vec![Redirect::File {
+                descriptor: 1,
+                path: PathBuf::from("test.txt"),
+                append: false,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+}
+
+#[cfg(test)]
+mod error_redirect {
+    use super::*;
+
+    fn file(descriptor: u8, path: &str, append: bool) -> Redirect {
+        Redirect::File {
+            descriptor,
+            path: PathBuf::from(path),
+            append,
+        }
+    }
+
+    #[test]
+    fn appends_and_descriptors() {
+        let mut parser = Parser::default();
+        let result = parser.parse_input("ls >> out.txt 2> err.txt");
+        assert_eq!(
+            result.redirects,
+            vec![file(1, "out.txt", true), file(2, "err.txt", false)]
+        );
+
+        let result = Parser::default().parse_input("ls 2>>err.txt > out.txt");
+        assert_eq!(
+            result.redirects,
+            vec![file(2, "err.txt", true), file(1, "out.txt", false)]
+        );
+    }
+
+    #[test]
+    fn errors_to_output() {
+        let mut parser = Parser::default();
+        let mut result = parser.parse_input("ls 2>&1 > out.txt");
+        assert_eq!(
+            result.redirects,
+            vec![Redirect::ErrorsToOutput, file(1, "out.txt", false)]
+        );
+        assert_eq!(result.output(), Some(&PathBuf::from("out.txt")));
+        let redirects = result.take_redirects();
+        assert!(redirects.has_redirects());
+        assert!(!result.has_redirects());
+        assert_eq!(result.cmd, Some("ls".into()));
+    }
+}
+
+#[cfg(test)]
+mod non_utf8 {
+    use super::*;
+
+    #[test]
+    fn latin1_bytes_round_trip() {
+        let mut parser = Parser::default();
+        let input = b"cat caf\xe9 'na\xefve' > r\xe9sum\xe9";
+        let result = parser.parse_input(input);
+        let expected = ParsedCommand {
+            cmd: Some("cat".into()),
+            args: Some(vec![
+                OsString::from_vec(b"caf\xe9".to_vec()),
+                OsString::from_vec(b"na\xefve".to_vec()),
+            ]),
+            redirects: vec![Redirect::File {
+                descriptor: 1,
+                path: PathBuf::from(OsString::from_vec(b"r\xe9sum\xe9".to_vec())),
+                append: false,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+}
+
+#[cfg(test)]
+mod parameters {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<OsString> {
+        match name {
+            "HOME" => Some("/home/me".into()),
+            "?" => Some("127".into()),
+            "LIST" => Some("a  b c".into()),
+            "#" => Some("2".into()),
+            "1" => Some("a b".into()),
+            "2" => Some("c".into()),
+            "@" | "*" => Some("a b c".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn unquoted_and_braced() {
+        let mut parser = Parser::with_lookup(&lookup);
+        let result = parser.parse_input("echo $HOME/x ${HOME}y $?");
+        let expected = ParsedCommand {
+            cmd: Some("echo".into()),
+            args: Some(vec!["/home/me/x".into(), "/home/mey".into(), "127".into()]),
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn unquoted_is_split_into_words() {
+        let mut parser = Parser::with_lookup(&lookup);
+        let result = parser.parse_input("echo x$LIST");
+        let expected = ParsedCommand {
+            cmd: Some("echo".into()),
+            args: Some(vec!["xa".into(), "b".into(), "c".into()]),
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn kept_whole_without_field_splitting() {
+        let mut parser = Parser::with_lookup(&lookup).without_field_splitting();
+        let result = parser.parse_input("x$LIST");
+        assert_eq!(result.cmd, Some("xa  b c".into()));
+        assert_eq!(result.args, None);
+    }
+
+    #[test]
+    fn double_quoted_is_one_word() {
+        let mut parser = Parser::with_lookup(&lookup);
+        let result = parser.parse_input(r#"echo "$LIST $UNSET." '$HOME' \$HOME $"#);
+        let expected = ParsedCommand {
+            cmd: Some("echo".into()),
+            args: Some(vec![
+                "a  b c .".into(),
+                "$HOME".into(),
+                "$HOME".into(),
+                "$".into(),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn quoted_at_is_a_word_per_parameter() {
+        let mut parser = Parser::with_lookup(&lookup);
+        let result = parser.parse_input(r#"echo "<$@>" "$*" $@"#);
+        let expected = ParsedCommand {
+            cmd: Some("echo".into()),
+            args: Some(vec![
+                "<a b".into(),
+                "c>".into(),
+                "a b c".into(),
+                "a".into(),
+                "b".into(),
+                "c".into(),
+            ]),
+            ..Default::default()
         };
         assert_eq!(result, expected);
+
+        let unset = |name: &str| (name == "#").then(|| OsString::from("0"));
+        let mut parser = Parser::with_lookup(&unset);
+        assert_eq!(parser.parse_input(r#"echo "$@""#).args, None);
+    }
+
+    #[test]
+    fn empty_expansion_leaves_no_command() {
+        let mut parser = Parser::with_lookup(&lookup);
+        let result = parser.parse_input("$UNSET");
+        assert_eq!(result, ParsedCommand::default());
+    }
+}
+
+#[cfg(test)]
+mod assignments {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<OsString> {
+        (name == "1").then(|| OsString::from("a b"))
+    }
+
+    #[test]
+    fn leading_plain_assignments_are_counted() {
+        let mut parser = Parser::with_lookup(&lookup);
+        let result = parser.parse_input("A=1 B=$1 C='x y' cmd D=2");
+        assert_eq!(result.assignments, 3);
+        assert_eq!(result.cmd, Some("A=1".into()));
+        assert_eq!(
+            result.args,
+            Some(vec![
+                "B=a b".into(),
+                "C=x y".into(),
+                "cmd".into(),
+                "D=2".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn quoted_or_escaped_names_are_words() {
+        let mut parser = Parser::with_lookup(&lookup);
+        for input in [
+            "'A=1' cmd",
+            "\"A\"=1 cmd",
+            "\\A=1 cmd",
+            "$1=1 cmd",
+            "1A=1 cmd",
+        ] {
+            assert_eq!(parser.parse_input(input).assignments, 0, "{}", input);
+        }
+        assert_eq!(parser.parse_input("A=1 'B=2' C=3").assignments, 1);
     }
 }
@@ -0,0 +1,1311 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+
+use super::tokens::{self, Operator, Token};
+use crate::aliases::Aliases;
+use crate::conditional::{is_binary_operator, is_unary_operator};
+use crate::vars;
+
+/// Why input could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SyntaxError {
+    /// The input stops in the middle of a command, and may be completed by more lines.
+    #[error("syntax error: unexpected end of file")]
+    Incomplete,
+    #[error("syntax error near unexpected token `{0}'")]
+    Unexpected(String),
+}
+
+/**
+A command as the shell sees it before expanding anything. Simple commands keep their words raw,
+quotes included, and `Parser` expands them only when they are about to run.
+**/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(Vec<Vec<u8>>),
+    /// `if`, followed by any `elif`s, each a condition and the list it guards.
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while`, or `until` which loops as long as the condition fails.
+    While {
+        condition: List,
+        body: List,
+        until: bool,
+    },
+    /// `for name in words`, or `for name` over the positional parameters.
+    For {
+        name: String,
+        words: Option<Vec<Vec<u8>>>,
+        body: List,
+    },
+    Case {
+        word: Vec<u8>,
+        items: Vec<CaseItem>,
+    },
+    /// `{ list; }`
+    Group(List),
+    /// `( list )`, run in a forked copy of the shell.
+    Subshell(List),
+    /// A compound command with redirections after it, like `{ a; b; } > log`. They are raw words like those of simple commands.
+    Redirected {
+        command: Box<Command>,
+        redirects: Vec<Vec<u8>>,
+    },
+    /// `name() body` or `function name body`, where the body is a compound command.
+    Function {
+        name: String,
+        body: Box<Command>,
+    },
+    /// `[[ expression ]]`
+    Conditional(Conditional),
+}
+
+/// The expression inside `[[ ]]`, whose words are raw like those of simple commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conditional {
+    /// A word on its own, true when it expands to something other than the empty string.
+    Word(Vec<u8>),
+    Unary(String, Vec<u8>),
+    Binary(Vec<u8>, String, Vec<u8>),
+    Not(Box<Conditional>),
+    And(Box<Conditional>, Box<Conditional>),
+    Or(Box<Conditional>, Box<Conditional>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    pub patterns: Vec<Vec<u8>>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+/// What happens after the body of a matching `case` item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseTerminator {
+    /// `;;`: the `case` is done.
+    Break,
+    /// `;&`: the next body runs too, without testing its patterns.
+    FallThrough,
+    /// `;;&`: the next patterns are tested as well.
+    Continue,
+}
+
+/// And-or lists separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub and_or: AndOr,
+    /// Ended with `&`.
+    pub background: bool,
+}
+
+/// Pipelines joined by `&&` and `||`, which decide whether the pipeline after them runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// Commands joined by `|`, each reading what the one before it writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Started with `!`, which inverts the exit status.
+    pub negated: bool,
+}
+
+impl Pipeline {
+    /// The one command of a pipeline with neither pipes nor `!`.
+    pub fn as_single(&self) -> Option<&Command> {
+        match self.commands.as_slice() {
+            [command] if !self.negated => Some(command),
+            _ => None,
+        }
+    }
+}
+
+impl AndOr {
+    /// The one pipeline of an and-or list without `&&` or `||`.
+    pub fn as_single(&self) -> Option<&Pipeline> {
+        self.rest.is_empty().then_some(&self.first)
+    }
+}
+
+/// Whether a word is a redirection, like `>log` or `2>&1`, or the start of one, like `>`.
+fn is_redirect(word: &[u8]) -> bool {
+    matches!(word, [b'>', ..] | [b'1' | b'2', b'>', ..])
+}
+
+/// Words that start or end compound commands when they come first in a command.
+const CLOSING_KEYWORDS: [&[u8]; 8] = [
+    b"then", b"elif", b"else", b"fi", b"do", b"done", b"esac", b"}",
+];
+
+pub fn parse(input: &[u8]) -> Result<List, SyntaxError> {
+    parse_with_aliases(input, &Aliases::default())
+}
+
+/// Parses input with the command words that are aliases replaced by their values.
+pub fn parse_with_aliases(input: &[u8], aliases: &Aliases) -> Result<List, SyntaxError> {
+    let mut parser = SyntaxParser::new(input, aliases.clone())?;
+    let list = parser.list()?;
+    match parser.peek() {
+        None => Ok(list),
+        Some(token) => Err(unexpected(token)),
+    }
+}
+
+/// Parses input an item at a time, so that the aliases an item defines apply to the items after it.
+pub struct Items {
+    parser: SyntaxParser,
+}
+
+impl Items {
+    pub fn new(input: &[u8]) -> Result<Self, SyntaxError> {
+        let parser = SyntaxParser::new(input, Aliases::default())?;
+        Ok(Items { parser })
+    }
+
+    /// The next and-or list with the separator after it, or `None` at the end of the input.
+    pub fn next(&mut self, aliases: &Aliases) -> Result<Option<Item>, SyntaxError> {
+        self.parser.aliases = aliases.clone();
+        self.parser.skip_newlines();
+        match self.parser.peek() {
+            None => Ok(None),
+            Some(token) if self.parser.at_list_end() => Err(unexpected(token)),
+            Some(_) => self.parser.item().map(Some),
+        }
+    }
+}
+
+fn unexpected(token: &Token) -> SyntaxError {
+    match token {
+        Token::Word(word) => SyntaxError::Unexpected(String::from_utf8_lossy(word).into_owned()),
+        Token::Operator(operator) => SyntaxError::Unexpected(operator.as_str().to_string()),
+    }
+}
+
+struct SyntaxParser {
+    tokens: Vec<Token>,
+    /// The aliases each token came from, which are not expanded again in it.
+    expanded: Vec<Vec<String>>,
+    position: usize,
+    aliases: Aliases,
+}
+
+/// Whether a raw word is a `NAME=value` assignment, which may come before the command word.
+fn is_assignment(word: &[u8]) -> bool {
+    vars::parse_assignment(OsStr::from_bytes(word)).is_some()
+}
+
+impl SyntaxParser {
+    fn new(input: &[u8], aliases: Aliases) -> Result<Self, SyntaxError> {
+        let tokens = tokens::tokenize(input)?;
+        Ok(SyntaxParser {
+            expanded: vec![Vec::new(); tokens.len()],
+            tokens,
+            position: 0,
+            aliases,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_operator(&self, operator: Operator) -> bool {
+        self.peek() == Some(&Token::Operator(operator))
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword.as_bytes())
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_operator(Operator::Newline) {
+            self.position += 1;
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SyntaxError> {
+        match self.peek() {
+            _ if self.peek_keyword(keyword) => {
+                self.position += 1;
+                Ok(())
+            }
+            None => Err(SyntaxError::Incomplete),
+            Some(token) => Err(unexpected(token)),
+        }
+    }
+
+    fn expect_operator(&mut self, operator: Operator) -> Result<(), SyntaxError> {
+        match self.peek() {
+            _ if self.peek_operator(operator) => {
+                self.position += 1;
+                Ok(())
+            }
+            None => Err(SyntaxError::Incomplete),
+            Some(token) => Err(unexpected(token)),
+        }
+    }
+
+    fn expect_word(&mut self) -> Result<Vec<u8>, SyntaxError> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            None => Err(SyntaxError::Incomplete),
+            Some(token) => Err(unexpected(&token)),
+        }
+    }
+
+    /// Whether the list being parsed ends here, at the end of the input or of the construct around it.
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(Token::Word(word)) => CLOSING_KEYWORDS.contains(&word.as_slice()),
+            Some(Token::Operator(operator)) => matches!(
+                operator,
+                Operator::CloseParen
+                    | Operator::DoubleSemicolon
+                    | Operator::SemicolonAmpersand
+                    | Operator::DoubleSemicolonAmpersand
+            ),
+        }
+    }
+
+    fn list(&mut self) -> Result<List, SyntaxError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                return Ok(List { items });
+            }
+            items.push(self.item()?);
+        }
+    }
+
+    /// An and-or list and the `;`, `&` or newline after it, which only the end of a list can do without.
+    fn item(&mut self) -> Result<Item, SyntaxError> {
+        let and_or = self.and_or()?;
+        let background = self.peek_operator(Operator::Ampersand);
+        let separated = background
+            || self.peek_operator(Operator::Semicolon)
+            || self.peek_operator(Operator::Newline);
+        if separated {
+            self.position += 1;
+        }
+        if !separated && !self.at_list_end() {
+            return Err(unexpected(self.peek().expect("not at the end")));
+        }
+        Ok(Item { and_or, background })
+    }
+
+    /// A list that has to hold at least one command, like the body of a loop.
+    fn compound_list(&mut self) -> Result<List, SyntaxError> {
+        let list = self.list()?;
+        match self.peek() {
+            _ if !list.items.is_empty() => Ok(list),
+            None => Err(SyntaxError::Incomplete),
+            Some(token) => Err(unexpected(token)),
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, SyntaxError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(Token::Operator(Operator::And)) => Connector::And,
+                Some(Token::Operator(Operator::Or)) => Connector::Or,
+                _ => return Ok(AndOr { first, rest }),
+            };
+            self.position += 1;
+            // The pipeline after `&&` or `||` may be on the next line.
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, SyntaxError> {
+        let negated = self.peek_keyword("!");
+        if negated {
+            self.position += 1;
+        }
+        let mut commands = vec![self.command()?];
+        while self.peek_operator(Operator::Pipe) {
+            self.position += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands, negated })
+    }
+
+    /**
+    Replaces the command word, after any assignments, with the tokens of its value when it is an
+    alias, so that operators in the value take effect. The first word of the value is expanded in
+    turn, except for aliases it came from, so that `alias ls='ls -F'` does not loop. When a value
+    ends with a blank, the word after it is checked for an alias too.
+    **/
+    fn expand_aliases(&mut self) -> Result<(), SyntaxError> {
+        let mut index = self.position;
+        while matches!(self.tokens.get(index), Some(Token::Word(word)) if is_assignment(word)) {
+            index += 1;
+        }
+        // A function being defined keeps its name, and reserved words are never aliases.
+        let reserved = [
+            &b"if"[..],
+            b"while",
+            b"until",
+            b"for",
+            b"case",
+            b"{",
+            b"[[",
+            b"function",
+        ];
+        match self.tokens.get(index) {
+            Some(Token::Word(word)) if reserved.contains(&word.as_slice()) => Ok(()),
+            _ if self.tokens.get(index + 1) == Some(&Token::Operator(Operator::OpenParen)) => {
+                Ok(())
+            }
+            _ => self.expand_alias(index),
+        }
+    }
+
+    fn expand_alias(&mut self, index: usize) -> Result<(), SyntaxError> {
+        let alias = match self.tokens.get(index) {
+            Some(Token::Word(word)) => std::str::from_utf8(word)
+                .ok()
+                .filter(|name| !self.expanded[index].iter().any(|from| from == name))
+                .and_then(|name| Some((name.to_string(), self.aliases.get(name)?.to_string()))),
+            _ => None,
+        };
+        let Some((name, value)) = alias else {
+            return Ok(());
+        };
+
+        let tokens = tokens::tokenize(value.as_bytes())?;
+        let mut expanded = self.expanded[index].clone();
+        expanded.push(name);
+        let length = tokens.len();
+        self.expanded.splice(index..=index, vec![expanded; length]);
+        self.tokens.splice(index..=index, tokens);
+        let after = self.tokens.len() - (index + length);
+        if length > 0 {
+            self.expand_alias(index)?;
+        }
+        if value.ends_with([' ', '\t']) {
+            self.expand_alias(self.tokens.len() - after)?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self) -> Result<Command, SyntaxError> {
+        self.expand_aliases()?;
+        let command = match self.peek() {
+            None => return Err(SyntaxError::Incomplete),
+            Some(Token::Operator(Operator::OpenParen)) => self.subshell()?,
+            Some(Token::Operator(_)) => return Err(unexpected(self.peek().expect("peeked"))),
+            Some(Token::Word(word)) => match word.as_slice() {
+                b"if" => self.if_clause()?,
+                b"while" => self.while_clause(false)?,
+                b"until" => self.while_clause(true)?,
+                b"for" => self.for_clause()?,
+                b"case" => self.case_clause()?,
+                b"{" => self.brace_group()?,
+                b"[[" => self.conditional_command()?,
+                b"function" => return self.function_definition(true),
+                _ if self.tokens.get(self.position + 1)
+                    == Some(&Token::Operator(Operator::OpenParen)) =>
+                {
+                    return self.function_definition(false);
+                }
+                _ => return Ok(self.simple_command()),
+            },
+        };
+        self.redirects(command)
+    }
+
+    /// Takes the redirections after a compound command.
+    fn redirects(&mut self, command: Command) -> Result<Command, SyntaxError> {
+        let mut redirects = Vec::new();
+        while let Some(Token::Word(word)) = self.peek() {
+            if !is_redirect(word) {
+                break;
+            }
+            let word = self.expect_word()?;
+            // A bare `>` or `2>` has its target in the next word.
+            let bare = word.last() == Some(&b'>');
+            redirects.push(word);
+            if bare {
+                redirects.push(self.expect_word()?);
+            }
+        }
+        if redirects.is_empty() {
+            return Ok(command);
+        }
+        Ok(Command::Redirected {
+            command: Box::new(command),
+            redirects,
+        })
+    }
+
+    fn subshell(&mut self) -> Result<Command, SyntaxError> {
+        self.expect_operator(Operator::OpenParen)?;
+        let list = self.compound_list()?;
+        self.expect_operator(Operator::CloseParen)?;
+        Ok(Command::Subshell(list))
+    }
+
+    /// A command that can be the body of a function.
+    fn compound_command(&mut self) -> Result<Command, SyntaxError> {
+        match self.peek() {
+            None => Err(SyntaxError::Incomplete),
+            Some(Token::Word(word))
+                if [&b"if"[..], b"while", b"until", b"for", b"case", b"{", b"[["]
+                    .contains(&word.as_slice()) =>
+            {
+                self.command()
+            }
+            Some(Token::Operator(Operator::OpenParen)) => self.command(),
+            Some(token) => Err(unexpected(token)),
+        }
+    }
+
+    fn brace_group(&mut self) -> Result<Command, SyntaxError> {
+        self.expect_keyword("{")?;
+        let list = self.compound_list()?;
+        self.expect_keyword("}")?;
+        Ok(Command::Group(list))
+    }
+
+    fn conditional_command(&mut self) -> Result<Command, SyntaxError> {
+        self.expect_keyword("[[")?;
+        let expression = self.conditional_or()?;
+        self.skip_newlines();
+        self.expect_keyword("]]")?;
+        Ok(Command::Conditional(expression))
+    }
+
+    /// `||` inside `[[ ]]`, which binds more loosely than `&&`. Newlines may go anywhere between words.
+    fn conditional_or(&mut self) -> Result<Conditional, SyntaxError> {
+        let mut expression = self.conditional_and()?;
+        loop {
+            self.skip_newlines();
+            if !self.peek_operator(Operator::Or) {
+                return Ok(expression);
+            }
+            self.position += 1;
+            let right = self.conditional_and()?;
+            expression = Conditional::Or(Box::new(expression), Box::new(right));
+        }
+    }
+
+    fn conditional_and(&mut self) -> Result<Conditional, SyntaxError> {
+        let mut expression = self.conditional_not()?;
+        loop {
+            self.skip_newlines();
+            if !self.peek_operator(Operator::And) {
+                return Ok(expression);
+            }
+            self.position += 1;
+            let right = self.conditional_not()?;
+            expression = Conditional::And(Box::new(expression), Box::new(right));
+        }
+    }
+
+    fn conditional_not(&mut self) -> Result<Conditional, SyntaxError> {
+        self.skip_newlines();
+        if self.peek_keyword("!") {
+            self.position += 1;
+            return Ok(Conditional::Not(Box::new(self.conditional_not()?)));
+        }
+        self.conditional_primary()
+    }
+
+    fn conditional_primary(&mut self) -> Result<Conditional, SyntaxError> {
+        if self.peek_operator(Operator::OpenParen) {
+            self.position += 1;
+            let expression = self.conditional_or()?;
+            self.skip_newlines();
+            self.expect_operator(Operator::CloseParen)?;
+            return Ok(expression);
+        }
+        if self.peek_keyword("]]") {
+            return Err(unexpected(self.peek().expect("peeked")));
+        }
+        let word = self.expect_word()?;
+        let next = match self.peek() {
+            Some(Token::Word(next)) if next != b"]]" => next.clone(),
+            _ => return Ok(Conditional::Word(word)),
+        };
+        if is_binary_operator(&next) {
+            self.position += 1;
+            let operator = String::from_utf8_lossy(&next).into_owned();
+            return Ok(Conditional::Binary(word, operator, self.expect_word()?));
+        }
+        if is_unary_operator(&word) {
+            self.position += 1;
+            let operator = String::from_utf8_lossy(&word).into_owned();
+            return Ok(Conditional::Unary(operator, next));
+        }
+        Err(unexpected(self.peek().expect("peeked")))
+    }
+
+    /// `name() body`, or after the `function` keyword, where the parentheses may be left out.
+    fn function_definition(&mut self, keyword: bool) -> Result<Command, SyntaxError> {
+        if keyword {
+            self.position += 1;
+        }
+        let name = String::from_utf8_lossy(&self.expect_word()?).into_owned();
+        if !keyword || self.peek_operator(Operator::OpenParen) {
+            self.expect_operator(Operator::OpenParen)?;
+            self.expect_operator(Operator::CloseParen)?;
+        }
+        self.skip_newlines();
+        let body = Box::new(self.compound_command()?);
+        Ok(Command::Function { name, body })
+    }
+
+    fn simple_command(&mut self) -> Command {
+        let mut words = Vec::new();
+        while let Some(Token::Word(word)) = self.peek() {
+            words.push(word.clone());
+            self.position += 1;
+        }
+        Command::Simple(words)
+    }
+
+    fn if_clause(&mut self) -> Result<Command, SyntaxError> {
+        self.expect_keyword("if")?;
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.compound_list()?;
+            self.expect_keyword("then")?;
+            branches.push((condition, self.compound_list()?));
+            if self.peek_keyword("elif") {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        let otherwise = if self.peek_keyword("else") {
+            self.position += 1;
+            Some(self.compound_list()?)
+        } else {
+            None
+        };
+        self.expect_keyword("fi")?;
+        Ok(Command::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn while_clause(&mut self, until: bool) -> Result<Command, SyntaxError> {
+        self.position += 1;
+        let condition = self.compound_list()?;
+        self.expect_keyword("do")?;
+        let body = self.compound_list()?;
+        self.expect_keyword("done")?;
+        Ok(Command::While {
+            condition,
+            body,
+            until,
+        })
+    }
+
+    fn for_clause(&mut self) -> Result<Command, SyntaxError> {
+        self.expect_keyword("for")?;
+        let name = self.expect_word()?;
+        let name = match String::from_utf8(name) {
+            Ok(name) if crate::vars::is_valid_name(&name) => name,
+            Ok(name) => return Err(SyntaxError::Unexpected(name)),
+            Err(err) => {
+                return Err(SyntaxError::Unexpected(
+                    String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                ))
+            }
+        };
+
+        self.skip_newlines();
+        let words = if self.peek_keyword("in") {
+            self.position += 1;
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.position += 1;
+            }
+            if !self.peek_operator(Operator::Semicolon) {
+                self.expect_operator(Operator::Newline)?;
+            } else {
+                self.position += 1;
+            }
+            Some(words)
+        } else {
+            if self.peek_operator(Operator::Semicolon) {
+                self.position += 1;
+            }
+            None
+        };
+
+        self.skip_newlines();
+        self.expect_keyword("do")?;
+        let body = self.compound_list()?;
+        self.expect_keyword("done")?;
+        Ok(Command::For { name, words, body })
+    }
+
+    fn case_clause(&mut self) -> Result<Command, SyntaxError> {
+        self.expect_keyword("case")?;
+        let word = self.expect_word()?;
+        self.skip_newlines();
+        self.expect_keyword("in")?;
+
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_keyword("esac") {
+                self.position += 1;
+                return Ok(Command::Case { word, items });
+            }
+            if self.peek_operator(Operator::OpenParen) {
+                self.position += 1;
+            }
+            let mut patterns = vec![self.expect_word()?];
+            while self.peek_operator(Operator::Pipe) {
+                self.position += 1;
+                patterns.push(self.expect_word()?);
+            }
+            self.expect_operator(Operator::CloseParen)?;
+            let body = self.list()?;
+            let terminator = match self.peek() {
+                Some(Token::Operator(Operator::DoubleSemicolon)) => CaseTerminator::Break,
+                Some(Token::Operator(Operator::SemicolonAmpersand)) => CaseTerminator::FallThrough,
+                Some(Token::Operator(Operator::DoubleSemicolonAmpersand)) => {
+                    CaseTerminator::Continue
+                }
+                // The last item does not need a terminator.
+                _ if self.peek_keyword("esac") => {
+                    items.push(CaseItem {
+                        patterns,
+                        body,
+                        terminator: CaseTerminator::Break,
+                    });
+                    continue;
+                }
+                None => return Err(SyntaxError::Incomplete),
+                Some(token) => return Err(unexpected(token)),
+            };
+            self.position += 1;
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
+        }
+    }
+}
+
+/// Writes raw words separated by spaces.
+fn write_words(f: &mut fmt::Formatter<'_>, words: &[Vec<u8>]) -> fmt::Result {
+    let words: Vec<&[u8]> = words.iter().map(Vec::as_slice).collect();
+    write!(
+        f,
+        "{}",
+        String::from_utf8_lossy(&words.join(b" ".as_slice()))
+    )
+}
+
+/// Commands are shown on one line, the way they could be typed back in.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(words) => write_words(f, words),
+            Command::If {
+                branches,
+                otherwise,
+            } => {
+                for (index, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "if" } else { "elif" };
+                    write!(f, "{} {}; then {}; ", keyword, condition, body)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else {}; ", otherwise)?;
+                }
+                write!(f, "fi")
+            }
+            Command::While {
+                condition,
+                body,
+                until,
+            } => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{} {}; do {}; done", keyword, condition, body)
+            }
+            Command::For { name, words, body } => {
+                write!(f, "for {}", name)?;
+                if let Some(words) = words {
+                    write!(f, " in ")?;
+                    write_words(f, words)?;
+                }
+                write!(f, "; do {}; done", body)
+            }
+            Command::Case { word, items } => {
+                write!(f, "case {} in ", String::from_utf8_lossy(word))?;
+                for item in items {
+                    let patterns: Vec<&[u8]> = item.patterns.iter().map(Vec::as_slice).collect();
+                    let terminator = match item.terminator {
+                        CaseTerminator::Break => ";;",
+                        CaseTerminator::FallThrough => ";&",
+                        CaseTerminator::Continue => ";;&",
+                    };
+                    write!(
+                        f,
+                        "{}) {}{} ",
+                        String::from_utf8_lossy(&patterns.join(b"|".as_slice())),
+                        item.body,
+                        terminator
+                    )?;
+                }
+                write!(f, "esac")
+            }
+            Command::Group(list) => write!(f, "{{ {}; }}", list),
+            Command::Subshell(list) => write!(f, "({})", list),
+            Command::Redirected { command, redirects } => {
+                write!(f, "{} ", command)?;
+                write_words(f, redirects)
+            }
+            Command::Function { name, body } => write!(f, "{} () {}", name, body),
+            Command::Conditional(expression) => write!(f, "[[ {} ]]", expression),
+        }
+    }
+}
+
+impl fmt::Display for Conditional {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Parentheses go back where they are needed to keep the same grouping.
+        let grouped = |f: &mut fmt::Formatter<'_>, inner: &Conditional, needed: bool| {
+            if needed {
+                write!(f, "( {} )", inner)
+            } else {
+                write!(f, "{}", inner)
+            }
+        };
+        match self {
+            Conditional::Word(word) => write!(f, "{}", String::from_utf8_lossy(word)),
+            Conditional::Unary(operator, word) => {
+                write!(f, "{} {}", operator, String::from_utf8_lossy(word))
+            }
+            Conditional::Binary(left, operator, right) => write!(
+                f,
+                "{} {} {}",
+                String::from_utf8_lossy(left),
+                operator,
+                String::from_utf8_lossy(right)
+            ),
+            Conditional::Not(inner) => {
+                write!(f, "! ")?;
+                let needed = matches!(**inner, Conditional::And(..) | Conditional::Or(..));
+                grouped(f, inner, needed)
+            }
+            Conditional::And(left, right) => {
+                grouped(f, left, matches!(**left, Conditional::Or(..)))?;
+                write!(f, " && ")?;
+                grouped(
+                    f,
+                    right,
+                    matches!(**right, Conditional::And(..) | Conditional::Or(..)),
+                )
+            }
+            Conditional::Or(left, right) => {
+                write!(f, "{} || ", left)?;
+                grouped(f, right, matches!(**right, Conditional::Or(..)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (index, command) in self.commands.iter().enumerate() {
+            if index > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let connector = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {} {}", connector, pipeline)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+Writes commands out over several lines, indented by four spaces for each level of nesting,
+the way `type` shows the body of a function.
+**/
+struct Pretty<'a> {
+    out: &'a mut String,
+    indent: usize,
+}
+
+impl Pretty<'_> {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"    ".repeat(self.indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn nested(&mut self, list: &List) {
+        self.indent += 1;
+        self.list(list);
+        self.indent -= 1;
+    }
+
+    fn list(&mut self, list: &List) {
+        for (index, item) in list.items.iter().enumerate() {
+            let start = self.out.len();
+            match item.and_or.as_single().and_then(Pipeline::as_single) {
+                Some(command) => self.command(command),
+                None => self.line(&item.and_or.to_string()),
+            }
+            // Commands over several lines end with a keyword, which needs nothing after it.
+            let one_line = self.out[start..self.out.len() - 1].find('\n').is_none();
+            if item.background {
+                self.out.insert_str(self.out.len() - 1, " &");
+            } else if one_line && index + 1 < list.items.len() {
+                self.out.insert(self.out.len() - 1, ';');
+            }
+        }
+    }
+
+    fn command(&mut self, command: &Command) {
+        match command {
+            Command::Simple(_) | Command::Function { .. } | Command::Conditional(_) => {
+                self.line(&command.to_string())
+            }
+            Command::If {
+                branches,
+                otherwise,
+            } => {
+                for (index, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "if" } else { "elif" };
+                    self.line(&format!("{} {}; then", keyword, condition));
+                    self.nested(body);
+                }
+                if let Some(otherwise) = otherwise {
+                    self.line("else");
+                    self.nested(otherwise);
+                }
+                self.line("fi");
+            }
+            Command::While {
+                condition,
+                body,
+                until,
+            } => {
+                let keyword = if *until { "until" } else { "while" };
+                self.line(&format!("{} {}; do", keyword, condition));
+                self.nested(body);
+                self.line("done");
+            }
+            Command::For { name, words, body } => {
+                let header = match words {
+                    Some(words) => {
+                        let words: Vec<&[u8]> = words.iter().map(Vec::as_slice).collect();
+                        let words =
+                            String::from_utf8_lossy(&words.join(b" ".as_slice())).into_owned();
+                        format!("for {} in {}; do", name, words)
+                    }
+                    None => format!("for {}; do", name),
+                };
+                self.line(&header);
+                self.nested(body);
+                self.line("done");
+            }
+            Command::Case { word, items } => {
+                self.line(&format!("case {} in", String::from_utf8_lossy(word)));
+                self.indent += 1;
+                for item in items {
+                    let patterns: Vec<&[u8]> = item.patterns.iter().map(Vec::as_slice).collect();
+                    self.line(&format!(
+                        "{})",
+                        String::from_utf8_lossy(&patterns.join(b"|".as_slice()))
+                    ));
+                    self.nested(&item.body);
+                    self.line(match item.terminator {
+                        CaseTerminator::Break => ";;",
+                        CaseTerminator::FallThrough => ";&",
+                        CaseTerminator::Continue => ";;&",
+                    });
+                }
+                self.indent -= 1;
+                self.line("esac");
+            }
+            Command::Group(list) => {
+                self.line("{");
+                self.nested(list);
+                self.line("}");
+            }
+            Command::Subshell(list) => {
+                self.line("(");
+                self.nested(list);
+                self.line(")");
+            }
+            Command::Redirected { command, redirects } => {
+                self.command(command);
+                let redirects: Vec<&[u8]> = redirects.iter().map(Vec::as_slice).collect();
+                let redirects =
+                    String::from_utf8_lossy(&redirects.join(b" ".as_slice())).into_owned();
+                // After the last line of the command, before its newline.
+                self.out
+                    .insert_str(self.out.len() - 1, &format!(" {}", redirects));
+            }
+        }
+    }
+}
+
+impl Command {
+    /// The command over several indented lines, without a newline at the end.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        Pretty {
+            out: &mut out,
+            indent: 0,
+        }
+        .command(self);
+        out.pop();
+        out
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, item) in self.items.iter().enumerate() {
+            if index > 0 {
+                let previous = &self.items[index - 1];
+                write!(f, "{}", if previous.background { " " } else { "; " })?;
+            }
+            write!(f, "{}", item.and_or)?;
+            if item.background {
+                write!(f, " &")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod compound_commands {
+    use super::*;
+
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(words.iter().map(|word| word.as_bytes().to_vec()).collect())
+    }
+
+    fn list(commands: Vec<Command>) -> List {
+        List {
+            items: commands
+                .into_iter()
+                .map(|command| Item {
+                    and_or: AndOr {
+                        first: Pipeline {
+                            commands: vec![command],
+                            negated: false,
+                        },
+                        rest: Vec::new(),
+                    },
+                    background: false,
+                })
+                .collect(),
+        }
+    }
+
+    fn first_command(list: &List) -> &Command {
+        &list.items[0].and_or.first.commands[0]
+    }
+
+    #[test]
+    fn if_elif_else() {
+        let parsed = parse(b"if a; then b\nelif c; then d; else e; fi").unwrap();
+        let expected = list(vec![Command::If {
+            branches: vec![
+                (list(vec![simple(&["a"])]), list(vec![simple(&["b"])])),
+                (list(vec![simple(&["c"])]), list(vec![simple(&["d"])])),
+            ],
+            otherwise: Some(list(vec![simple(&["e"])])),
+        }]);
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn loops() {
+        let parsed =
+            parse(b"for x in 'a b' c; do echo $x; done; until false\ndo break; done").unwrap();
+        let expected = list(vec![
+            Command::For {
+                name: "x".to_string(),
+                words: Some(vec![b"'a b'".to_vec(), b"c".to_vec()]),
+                body: list(vec![simple(&["echo", "$x"])]),
+            },
+            Command::While {
+                condition: list(vec![simple(&["false"])]),
+                body: list(vec![simple(&["break"])]),
+                until: true,
+            },
+        ]);
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn case_items() {
+        let parsed = parse(b"case $1 in\n(a|b) one;;\nc) two;&\n*) ;;& esac").unwrap();
+        let Command::Case { word, items } = first_command(&parsed) else {
+            panic!("not a case: {:?}", parsed);
+        };
+        assert_eq!(word, b"$1");
+        assert_eq!(items[0].patterns, vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(items[1].terminator, CaseTerminator::FallThrough);
+        assert_eq!(items[2].body, List::default());
+        assert_eq!(items[2].terminator, CaseTerminator::Continue);
+    }
+
+    #[test]
+    fn keywords_only_count_first() {
+        let parsed = parse(b"echo if then fi").unwrap();
+        assert_eq!(parsed, list(vec![simple(&["echo", "if", "then", "fi"])]));
+    }
+
+    #[test]
+    fn unfinished_and_wrong() {
+        assert_eq!(parse(b"if true; then\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(parse(b"for x in a b\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(parse(b"case x in a) b;;\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(
+            parse(b"if true; fi"),
+            Err(SyntaxError::Unexpected("fi".to_string()))
+        );
+        assert_eq!(
+            parse(b"echo a; ; b"),
+            Err(SyntaxError::Unexpected(";".to_string()))
+        );
+        assert_eq!(
+            parse(b"done"),
+            Err(SyntaxError::Unexpected("done".to_string()))
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let parsed =
+            parse(b"f() { echo a; }\nfunction g\n{\n  f\n}\nfunction h() if x; then y; fi")
+                .unwrap();
+        let names: Vec<String> = parsed
+            .items
+            .iter()
+            .map(|item| match &item.and_or.first.commands[0] {
+                Command::Function { name, .. } => name.clone(),
+                command => panic!("not a function: {:?}", command),
+            })
+            .collect();
+        assert_eq!(names, ["f", "g", "h"]);
+        assert_eq!(
+            parse(b"f() echo a"),
+            Err(SyntaxError::Unexpected("echo".to_string()))
+        );
+        assert_eq!(parse(b"f() {\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(
+            parse(b"echo { }").unwrap(),
+            list(vec![simple(&["echo", "{", "}"])])
+        );
+    }
+
+    #[test]
+    fn shown_over_several_lines() {
+        let parsed =
+            parse(b"f() { for x in a b; do if [ $x ]; then echo $x; fi; done; wait; }").unwrap();
+        let expected = "\
+f () {
+    for x in a b; do
+        if [ $x ]; then
+            echo $x
+        fi
+    done
+    wait
+}";
+        let Command::Function { body, .. } = first_command(&parsed) else {
+            panic!("not a function: {:?}", parsed);
+        };
+        assert_eq!(format!("f () {}", body.pretty()), expected);
+    }
+
+    #[test]
+    fn pipelines_and_lists() {
+        let parsed = parse(b"! a | b && c ||\n d &").unwrap();
+        let item = &parsed.items[0];
+        assert!(item.background);
+        assert!(item.and_or.first.negated);
+        assert_eq!(
+            item.and_or.first.commands,
+            vec![simple(&["a"]), simple(&["b"])]
+        );
+        let connectors: Vec<Connector> = item.and_or.rest.iter().map(|(c, _)| *c).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        assert_eq!(parse(b"a |"), Err(SyntaxError::Incomplete));
+        assert_eq!(
+            parse(b"a && || b"),
+            Err(SyntaxError::Unexpected("||".to_string()))
+        );
+    }
+
+    #[test]
+    fn groups_and_redirects() {
+        let parsed = parse(b"(cd /; pwd) | cat; { a; b; } > log 2>&1").unwrap();
+        assert_eq!(
+            parsed.items[0].and_or.first.commands[0],
+            Command::Subshell(list(vec![simple(&["cd", "/"]), simple(&["pwd"])]))
+        );
+        assert_eq!(
+            parsed.items[1].and_or.first.commands[0],
+            Command::Redirected {
+                command: Box::new(Command::Group(list(vec![simple(&["a"]), simple(&["b"])]))),
+                redirects: vec![b">".to_vec(), b"log".to_vec(), b"2>&1".to_vec()],
+            }
+        );
+        assert_eq!(
+            parse(b"{ a; } b"),
+            Err(SyntaxError::Unexpected("b".to_string()))
+        );
+        assert_eq!(parse(b"( a"), Err(SyntaxError::Incomplete));
+    }
+
+    #[test]
+    fn shown_on_one_line() {
+        let parsed = parse(b"while true\ndo\n  sleep 1 &\n  wait\ndone").unwrap();
+        assert_eq!(parsed.to_string(), "while true; do sleep 1 & wait; done");
+    }
+
+    #[test]
+    fn conditionals() {
+        let word = |word: &str| Box::new(Conditional::Word(word.as_bytes().to_vec()));
+        let parsed = parse(b"[[ ! -f $x && ( a == b* ||\n  c ) ]]").unwrap();
+        let expected = Conditional::And(
+            Box::new(Conditional::Not(Box::new(Conditional::Unary(
+                "-f".to_string(),
+                b"$x".to_vec(),
+            )))),
+            Box::new(Conditional::Or(
+                Box::new(Conditional::Binary(
+                    b"a".to_vec(),
+                    "==".to_string(),
+                    b"b*".to_vec(),
+                )),
+                word("c"),
+            )),
+        );
+        assert_eq!(first_command(&parsed), &Command::Conditional(expected));
+        assert_eq!(parsed.to_string(), "[[ ! -f $x && ( a == b* || c ) ]]");
+        let parsed = parse(b"[[ $x =~ ^(a|b)$ ]]").unwrap();
+        assert_eq!(parsed.to_string(), "[[ $x =~ ^(a|b)$ ]]");
+        assert_eq!(parse(b"[[ a &&\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(
+            parse(b"[[ ]]"),
+            Err(SyntaxError::Unexpected("]]".to_string()))
+        );
+        assert_eq!(
+            parse(b"[[ a b ]]"),
+            Err(SyntaxError::Unexpected("b".to_string()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod alias_expansion {
+    use super::*;
+
+    fn aliases(pairs: &[(&str, &str)]) -> Aliases {
+        let mut aliases = Aliases::default();
+        for (name, value) in pairs {
+            aliases.set(name, value.to_string());
+        }
+        aliases
+    }
+
+    /// The input as it reads with its aliases expanded.
+    fn expanded(input: &str, aliases: &Aliases) -> String {
+        parse_with_aliases(input.as_bytes(), aliases)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn first_word_only() {
+        let aliases = aliases(&[("ll", "ls -l"), ("x", "y")]);
+        assert_eq!(expanded("ll -a x", &aliases), "ls -l -a x");
+        assert_eq!(expanded("echo ll", &aliases), "echo ll");
+        assert_eq!(expanded("'ll'", &aliases), "'ll'");
+        assert_eq!(
+            expanded("FOO=1 ll; x && ll", &aliases),
+            "FOO=1 ls -l; y && ls -l"
+        );
+        assert_eq!(expanded("ll() { x; }", &aliases), "ll () { y; }");
+    }
+
+    #[test]
+    fn chained_and_recursive() {
+        let aliases = aliases(&[
+            ("sudo", "sudo "),
+            ("ll", "ls -l"),
+            ("ls", "ls -F"),
+            ("a", "b"),
+            ("b", "a"),
+        ]);
+        assert_eq!(expanded("sudo ll /", &aliases), "sudo ls -F -l /");
+        assert_eq!(expanded("ll", &aliases), "ls -F -l");
+        // Each alias is expanded once, so mutual aliases stop where they started.
+        assert_eq!(expanded("a", &aliases), "a");
+    }
+
+    #[test]
+    fn operators_in_values() {
+        let aliases = aliases(&[("gs", "echo one; echo two"), ("count", "ls | wc")]);
+        let parsed = parse_with_aliases(b"gs", &aliases).unwrap();
+        assert_eq!(parsed.items.len(), 2);
+        assert_eq!(parsed.to_string(), "echo one; echo two");
+        let parsed = parse_with_aliases(b"count -l", &aliases).unwrap();
+        assert_eq!(parsed.items[0].and_or.first.commands.len(), 2);
+        assert_eq!(parsed.to_string(), "ls | wc -l");
+    }
+}
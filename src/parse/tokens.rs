@@ -0,0 +1,287 @@
+use super::syntax::SyntaxError;
+
+/// Splits input into words and operators. Words keep their quotes, since `Parser` takes care of those later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Word(Vec<u8>),
+    Operator(Operator),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// `;`
+    Semicolon,
+    /// `&`, which runs the command before it in the background.
+    Ampersand,
+    /// The end of a line, which separates commands like `;` does.
+    Newline,
+    /// `;;`, ending a `case` item.
+    DoubleSemicolon,
+    /// `;&`, falling through into the next `case` item.
+    SemicolonAmpersand,
+    /// `;;&`, going on to test the next `case` item.
+    DoubleSemicolonAmpersand,
+    /// `&&`, running the next pipeline if the one before succeeded.
+    And,
+    /// `||`, running the next pipeline if the one before failed.
+    Or,
+    /// `|`
+    Pipe,
+    /// `(`
+    OpenParen,
+    /// `)`
+    CloseParen,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Semicolon => ";",
+            Operator::Ampersand => "&",
+            Operator::Newline => "newline",
+            Operator::DoubleSemicolon => ";;",
+            Operator::SemicolonAmpersand => ";&",
+            Operator::DoubleSemicolonAmpersand => ";;&",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Pipe => "|",
+            Operator::OpenParen => "(",
+            Operator::CloseParen => ")",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tokenizer {
+    tokens: Vec<Token>,
+    word: Vec<u8>,
+    single_quoted: bool,
+    double_quoted: bool,
+    escaped: bool,
+    /// Set after `=~`, whose regular expression may hold `(`, `)` and `|` without quotes.
+    regex: bool,
+}
+
+impl Tokenizer {
+    fn finish_word(&mut self) {
+        if !self.word.is_empty() {
+            let word = std::mem::take(&mut self.word);
+            self.regex = word == b"=~";
+            self.tokens.push(Token::Word(word));
+        }
+    }
+
+    fn push_operator(&mut self, operator: Operator) {
+        self.finish_word();
+        self.tokens.push(Token::Operator(operator));
+    }
+}
+
+/// Fails with `SyntaxError::Incomplete` if the input ends inside quotes or right after a backslash.
+pub fn tokenize(input: &[u8]) -> Result<Vec<Token>, SyntaxError> {
+    let mut tokenizer = Tokenizer::default();
+    let mut index = 0;
+    while index < input.len() {
+        let char = input[index];
+        let next = input.get(index + 1).copied();
+        index += 1;
+
+        // A backslash at the end of a line joins it with the next, outside single quotes.
+        if char == b'\\' && next == Some(b'\n') && !tokenizer.single_quoted && !tokenizer.escaped {
+            // With nothing after it, the line it continues onto has yet to come.
+            if index + 1 == input.len() {
+                return Err(SyntaxError::Incomplete);
+            }
+            index += 1;
+            continue;
+        }
+        if tokenizer.escaped {
+            tokenizer.escaped = false;
+            tokenizer.word.push(char);
+            continue;
+        }
+        if tokenizer.single_quoted {
+            tokenizer.single_quoted = char != b'\'';
+            tokenizer.word.push(char);
+            continue;
+        }
+        if tokenizer.double_quoted {
+            match char {
+                b'"' => tokenizer.double_quoted = false,
+                b'\\' => tokenizer.escaped = true,
+                _ => (),
+            }
+            tokenizer.word.push(char);
+            continue;
+        }
+
+        match char {
+            // A comment runs to the end of the line.
+            b'#' if tokenizer.word.is_empty() => {
+                while index < input.len() && input[index] != b'\n' {
+                    index += 1;
+                }
+            }
+            b' ' | b'\t' => tokenizer.finish_word(),
+            b'\n' => tokenizer.push_operator(Operator::Newline),
+            b'(' | b')' | b'|' if tokenizer.regex => tokenizer.word.push(char),
+            b';' => {
+                let rest = &input[index..];
+                let operator = if rest.starts_with(b";&") {
+                    index += 2;
+                    Operator::DoubleSemicolonAmpersand
+                } else if rest.starts_with(b";") {
+                    index += 1;
+                    Operator::DoubleSemicolon
+                } else if rest.starts_with(b"&") {
+                    index += 1;
+                    Operator::SemicolonAmpersand
+                } else {
+                    Operator::Semicolon
+                };
+                tokenizer.push_operator(operator);
+            }
+            // `>&` duplicates a file descriptor, and is left to the parser.
+            b'&' if tokenizer.word.last() == Some(&b'>') => tokenizer.word.push(char),
+            b'&' | b'|' if input.get(index) == Some(&char) => {
+                index += 1;
+                let operator = if char == b'&' {
+                    Operator::And
+                } else {
+                    Operator::Or
+                };
+                tokenizer.push_operator(operator);
+            }
+            b'&' => tokenizer.push_operator(Operator::Ampersand),
+            b'|' => tokenizer.push_operator(Operator::Pipe),
+            b'(' => tokenizer.push_operator(Operator::OpenParen),
+            b')' => tokenizer.push_operator(Operator::CloseParen),
+            _ => {
+                match char {
+                    b'\'' => tokenizer.single_quoted = true,
+                    b'"' => tokenizer.double_quoted = true,
+                    b'\\' => tokenizer.escaped = true,
+                    _ => (),
+                }
+                tokenizer.word.push(char);
+            }
+        }
+    }
+    if tokenizer.single_quoted || tokenizer.double_quoted || tokenizer.escaped {
+        return Err(SyntaxError::Incomplete);
+    }
+    tokenizer.finish_word();
+    Ok(tokenizer.tokens)
+}
+
+#[cfg(test)]
+mod tokenize {
+    use super::*;
+
+    fn word(word: &str) -> Token {
+        Token::Word(word.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn operators_split_commands() {
+        let tokens = tokenize(b"sleep 1 &echo a;echo b").unwrap();
+        let expected = vec![
+            word("sleep"),
+            word("1"),
+            Token::Operator(Operator::Ampersand),
+            word("echo"),
+            word("a"),
+            Token::Operator(Operator::Semicolon),
+            word("echo"),
+            word("b"),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn quotes_and_escapes_are_kept() {
+        let tokens = tokenize(br#"echo 'a;b' "c & d" e\;f"#).unwrap();
+        let expected = vec![
+            word("echo"),
+            word("'a;b'"),
+            word(r#""c & d""#),
+            word(r"e\;f"),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn comments_and_descriptor_duplication() {
+        let tokens = tokenize(b"ls >&2 # not; run").unwrap();
+        let expected = vec![word("ls"), word(">&2")];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn case_operators() {
+        let tokens = tokenize(b"a|b) x;; c) y;& *) z;;&\n").unwrap();
+        let expected = vec![
+            word("a"),
+            Token::Operator(Operator::Pipe),
+            word("b"),
+            Token::Operator(Operator::CloseParen),
+            word("x"),
+            Token::Operator(Operator::DoubleSemicolon),
+            word("c"),
+            Token::Operator(Operator::CloseParen),
+            word("y"),
+            Token::Operator(Operator::SemicolonAmpersand),
+            word("*"),
+            Token::Operator(Operator::CloseParen),
+            word("z"),
+            Token::Operator(Operator::DoubleSemicolonAmpersand),
+            Token::Operator(Operator::Newline),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn and_or_and_pipes() {
+        let tokens = tokenize(b"a&&b||c|d&").unwrap();
+        let expected = vec![
+            word("a"),
+            Token::Operator(Operator::And),
+            word("b"),
+            Token::Operator(Operator::Or),
+            word("c"),
+            Token::Operator(Operator::Pipe),
+            word("d"),
+            Token::Operator(Operator::Ampersand),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn regular_expressions() {
+        let tokens = tokenize(b"[[ $x =~ ^(a|b)+$ ]] && (y)").unwrap();
+        let expected = vec![
+            word("[["),
+            word("$x"),
+            word("=~"),
+            word("^(a|b)+$"),
+            word("]]"),
+            Token::Operator(Operator::And),
+            Token::Operator(Operator::OpenParen),
+            word("y"),
+            Token::Operator(Operator::CloseParen),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn unfinished_input() {
+        assert_eq!(tokenize(b"echo 'a\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(tokenize(b"echo \"a"), Err(SyntaxError::Incomplete));
+        assert_eq!(tokenize(b"echo a\\"), Err(SyntaxError::Incomplete));
+        assert_eq!(tokenize(b"echo a\\\n"), Err(SyntaxError::Incomplete));
+        assert_eq!(
+            tokenize(b"echo a\\\nb").unwrap(),
+            vec![word("echo"), word("ab")]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::ffi::CString;
+
+/// Characters with a meaning in glob patterns, which stand for themselves when quoted.
+pub const GLOB_SPECIALS: &[u8] = b"*?[]\\";
+/// Characters with a meaning in extended regular expressions.
+pub const REGEX_SPECIALS: &[u8] = b".[]()*+?{}|^$\\";
+
+/// Whether `text` matches the glob `pattern`, where a backslash takes away the meaning of the next character.
+pub fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (Ok(pattern), Ok(text)) = (CString::new(pattern), CString::new(text)) else {
+        return false;
+    };
+    // SAFETY: both arguments are valid nul-terminated strings, which fnmatch only reads.
+    unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
+
+/// A regular expression that `regcomp` rejected.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("invalid regular expression")]
+pub struct InvalidRegex;
+
+/**
+Matches `text` against the extended regular expression `pattern`. On a match, returns what it
+matched followed by what each parenthesised group matched, empty for groups that took no part.
+**/
+pub fn regex_captures(pattern: &[u8], text: &[u8]) -> Result<Option<Vec<Vec<u8>>>, InvalidRegex> {
+    let (Ok(c_pattern), Ok(c_text)) = (CString::new(pattern), CString::new(text)) else {
+        return Err(InvalidRegex);
+    };
+    // glibc keeps the number of groups private, so there is room for one per parenthesis.
+    let groups = pattern.iter().filter(|&&b| b == b'(').count();
+    let mut matches = vec![
+        libc::regmatch_t {
+            rm_so: -1,
+            rm_eo: -1
+        };
+        groups + 1
+    ];
+    // SAFETY: regex_t is plain data that regcomp initialises, and it is freed once compiled;
+    // regexec writes at most `matches.len()` entries.
+    unsafe {
+        let mut regex: libc::regex_t = std::mem::zeroed();
+        if libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED) != 0 {
+            return Err(InvalidRegex);
+        }
+        let found = libc::regexec(
+            &regex,
+            c_text.as_ptr(),
+            matches.len(),
+            matches.as_mut_ptr(),
+            0,
+        ) == 0;
+        libc::regfree(&mut regex);
+        if !found {
+            return Ok(None);
+        }
+    }
+    let captures = matches
+        .iter()
+        .map(
+            |found| match (usize::try_from(found.rm_so), usize::try_from(found.rm_eo)) {
+                (Ok(start), Ok(end)) => text[start..end].to_vec(),
+                _ => Vec::new(),
+            },
+        )
+        .collect();
+    Ok(Some(captures))
+}
+
+/**
+Rewrites a raw word so that, once `Parser` has removed its quotes, the `specials` that were quoted
+come out escaped with a backslash. As a glob, `'*'` then matches a star, while `*` matches anything.
+**/
+pub fn quote_specials(word: &[u8], specials: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(word.len());
+    let mut chars = word.iter().copied();
+    while let Some(char) = chars.next() {
+        match char {
+            b'\'' => {
+                quoted.push(char);
+                for char in chars.by_ref() {
+                    if char == b'\'' {
+                        break;
+                    }
+                    if specials.contains(&char) {
+                        // Out of the quotes, `\\` leaves a backslash and `\c` the character.
+                        quoted.extend_from_slice(b"'\\\\\\");
+                        quoted.push(char);
+                        quoted.push(b'\'');
+                    } else {
+                        quoted.push(char);
+                    }
+                }
+                quoted.push(b'\'');
+            }
+            b'"' => {
+                quoted.push(char);
+                while let Some(char) = chars.next() {
+                    match char {
+                        b'"' => break,
+                        b'\\' => match chars.next() {
+                            Some(next @ (b'$' | b'`' | b'"' | b'\\')) => {
+                                push_double_quoted(&mut quoted, next, specials)
+                            }
+                            Some(next) => {
+                                push_double_quoted(&mut quoted, b'\\', specials);
+                                push_double_quoted(&mut quoted, next, specials);
+                            }
+                            None => quoted.push(b'\\'),
+                        },
+                        // A `$` still expands, but a quoted glob character stands for itself.
+                        b'$' | b'`' => quoted.push(char),
+                        _ => push_double_quoted(&mut quoted, char, specials),
+                    }
+                }
+                quoted.push(b'"');
+            }
+            b'\\' => match chars.next() {
+                Some(next) if specials.contains(&next) => {
+                    quoted.extend_from_slice(b"\\\\\\");
+                    quoted.push(next);
+                }
+                Some(next) => quoted.extend_from_slice(&[b'\\', next]),
+                None => quoted.push(b'\\'),
+            },
+            _ => quoted.push(char),
+        }
+    }
+    quoted
+}
+
+/// Pushes a character that stands for itself, inside double quotes.
+fn push_double_quoted(quoted: &mut Vec<u8>, char: u8, specials: &[u8]) {
+    if specials.contains(&char) {
+        quoted.extend_from_slice(b"\\\\");
+    }
+    if matches!(char, b'$' | b'`' | b'"' | b'\\') {
+        quoted.push(b'\\');
+    }
+    quoted.push(char);
+}
+
+#[cfg(test)]
+mod globs {
+    use super::*;
+    use crate::parse::Parser;
+
+    /// The pattern a raw word stands for, the way `case` reads it.
+    fn pattern(word: &str) -> Vec<u8> {
+        let parsed = Parser::default().parse_input(quote_specials(word.as_bytes(), GLOB_SPECIALS));
+        parsed.cmd.unwrap_or_default().into_encoded_bytes()
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"a?c", b"abc"));
+        assert!(matches(b"[a-c]x", b"bx"));
+        assert!(!matches(b"[a-c]x", b"dx"));
+        assert!(matches(b"\\*", b"*"));
+        assert!(!matches(b"\\*", b"a"));
+    }
+
+    #[test]
+    fn regular_expressions() {
+        assert_eq!(
+            regex_captures(b"([a-z]+)-([0-9]+)", b"x abc-12 y"),
+            Ok(Some(vec![
+                b"abc-12".to_vec(),
+                b"abc".to_vec(),
+                b"12".to_vec()
+            ]))
+        );
+        assert_eq!(
+            regex_captures(b"^a(b)?$", b"a"),
+            Ok(Some(vec![b"a".to_vec(), Vec::new()]))
+        );
+        assert_eq!(regex_captures(b"^z", b"abc"), Ok(None));
+        assert_eq!(regex_captures(b"(", b"abc"), Err(InvalidRegex));
+    }
+
+    #[test]
+    fn quoted_specials_are_literal() {
+        assert_eq!(pattern("*.rs"), b"*.rs");
+        assert_eq!(pattern("'*'.rs"), b"\\*.rs");
+        assert_eq!(pattern("\"a?\"b"), b"a\\?b");
+        assert_eq!(pattern(r"\[x"), b"\\[x");
+        assert_eq!(pattern(r"'\'"), b"\\\\");
+        assert_eq!(pattern(r#""\$\*""#), b"$\\\\\\*");
+        let regex = Parser::default().parse_input(quote_specials(b"'a.b'.", REGEX_SPECIALS));
+        assert_eq!(regex.cmd.unwrap_or_default(), "a\\.b.");
+    }
+}
@@ -0,0 +1,286 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// One bit per signal that arrived and has not been dealt with yet.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+/// One bit per signal ignored with `trap '' SIG`, which child processes keep ignoring.
+static IGNORED: AtomicU64 = AtomicU64::new(0);
+
+/// The signals by name, without their `SIG` prefix, in the order of their numbers.
+pub const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    #[cfg(target_os = "linux")]
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    #[cfg(target_os = "linux")]
+    ("PWR", libc::SIGPWR),
+    ("SYS", libc::SIGSYS),
+];
+
+/// The number of a signal given by number or by name, with or without `SIG` and in any case.
+pub fn signal_number(spec: &str) -> Option<libc::c_int> {
+    if let Ok(number) = spec.parse() {
+        return SIGNALS
+            .iter()
+            .any(|&(_, signal)| signal == number)
+            .then_some(number);
+    }
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|&(_, signal)| signal)
+}
+
+/// The name of a signal without its `SIG` prefix, like `TERM`.
+pub fn signal_name(signal: libc::c_int) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .find(|&&(_, number)| number == signal)
+        .map(|&(name, _)| name)
+}
+
+/**
+Signals an interactive shell does not let stop or kill it, though its children still can be. Those for
+reading from or writing to the terminal while in the background are part of job control.
+**/
+const INTERACTIVE_IGNORED: [libc::c_int; 4] =
+    [libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+/// Every signal whose disposition the shell changes, to be put back for child processes.
+const CHANGED: [libc::c_int; 8] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+    libc::SIGCHLD,
+    libc::SIGWINCH,
+    libc::SIGHUP,
+];
+
+extern "C" fn record(signal: libc::c_int) {
+    PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Catches `signal` with `record`. Only restarting handlers let system calls carry on once they return.
+fn catch(signal: libc::c_int, restart: bool) {
+    // SAFETY: the handler only touches an atomic, which is async-signal-safe, and the sigaction is fully initialised.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = record as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = if restart { libc::SA_RESTART } else { 0 };
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+/**
+Sets up the signal dispositions of the shell. Children finishing and the window being resized are
+noted for later. An interactive shell ignores `SIGQUIT`, `SIGTSTP`, `SIGTTIN` and `SIGTTOU`, and
+catches `SIGINT` so that Ctrl-C can cancel the line being typed; hanging up interrupts it too.
+**/
+pub fn init(interactive: bool) {
+    catch(libc::SIGCHLD, true);
+    catch(libc::SIGWINCH, true);
+    if interactive {
+        catch(libc::SIGINT, false);
+        catch(libc::SIGHUP, false);
+        for signal in INTERACTIVE_IGNORED {
+            // SAFETY: ignoring a signal has no effect on memory.
+            unsafe { libc::signal(signal, libc::SIG_IGN) };
+        }
+    } else {
+        catch(libc::SIGHUP, true);
+    }
+}
+
+/// Catches a signal for a trap, which runs once the current command is done.
+pub fn trap(signal: libc::c_int) {
+    IGNORED.fetch_and(!(1 << signal), Ordering::SeqCst);
+    // The shell may already catch it, possibly without restarting system calls.
+    if !is_caught(signal) {
+        catch(signal, true);
+    }
+}
+
+/// Ignores a signal for `trap '' SIG`, in the shell and in everything it starts.
+pub fn ignore(signal: libc::c_int) {
+    IGNORED.fetch_or(1 << signal, Ordering::SeqCst);
+    // SAFETY: ignoring a signal has no effect on memory.
+    unsafe { libc::signal(signal, libc::SIG_IGN) };
+}
+
+/// Gives a signal back the disposition the shell would have without any trap, like ignoring `SIGTTOU` when interactive.
+pub fn restore(signal: libc::c_int, interactive: bool) {
+    IGNORED.fetch_and(!(1 << signal), Ordering::SeqCst);
+    // SAFETY: restoring the default disposition has no effect on memory.
+    unsafe { libc::signal(signal, libc::SIG_DFL) };
+    init(interactive);
+}
+
+fn is_caught(signal: libc::c_int) -> bool {
+    // SAFETY: sigaction only writes the current disposition into `action`.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(signal, std::ptr::null(), &mut action);
+        action.sa_sigaction == record as extern "C" fn(libc::c_int) as libc::sighandler_t
+    }
+}
+
+/// Gives a forked child the default dispositions, apart from trapped signals being ignored, and forgets what the shell had pending.
+pub fn reset() {
+    let ignored = IGNORED.load(Ordering::SeqCst);
+    for &(_, signal) in SIGNALS {
+        if ignored & (1 << signal) == 0 && (CHANGED.contains(&signal) || is_caught(signal)) {
+            // SAFETY: restoring the default disposition has no effect on memory.
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+    PENDING.store(0, Ordering::SeqCst);
+}
+
+/// Whether `signal` arrived since it was last taken.
+pub fn take(signal: libc::c_int) -> bool {
+    PENDING.fetch_and(!(1 << signal), Ordering::SeqCst) & (1 << signal) != 0
+}
+
+/// Notes `signal` as if it had arrived.
+pub fn raise(signal: libc::c_int) {
+    record(signal);
+}
+
+pub fn is_pending(signal: libc::c_int) -> bool {
+    PENDING.load(Ordering::SeqCst) & (1 << signal) != 0
+}
+
+/// What reading input fails with when Ctrl-C or a hangup interrupts it.
+#[derive(Debug, thiserror::Error)]
+#[error("interrupted by a signal")]
+pub struct Interrupted;
+
+impl Interrupted {
+    pub fn is(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<Interrupted>())
+    }
+}
+
+/**
+Standard input, read without any buffering of its own. Unlike `io::Stdin` it gives up with `Interrupted`
+when `SIGINT` or `SIGHUP` arrives during a read, rather than carrying on.
+**/
+pub struct Stdin;
+
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // SAFETY: read writes at most `buf.len()` bytes into `buf`.
+            let read =
+                unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+            if read >= 0 {
+                return Ok(read as usize);
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+            if is_pending(libc::SIGINT) || is_pending(libc::SIGHUP) {
+                return Err(io::Error::other(Interrupted));
+            }
+        }
+    }
+}
+
+/// The size of the terminal as `(columns, lines)`, if standard error is one.
+pub fn window_size() -> Option<(u16, u16)> {
+    // SAFETY: TIOCGWINSZ only writes a winsize into `size`.
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) == -1 || size.ws_col == 0 {
+            return None;
+        }
+        Some((size.ws_col, size.ws_row))
+    }
+}
+
+#[cfg(test)]
+mod names {
+    use super::*;
+
+    #[test]
+    fn names_and_numbers() {
+        assert_eq!(signal_number("TERM"), Some(libc::SIGTERM));
+        assert_eq!(signal_number("sigint"), Some(libc::SIGINT));
+        assert_eq!(signal_number("9"), Some(libc::SIGKILL));
+        assert_eq!(signal_number("0"), None);
+        assert_eq!(signal_number("NOPE"), None);
+        assert_eq!(signal_name(libc::SIGHUP), Some("HUP"));
+    }
+}
+
+#[cfg(test)]
+mod pending {
+    use super::*;
+
+    #[test]
+    fn signals_are_taken_once() {
+        record(libc::SIGWINCH);
+        assert!(take(libc::SIGWINCH));
+        assert!(!take(libc::SIGWINCH));
+    }
+}
+
+#[cfg(test)]
+mod dispositions {
+    use super::*;
+
+    fn is_ignored(signal: libc::c_int) -> bool {
+        // SAFETY: sigaction only writes the current disposition into `action`.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(signal, std::ptr::null(), &mut action);
+            action.sa_sigaction == libc::SIG_IGN
+        }
+    }
+
+    #[test]
+    fn restoring_keeps_background_terminal_access_ignored() {
+        trap(libc::SIGTTOU);
+        assert!(!is_ignored(libc::SIGTTOU));
+        restore(libc::SIGTTOU, true);
+        assert!(is_ignored(libc::SIGTTOU));
+        assert!(is_ignored(libc::SIGTTIN));
+
+        // Leave the test process as it was.
+        for signal in INTERACTIVE_IGNORED.into_iter().chain([libc::SIGINT]) {
+            // SAFETY: restoring the default disposition has no effect on memory.
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Candidates this far away or further are not considered a typo of the original.
+const TOO_DIFFERENT: usize = 3;
+
+/**
+Corrects every component of `path` that does not exist to the closest directory entry of its parent.
+Returns None if some component has no close enough match, or if the corrected path is not a directory.
+**/
+pub fn correct_dir(path: &Path) -> Option<PathBuf> {
+    let mut corrected = PathBuf::new();
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            corrected.push(component);
+            continue;
+        };
+        if corrected.join(name).exists() {
+            corrected.push(name);
+            continue;
+        }
+        let name = name.to_str()?;
+        let parent = if corrected.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            corrected.as_path()
+        };
+        let closest = fs::read_dir(parent)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .map(|candidate| (distance(name, &candidate), candidate))
+            .filter(|(distance, _)| *distance < TOO_DIFFERENT)
+            .min_by_key(|(distance, _)| *distance)?;
+        corrected.push(closest.1);
+    }
+    corrected.is_dir().then_some(corrected)
+}
+
+/**
+How far `new` is from being a typo of `old`:
+0 if they are equal, 1 if two adjacent characters are transposed,
+2 if one character is wrong, missing or added, and 3 for anything else.
+**/
+fn distance(old: &str, new: &str) -> usize {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let common = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[common..], &new[common..]);
+
+    match (old, new) {
+        ([], []) => 0,
+        ([a, b, old_rest @ ..], [c, d, new_rest @ ..])
+            if a == d && b == c && old_rest == new_rest =>
+        {
+            1
+        }
+        ([_, old_rest @ ..], [_, new_rest @ ..]) if old_rest == new_rest => 2,
+        ([_, old_rest @ ..], new) if old_rest == new => 2,
+        (old, [_, new_rest @ ..]) if old == new_rest => 2,
+        _ => TOO_DIFFERENT,
+    }
+}
+
+#[cfg(test)]
+mod distance {
+    use super::*;
+
+    #[test]
+    fn equal() {
+        assert_eq!(distance("src", "src"), 0);
+    }
+
+    #[test]
+    fn transposed() {
+        assert_eq!(distance("scr", "src"), 1);
+    }
+
+    #[test]
+    fn wrong_missing_or_added() {
+        assert_eq!(distance("sxc", "src"), 2);
+        assert_eq!(distance("sc", "src"), 2);
+        assert_eq!(distance("srcc", "src"), 2);
+    }
+
+    #[test]
+    fn unrelated() {
+        assert_eq!(distance("target", "src"), TOO_DIFFERENT);
+    }
+}
@@ -0,0 +1,170 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+/// How the shell was started, as far as the files it reads before its first command go.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Startup {
+    /// Set by `-l`, `--login`, or a `$0` starting with `-` as `login` gives it.
+    pub login: bool,
+    /// `--norc`: no rc file for an interactive shell.
+    pub no_rc: bool,
+    /// `--noprofile`: no profile for a login shell.
+    pub no_profile: bool,
+    /// `--rcfile path`, read instead of the usual rc file.
+    pub rc_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum OptionError {
+    #[error("{0}: invalid option")]
+    Invalid(String),
+    #[error("{0}: option requires an argument")]
+    MissingArgument(String),
+}
+
+impl Startup {
+    /// Reads the options that come before the script, returning what is left: the script and its arguments.
+    pub fn parse(arg0: &OsStr, args: Vec<OsString>) -> Result<(Self, Vec<OsString>), OptionError> {
+        let mut startup = Startup {
+            login: arg0.as_bytes().starts_with(b"-"),
+            ..Startup::default()
+        };
+        let mut args = args.into_iter().peekable();
+        while let Some(option) = args.next_if(|arg| arg.as_bytes().starts_with(b"-")) {
+            match option.to_string_lossy().as_ref() {
+                "--" => break,
+                "-l" | "--login" => startup.login = true,
+                "--norc" => startup.no_rc = true,
+                "--noprofile" => startup.no_profile = true,
+                "--rcfile" | "--init-file" => match args.next() {
+                    Some(path) => startup.rc_file = Some(PathBuf::from(path)),
+                    None => {
+                        return Err(OptionError::MissingArgument(
+                            option.to_string_lossy().into(),
+                        ))
+                    }
+                },
+                option => return Err(OptionError::Invalid(option.to_string())),
+            }
+        }
+        Ok((startup, args.collect()))
+    }
+
+    /**
+    The files to source, in order. A login shell reads `/etc/profile` and `~/.profile`; an
+    interactive one then reads its rc file, `~/.shellyrc` or else `$XDG_CONFIG_HOME/shelly/rc`,
+    followed by the file `$ENV` names. `lookup` gives the value of a variable, with `ENV` expanded.
+    **/
+    pub fn files(
+        &self,
+        interactive: bool,
+        lookup: impl Fn(&str) -> Option<OsString>,
+    ) -> Vec<PathBuf> {
+        let home = lookup("HOME").map(PathBuf::from);
+        let mut files = Vec::new();
+        if self.login && !self.no_profile {
+            files.push(PathBuf::from("/etc/profile"));
+            files.extend(home.as_ref().map(|home| home.join(".profile")));
+        }
+        if !interactive || self.no_rc {
+            return files;
+        }
+        match &self.rc_file {
+            Some(rc_file) => files.push(rc_file.clone()),
+            None => {
+                let shellyrc = home.as_ref().map(|home| home.join(".shellyrc"));
+                let config = match lookup("XDG_CONFIG_HOME") {
+                    Some(config) if !config.is_empty() => Some(PathBuf::from(config)),
+                    _ => home.as_ref().map(|home| home.join(".config")),
+                };
+                let xdg = config.map(|config| config.join("shelly/rc"));
+                files.extend(match shellyrc {
+                    Some(shellyrc) if shellyrc.exists() => Some(shellyrc),
+                    _ => xdg,
+                });
+            }
+        }
+        files.extend(
+            lookup("ENV")
+                .filter(|env| !env.is_empty())
+                .map(PathBuf::from),
+        );
+        files
+    }
+}
+
+#[cfg(test)]
+mod options {
+    use super::*;
+
+    fn parse(arg0: &str, args: &[&str]) -> Result<(Startup, Vec<OsString>), OptionError> {
+        Startup::parse(OsStr::new(arg0), args.iter().map(OsString::from).collect())
+    }
+
+    #[test]
+    fn flags_before_the_script() {
+        let (startup, rest) =
+            parse("shelly", &["-l", "--norc", "--rcfile", "rc", "s", "-l"]).unwrap();
+        let expected = Startup {
+            login: true,
+            no_rc: true,
+            no_profile: false,
+            rc_file: Some(PathBuf::from("rc")),
+        };
+        assert_eq!(startup, expected);
+        assert_eq!(rest, ["s", "-l"]);
+        assert!(parse("-shelly", &[]).unwrap().0.login);
+        assert_eq!(parse("shelly", &["--", "-x"]).unwrap().1, ["-x"]);
+        assert_eq!(
+            parse("shelly", &["--bad"]),
+            Err(OptionError::Invalid("--bad".to_string()))
+        );
+        assert_eq!(
+            parse("shelly", &["--rcfile"]),
+            Err(OptionError::MissingArgument("--rcfile".to_string()))
+        );
+    }
+
+    #[test]
+    fn files_in_order() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some(OsString::from("/nonexistent/home")),
+            "ENV" => Some(OsString::from("/env")),
+            _ => None,
+        };
+        let login = Startup {
+            login: true,
+            ..Startup::default()
+        };
+        assert_eq!(
+            login.files(true, lookup),
+            [
+                "/etc/profile",
+                "/nonexistent/home/.profile",
+                "/nonexistent/home/.config/shelly/rc",
+                "/env"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            login.files(false, lookup),
+            ["/etc/profile", "/nonexistent/home/.profile"].map(PathBuf::from)
+        );
+        let custom = Startup {
+            no_profile: true,
+            login: true,
+            rc_file: Some(PathBuf::from("/rc")),
+            ..Startup::default()
+        };
+        assert_eq!(
+            custom.files(true, lookup),
+            ["/rc", "/env"].map(PathBuf::from)
+        );
+        let no_rc = Startup {
+            no_rc: true,
+            ..Startup::default()
+        };
+        assert!(no_rc.files(true, lookup).is_empty());
+    }
+}
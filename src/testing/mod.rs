@@ -0,0 +1,28 @@
+//! Fixtures shared by the tests of several modules.
+use std::fs;
+use std::path::PathBuf;
+
+/// A directory of its own for a test to write files in, removed along with them when dropped.
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// Makes the directory, named after the test and the process running it.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("shelly-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        ScratchDir { path }
+    }
+
+    /// The path of a file in the directory.
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
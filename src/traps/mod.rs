@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use crate::signals;
+
+/// What a trap can be set on: a signal, or one of the shell's own events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Condition {
+    /// The shell exiting.
+    Exit,
+    Signal(libc::c_int),
+    /// Before each simple command.
+    Debug,
+    /// A command failing.
+    Err,
+    /// A function or sourced script returning.
+    Return,
+}
+
+impl Condition {
+    /// Reads a condition the way `trap` takes it: `EXIT` or 0, `ERR`, `DEBUG`, `RETURN`, or a signal.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let upper = spec.to_ascii_uppercase();
+        match upper.as_str() {
+            "EXIT" | "SIGEXIT" | "0" => Some(Condition::Exit),
+            "DEBUG" => Some(Condition::Debug),
+            "ERR" => Some(Condition::Err),
+            "RETURN" => Some(Condition::Return),
+            _ => signals::signal_number(spec).map(Condition::Signal),
+        }
+    }
+
+    /// The name `trap -p` lists the condition under, like `EXIT` or `SIGINT`.
+    pub fn name(&self) -> String {
+        match self {
+            Condition::Exit => "EXIT".to_string(),
+            Condition::Signal(signal) => match signals::signal_name(*signal) {
+                Some(name) => format!("SIG{}", name),
+                None => signal.to_string(),
+            },
+            Condition::Debug => "DEBUG".to_string(),
+            Condition::Err => "ERR".to_string(),
+            Condition::Return => "RETURN".to_string(),
+        }
+    }
+}
+
+/**
+The commands set with `trap`. An empty command means the signal is ignored. Setting a trap on
+a signal also changes how the shell handles it, so that it is noted and the trap run later.
+**/
+#[derive(Debug, Default, Clone)]
+pub struct Traps {
+    actions: BTreeMap<Condition, Vec<u8>>,
+}
+
+impl Traps {
+    pub fn get(&self, condition: Condition) -> Option<&[u8]> {
+        self.actions.get(&condition).map(Vec::as_slice)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Condition, &Vec<u8>)> {
+        self.actions.iter()
+    }
+
+    pub fn set(&mut self, condition: Condition, action: Vec<u8>) {
+        // The shell needs to hear about children to reap them, so `SIGCHLD` is never really ignored.
+        match condition {
+            Condition::Signal(libc::SIGCHLD) => (),
+            Condition::Signal(signal) if action.is_empty() => signals::ignore(signal),
+            Condition::Signal(signal) => signals::trap(signal),
+            _ => (),
+        }
+        self.actions.insert(condition, action);
+    }
+
+    pub fn reset(&mut self, condition: Condition, interactive: bool) {
+        if self.actions.remove(&condition).is_some() {
+            if let Condition::Signal(signal) = condition {
+                signals::restore(signal, interactive);
+            }
+        }
+    }
+
+    /// A subshell keeps ignoring what the shell ignored, but its other traps are reset.
+    pub fn reset_for_subshell(&mut self) {
+        self.actions.retain(|_, action| action.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod conditions {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Condition::parse("EXIT"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("0"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("err"), Some(Condition::Err));
+        assert_eq!(
+            Condition::parse("INT"),
+            Some(Condition::Signal(libc::SIGINT))
+        );
+        assert_eq!(
+            Condition::parse("SIGTERM"),
+            Some(Condition::Signal(libc::SIGTERM))
+        );
+        assert_eq!(
+            Condition::parse("15"),
+            Some(Condition::Signal(libc::SIGTERM))
+        );
+        assert_eq!(Condition::parse("BOGUS"), None);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Condition::Signal(libc::SIGINT).name(), "SIGINT");
+        assert_eq!(Condition::Exit.name(), "EXIT");
+    }
+}
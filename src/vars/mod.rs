@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+/// Used for command lookup whenever PATH is unset.
+const FALLBACK_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub value: OsString,
+    /// Exported variables are passed on to the environment of child processes.
+    pub exported: bool,
+}
+
+/**
+The shell variables. Exported variables are mirrored into the environment of the shell process itself,
+so children inherit them and the rest of the shell can keep reading them with `env::var_os`.
+
+Local variables are scoped dynamically: a function call opens a scope, and when it closes, the
+variables made local in it get back the values they had before.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    vars: BTreeMap<String, Variable>,
+    /// For each open scope, the variables made local in it and what they were before.
+    scopes: Vec<Vec<(String, Option<Variable>)>>,
+}
+
+impl Variables {
+    /// Every environment variable with a valid name becomes an exported shell variable.
+    pub fn from_env() -> Self {
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| {
+                let name = name.into_string().ok().filter(|name| is_valid_name(name))?;
+                Some((
+                    name,
+                    Variable {
+                        value,
+                        exported: true,
+                    },
+                ))
+            })
+            .collect();
+        Variables {
+            vars,
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OsStr> {
+        self.vars.get(name).map(|var| var.value.as_os_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.vars.iter()
+    }
+
+    pub fn set(&mut self, name: &str, value: OsString) {
+        let var = self.vars.entry(name.to_string()).or_insert(Variable {
+            value: OsString::new(),
+            exported: false,
+        });
+        var.value = value;
+        if var.exported {
+            env::set_var(name, &var.value);
+        }
+    }
+
+    /// Marks the variable as exported, creating it empty if it does not exist.
+    pub fn export(&mut self, name: &str) {
+        let var = self.vars.entry(name.to_string()).or_insert(Variable {
+            value: OsString::new(),
+            exported: false,
+        });
+        var.exported = true;
+        env::set_var(name, &var.value);
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        if self.vars.remove(name).is_some_and(|var| var.exported) {
+            env::remove_var(name);
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    /// Closes the innermost scope, putting back the variables that were made local in it.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, saved) in scope.into_iter().rev() {
+            self.unset(&name);
+            if let Some(var) = saved {
+                if var.exported {
+                    env::set_var(&name, &var.value);
+                }
+                self.vars.insert(name, var);
+            }
+        }
+    }
+
+    /**
+    Makes `name` local to the innermost scope, starting out unset. Returns false when no scope is
+    open, outside of any function.
+    **/
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let Some(scope) = self.scopes.last_mut() else {
+            return false;
+        };
+        if !scope.iter().any(|(local, _)| local == name) {
+            scope.push((name.to_string(), self.vars.get(name).cloned()));
+            self.unset(name);
+        }
+        true
+    }
+}
+
+/// Whether `name` can be used as a variable name: a letter or underscore, followed by letters, digits and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits a `NAME=value` word into its name and value, if it is an assignment.
+pub fn parse_assignment(word: &OsStr) -> Option<(String, OsString)> {
+    let bytes = word.as_bytes();
+    let equals = bytes.iter().position(|&b| b == b'=')?;
+    let name = std::str::from_utf8(&bytes[..equals]).ok()?;
+    is_valid_name(name).then(|| {
+        (
+            name.to_string(),
+            OsString::from_vec(bytes[equals + 1..].to_vec()),
+        )
+    })
+}
+
+/// The system's default search path, as `confstr(_CS_PATH)` gives it.
+pub fn default_path() -> OsString {
+    // SAFETY: confstr is first asked for the length it needs, and then given a buffer of that size.
+    unsafe {
+        let len = libc::confstr(libc::_CS_PATH, std::ptr::null_mut(), 0);
+        if len == 0 {
+            return OsString::from(FALLBACK_PATH);
+        }
+        let mut buf = vec![0u8; len];
+        libc::confstr(libc::_CS_PATH, buf.as_mut_ptr().cast(), len);
+        // The length includes the terminating nul.
+        buf.truncate(len - 1);
+        OsString::from_vec(buf)
+    }
+}
+
+#[cfg(test)]
+mod scopes {
+    use super::*;
+
+    #[test]
+    fn locals_are_put_back() {
+        let mut vars = Variables::default();
+        vars.set("x", "global".into());
+        assert!(!vars.make_local("x"));
+
+        vars.push_scope();
+        assert!(vars.make_local("x"));
+        assert_eq!(vars.get("x"), None);
+        vars.set("x", "outer".into());
+
+        vars.push_scope();
+        vars.make_local("x");
+        vars.make_local("y");
+        vars.set("x", "inner".into());
+        vars.set("y", "inner".into());
+        vars.pop_scope();
+        assert_eq!(vars.get("x"), Some(OsStr::new("outer")));
+        assert_eq!(vars.get("y"), None);
+
+        vars.pop_scope();
+        assert_eq!(vars.get("x"), Some(OsStr::new("global")));
+    }
+}
+
+#[cfg(test)]
+mod assignments {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        let parsed = parse_assignment(OsStr::new("PATH=/bin:/usr/bin"));
+        assert_eq!(parsed, Some(("PATH".to_string(), "/bin:/usr/bin".into())));
+    }
+
+    #[test]
+    fn empty_value() {
+        let parsed = parse_assignment(OsStr::new("_x1="));
+        assert_eq!(parsed, Some(("_x1".to_string(), "".into())));
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert_eq!(parse_assignment(OsStr::new("1x=a")), None);
+        assert_eq!(parse_assignment(OsStr::new("=a")), None);
+        assert_eq!(parse_assignment(OsStr::new("a-b=c")), None);
+        assert_eq!(parse_assignment(OsStr::new("echo")), None);
+    }
+}